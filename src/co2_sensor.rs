@@ -0,0 +1,157 @@
+use std::{
+    io::{Cursor, Read},
+    net::{SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+pub struct CO2Readings {
+    pub timings: Vec<u64>,
+    pub readings: Vec<u32>,
+}
+
+/// Network CO2 sensor that answers every connection with its reading history.
+///
+/// Failed reads are not fatal: the sensor is skipped for an exponentially
+/// growing amount of time (capped at `max_backoff`) and tried again later.
+pub struct Co2Sensor {
+    address: SocketAddr,
+    timeout: Duration,
+    max_backoff: Duration,
+    failures: u32,
+    next_attempt: Option<Instant>,
+}
+
+impl Co2Sensor {
+    pub fn new(address: SocketAddr, timeout: Duration, max_backoff: Duration) -> Self {
+        Self {
+            address,
+            timeout,
+            max_backoff,
+            failures: 0,
+            next_attempt: None,
+        }
+    }
+
+    /// Returns fresh readings, or `None` if the sensor could not be read or
+    /// is still backing off after an earlier failure.
+    pub fn poll(&mut self) -> Option<CO2Readings> {
+        if let Some(next_attempt) = self.next_attempt {
+            if Instant::now() < next_attempt {
+                return None;
+            }
+        }
+
+        match self.fetch() {
+            Ok(readings) => {
+                self.failures = 0;
+                self.next_attempt = None;
+                Some(readings)
+            }
+            Err(e) => {
+                self.failures += 1;
+                let backoff = self.backoff();
+                eprintln!(
+                    "\nFailed to read CO2 sensor at {}: {e}, retrying in {}s",
+                    self.address,
+                    backoff.as_secs()
+                );
+                self.next_attempt = Some(Instant::now() + backoff);
+                None
+            }
+        }
+    }
+
+    fn backoff(&self) -> Duration {
+        let exponent = self.failures.saturating_sub(1).min(16);
+        Duration::from_secs(1 << exponent).min(self.max_backoff)
+    }
+
+    fn fetch(&self) -> Result<CO2Readings, std::io::Error> {
+        let mut stream = TcpStream::connect_timeout(&self.address, self.timeout)?;
+
+        stream.set_read_timeout(Some(self.timeout))?;
+
+        let mut buffer: Vec<u8> = Default::default();
+        let _bytes_read = stream.read_to_end(&mut buffer)?;
+        let mut cursor = Cursor::new(buffer);
+
+        let latest_time = cursor.read_u64::<LittleEndian>()?;
+        let read_interval = cursor.read_u64::<LittleEndian>()?;
+        let reading_count = cursor.read_u16::<LittleEndian>()?;
+
+        let mut timings: Vec<u64> = Default::default();
+        let mut readings: Vec<u32> = Default::default();
+        for i in 0..reading_count {
+            let age = read_interval.saturating_mul((reading_count - i - 1) as u64);
+            timings.push(latest_time.saturating_sub(age));
+            readings.push(cursor.read_u16::<LittleEndian>()? as u32);
+        }
+
+        Ok(CO2Readings { timings, readings })
+    }
+}
+
+#[cfg(test)]
+mod sensor {
+    use crate::co2_sensor::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    fn sensor_at(address: SocketAddr) -> Co2Sensor {
+        Co2Sensor::new(address, Duration::from_secs(1), Duration::from_secs(60))
+    }
+
+    #[test]
+    fn poll_reads_history() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut sensor = sensor_at(listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut bytes: Vec<u8> = Vec::new();
+            bytes.extend_from_slice(&(1_u64 << 40).to_le_bytes()); // Latest time
+            bytes.extend_from_slice(&30_u64.to_le_bytes()); // Read interval
+            bytes.extend_from_slice(&3_u16.to_le_bytes()); // Reading count
+            bytes.extend_from_slice(&400_u16.to_le_bytes());
+            bytes.extend_from_slice(&410_u16.to_le_bytes());
+            bytes.extend_from_slice(&420_u16.to_le_bytes());
+            stream.write_all(&bytes).unwrap();
+        });
+
+        let readings = sensor.poll().unwrap();
+        server.join().unwrap();
+
+        let latest = 1_u64 << 40;
+        assert_eq!(readings.timings, [latest - 60, latest - 30, latest]);
+        assert_eq!(readings.readings, [400, 410, 420]);
+    }
+
+    #[test]
+    fn failed_poll_backs_off() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        let mut sensor = sensor_at(address);
+        assert!(sensor.poll().is_none());
+        assert_eq!(sensor.failures, 1);
+
+        // Still backing off, so the sensor is not even contacted.
+        assert!(sensor.poll().is_none());
+        assert_eq!(sensor.failures, 1);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_limit() {
+        let mut sensor = sensor_at("127.0.0.1:1".parse().unwrap());
+        let mut backoffs = Vec::new();
+        for failures in 1..=8 {
+            sensor.failures = failures;
+            backoffs.push(sensor.backoff().as_secs());
+        }
+
+        assert_eq!(backoffs, [1, 2, 4, 8, 16, 32, 60, 60]);
+    }
+}
@@ -0,0 +1,666 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Instant,
+};
+
+use sysinfo::{ComponentExt, CpuExt, DiskExt, NetworkExt, System, SystemExt};
+
+use crate::co2_sensor::Co2Sensor;
+use crate::config::NameFilter;
+use tinygraph::tgph_format::{
+    BaseContainerElementType, ContainerMetadata, ElementArrayType, MetricKind, TGPHContainer,
+    TgphError, TypeMismatchPolicy, TGPH,
+};
+
+/// A source of metrics appended to the TGPH once per collection period.
+///
+/// Every collector owns whatever state it needs between periods (its own
+/// `System`, a socket address, previous counter values...), so a failure in
+/// one of them does not affect the others.
+pub trait Collector {
+    /// Name used to enable the collector in the configuration.
+    fn name(&self) -> &'static str;
+
+    fn collect(&mut self, tgph: &mut TGPH) -> Result<(), TgphError>;
+}
+
+/// Container holding the time at which each collection period started, the
+/// time axis of everything collected periodically.
+pub const TIMESTAMP_CONTAINER: &str = "Unix timestamp";
+
+/// Appends `data` to the container called `name` and describes it as sampled
+/// once per collection period.
+fn append_described<T: BaseContainerElementType>(
+    tgph: &mut TGPH,
+    data: T,
+    name: &str,
+    kind: MetricKind,
+    unit: &str,
+    label: &str,
+) -> Result<(), TgphError> {
+    tgph.append(data, name)?;
+    tgph.set_metadata(
+        name,
+        ContainerMetadata::new(kind, unit, label).with_time_axis(TIMESTAMP_CONTAINER),
+    );
+    Ok(())
+}
+
+/// Names of all built-in collectors, in the order they run by default.
+pub const COLLECTOR_NAMES: [&str; 7] = [
+    "disk",
+    "network",
+    "temperature",
+    "cpu",
+    "memory",
+    "host",
+    "co2",
+];
+
+pub struct DiskCollector {
+    sys: System,
+    filter: NameFilter,
+}
+
+impl DiskCollector {
+    pub fn new(filter: NameFilter) -> Self {
+        let mut sys = System::new();
+        sys.refresh_disks_list();
+        Self { sys, filter }
+    }
+}
+
+impl Collector for DiskCollector {
+    fn name(&self) -> &'static str {
+        "disk"
+    }
+
+    fn collect(&mut self, tgph: &mut TGPH) -> Result<(), TgphError> {
+        self.sys.refresh_disks();
+
+        for disk in self.sys.disks() {
+            let name = disk.name().to_string_lossy();
+            if !self.filter.allows(&name) {
+                continue;
+            }
+
+            append_described(
+                tgph,
+                disk.total_space(),
+                &format!("Disk {} Total Space [B]", name),
+                MetricKind::Gauge,
+                "B",
+                &format!("{} total space", name),
+            )?;
+            append_described(
+                tgph,
+                disk.available_space(),
+                &format!("Disk {} Available Space [B]", name),
+                MetricKind::Gauge,
+                "B",
+                &format!("{} available space", name),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Disk sizes used to be stored in whole gigabytes, in containers called
+/// "Disk <name> Total Space [GB]" and "Disk <name> Available Space [GB]".
+const LEGACY_DISK_SUFFIX: &str = "Space [GB]";
+const BYTES_PER_GB: u64 = 1024 * 1024 * 1024;
+
+/// Moves the disk sizes of files written before they were kept in bytes into
+/// the "[B]" containers, ahead of anything already in there, so that the old
+/// and the new history end up on the same graph. Their rollups are moved
+/// too, as every rollup function scales with its samples.
+pub fn migrate_disk_containers(tgph: &mut TGPH) {
+    let legacy: Vec<String> = tgph
+        .containers
+        .iter()
+        .filter(|c| c.name.contains("Disk ") && c.name.ends_with(LEGACY_DISK_SUFFIX))
+        .map(|c| c.name.clone())
+        .collect();
+
+    for name in legacy {
+        let index = tgph.containers.iter().position(|c| c.name == name).unwrap();
+        let old = tgph.containers.remove(index);
+        let new_name = format!("{}[B]", name.strip_suffix("[GB]").unwrap());
+
+        let mut elements = match &old.elements {
+            ElementArrayType::U32(arr) => {
+                ElementArrayType::U64(arr.iter().map(|&e| e as u64 * BYTES_PER_GB).collect())
+            }
+            ElementArrayType::U64(arr) => {
+                ElementArrayType::U64(arr.iter().map(|&e| e * BYTES_PER_GB).collect())
+            }
+            ElementArrayType::I64(arr) => {
+                ElementArrayType::I64(arr.iter().map(|&e| e * BYTES_PER_GB as i64).collect())
+            }
+            ElementArrayType::FLOAT32(arr) => ElementArrayType::FLOAT64(
+                arr.iter()
+                    .map(|&e| e as f64 * BYTES_PER_GB as f64)
+                    .collect(),
+            ),
+            ElementArrayType::FLOAT64(arr) => {
+                ElementArrayType::FLOAT64(arr.iter().map(|&e| e * BYTES_PER_GB as f64).collect())
+            }
+            other => {
+                eprintln!(
+                    "Keeping {name}, its {} are not sizes",
+                    other.element_type().name()
+                );
+                tgph.add_container(old);
+                continue;
+            }
+        };
+        let mut metadata = old.metadata.clone();
+        if metadata.unit == "GB" {
+            metadata.unit = "B".to_string();
+        }
+
+        let newer = tgph
+            .containers
+            .iter()
+            .position(|c| c.name == new_name)
+            .map(|index| tgph.containers.remove(index));
+        let joinable = newer.as_ref().is_none_or(|newer| {
+            let new_type = newer.elements.element_type();
+            elements.element_type().widened_with(new_type).is_some()
+        });
+        let newer = match newer {
+            Some(newer) if !joinable => {
+                eprintln!(
+                    "Keeping {name}, its {} cannot be joined with the {} of {new_name}",
+                    old.elements.element_type().name(),
+                    newer.elements.element_type().name()
+                );
+                tgph.add_container(old);
+                tgph.add_container(newer);
+                continue;
+            }
+            newer => newer,
+        };
+
+        trim_front(&mut elements, tgph.entry_limit);
+        tgph.add_container(TGPHContainer {
+            name: new_name.clone(),
+            elements,
+            metadata,
+        });
+        if let Some(newer) = newer {
+            // The newer samples follow the migrated ones, in whichever type
+            // holds both.
+            let policy = tgph.type_mismatch_policy;
+            tgph.type_mismatch_policy = TypeMismatchPolicy::Widen;
+            let mut sample = TGPH::new(tgph.entry_limit);
+            sample.add_container(newer);
+            if let Err(e) = tgph.merge(sample) {
+                eprintln!("Failed to join {name} with {new_name}: {e}");
+            }
+            tgph.type_mismatch_policy = policy;
+        }
+    }
+}
+
+fn trim_front(elements: &mut ElementArrayType, entry_limit: usize) {
+    fn trim<T>(arr: &mut VecDeque<T>, entry_limit: usize) {
+        let excess = arr.len().saturating_sub(entry_limit);
+        arr.drain(..excess);
+    }
+    match elements {
+        ElementArrayType::U64(arr) => trim(arr, entry_limit),
+        ElementArrayType::FLOAT64(arr) => trim(arr, entry_limit),
+        _ => {}
+    }
+}
+
+/// Difference between two readings of a monotonically growing counter.
+///
+/// A value lower than the previous one means the counter either wrapped
+/// around (32-bit kernel counters do that on fast links) or was reset, e.g.
+/// when the interface went down. Only the first case can be recovered from,
+/// and only happens to counters of 32 bits, see `COUNTERS_WRAP_AT_32_BITS`.
+fn counter_delta(previous: u64, current: u64, wraps_at_32_bits: bool) -> u64 {
+    if current >= previous {
+        current - previous
+    } else if wraps_at_32_bits && previous <= u32::MAX as u64 {
+        (u32::MAX as u64 - previous) + current + 1
+    } else {
+        current
+    }
+}
+
+/// The kernel keeps interface totals in an `unsigned long`, which only has 32
+/// bits on 32-bit systems. Anywhere else a drop is a reset.
+const COUNTERS_WRAP_AT_32_BITS: bool = cfg!(target_pointer_width = "32");
+
+#[derive(Clone, Copy)]
+struct InterfaceTotals {
+    received: u64,
+    transmitted: u64,
+}
+
+pub struct NetworkCollector {
+    sys: System,
+    filter: NameFilter,
+    previous: HashMap<String, InterfaceTotals>,
+    previous_refresh: Option<Instant>,
+}
+
+impl NetworkCollector {
+    pub fn new(filter: NameFilter) -> Self {
+        let mut sys = System::new();
+        sys.refresh_networks_list();
+        Self {
+            sys,
+            filter,
+            previous: HashMap::new(),
+            previous_refresh: None,
+        }
+    }
+}
+
+impl Collector for NetworkCollector {
+    fn name(&self) -> &'static str {
+        "network"
+    }
+
+    fn collect(&mut self, tgph: &mut TGPH) -> Result<(), TgphError> {
+        self.sys.refresh_networks();
+
+        let now = Instant::now();
+        let elapsed = self
+            .previous_refresh
+            .map(|previous| now.duration_since(previous).as_secs_f64());
+        self.previous_refresh = Some(now);
+
+        for (interface_name, data) in self.sys.networks() {
+            if !self.filter.allows(interface_name) {
+                continue;
+            }
+
+            let totals = InterfaceTotals {
+                received: data.total_received(),
+                transmitted: data.total_transmitted(),
+            };
+            let previous = self.previous.insert(interface_name.clone(), totals);
+
+            // The first sample of an interface has nothing to be compared
+            // against. It still gets a rate of 0 so that every element lines
+            // up with the timestamp of its period.
+            let rate = |previous: Option<u64>, current: u64| match (previous, elapsed) {
+                (Some(previous), Some(elapsed)) if elapsed > 0.0 => {
+                    let delta = counter_delta(previous, current, COUNTERS_WRAP_AT_32_BITS);
+                    (delta as f64 / elapsed) as f32
+                }
+                _ => 0.0,
+            };
+            append_described(
+                tgph,
+                rate(previous.map(|p| p.received), totals.received),
+                &format!("Interface {} Received [B/s]", interface_name),
+                MetricKind::Gauge,
+                "B/s",
+                &format!("{} received", interface_name),
+            )?;
+            append_described(
+                tgph,
+                rate(previous.map(|p| p.transmitted), totals.transmitted),
+                &format!("Interface {} Transmitted [B/s]", interface_name),
+                MetricKind::Gauge,
+                "B/s",
+                &format!("{} transmitted", interface_name),
+            )?;
+            append_described(
+                tgph,
+                data.total_packets_received(),
+                &format!("Interface {} Received [packets]", interface_name),
+                MetricKind::Counter,
+                "packets",
+                &format!("{} received packets", interface_name),
+            )?;
+            append_described(
+                tgph,
+                data.total_packets_transmitted(),
+                &format!("Interface {} Transmitted [packets]", interface_name),
+                MetricKind::Counter,
+                "packets",
+                &format!("{} transmitted packets", interface_name),
+            )?;
+            append_described(
+                tgph,
+                data.total_errors_on_received(),
+                &format!("Interface {} Receive Errors [errors]", interface_name),
+                MetricKind::Counter,
+                "errors",
+                &format!("{} receive errors", interface_name),
+            )?;
+            append_described(
+                tgph,
+                data.total_errors_on_transmitted(),
+                &format!("Interface {} Transmit Errors [errors]", interface_name),
+                MetricKind::Counter,
+                "errors",
+                &format!("{} transmit errors", interface_name),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct TemperatureCollector {
+    sys: System,
+}
+
+impl TemperatureCollector {
+    pub fn new() -> Self {
+        let mut sys = System::new();
+        sys.refresh_components_list();
+        Self { sys }
+    }
+}
+
+impl Collector for TemperatureCollector {
+    fn name(&self) -> &'static str {
+        "temperature"
+    }
+
+    fn collect(&mut self, tgph: &mut TGPH) -> Result<(), TgphError> {
+        self.sys.refresh_components();
+
+        for component in self.sys.components() {
+            append_described(
+                tgph,
+                component.temperature(),
+                &format!("{} Temperature [C]", component.label()),
+                MetricKind::Gauge,
+                "C",
+                &format!("{} temperature", component.label()),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct CpuCollector {
+    sys: System,
+}
+
+impl CpuCollector {
+    pub fn new() -> Self {
+        let mut sys = System::new();
+        sys.refresh_cpu();
+        Self { sys }
+    }
+}
+
+impl Collector for CpuCollector {
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    fn collect(&mut self, tgph: &mut TGPH) -> Result<(), TgphError> {
+        self.sys.refresh_cpu();
+
+        append_described(
+            tgph,
+            self.sys.cpus().len() as u32,
+            "CPU Count",
+            MetricKind::Info,
+            "",
+            "CPU count",
+        )?;
+
+        for (i, cpu) in self.sys.cpus().iter().enumerate() {
+            append_described(
+                tgph,
+                cpu.cpu_usage(),
+                &format!("CPU {} Usage [%]", i),
+                MetricKind::Gauge,
+                "%",
+                &format!("CPU {} usage", i),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct MemoryCollector {
+    sys: System,
+}
+
+impl MemoryCollector {
+    pub fn new() -> Self {
+        Self { sys: System::new() }
+    }
+}
+
+impl Collector for MemoryCollector {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn collect(&mut self, tgph: &mut TGPH) -> Result<(), TgphError> {
+        self.sys.refresh_memory();
+
+        append_described(
+            tgph,
+            (self.sys.total_memory() / 1024 / 1024) as u32,
+            "Total memory [MB]",
+            MetricKind::Gauge,
+            "MB",
+            "Total memory",
+        )?;
+        append_described(
+            tgph,
+            (self.sys.used_memory() / 1024 / 1024) as u32,
+            "Used memory [MB]",
+            MetricKind::Gauge,
+            "MB",
+            "Used memory",
+        )?;
+        append_described(
+            tgph,
+            (self.sys.total_swap() / 1024 / 1024) as u32,
+            "Total swap [MB]",
+            MetricKind::Gauge,
+            "MB",
+            "Total swap",
+        )?;
+        append_described(
+            tgph,
+            (self.sys.used_swap() / 1024 / 1024) as u32,
+            "Used swap [MB]",
+            MetricKind::Gauge,
+            "MB",
+            "Used swap",
+        )?;
+
+        Ok(())
+    }
+}
+
+pub struct HostInfoCollector {
+    sys: System,
+}
+
+impl HostInfoCollector {
+    pub fn new() -> Self {
+        Self { sys: System::new() }
+    }
+}
+
+impl Collector for HostInfoCollector {
+    fn name(&self) -> &'static str {
+        "host"
+    }
+
+    fn collect(&mut self, tgph: &mut TGPH) -> Result<(), TgphError> {
+        append_described(
+            tgph,
+            self.sys.kernel_version().unwrap_or("UNDEFINED".to_string()),
+            "Kernel Version",
+            MetricKind::Info,
+            "",
+            "Kernel version",
+        )?;
+        append_described(
+            tgph,
+            self.sys.os_version().unwrap_or("UNDEFINED".to_string()),
+            "OS Version",
+            MetricKind::Info,
+            "",
+            "OS version",
+        )?;
+        append_described(
+            tgph,
+            self.sys.host_name().unwrap_or("UNDEFINED".to_string()),
+            "Hostname",
+            MetricKind::Info,
+            "",
+            "Hostname",
+        )?;
+
+        Ok(())
+    }
+}
+
+/// The sensor keeps its own reading history, which has its own timestamps.
+const CO2_TIMESTAMP_CONTAINER: &str = "Unix timestamp CO2";
+
+pub struct Co2SensorCollector {
+    sensor: Co2Sensor,
+}
+
+impl Co2SensorCollector {
+    pub fn new(sensor: Co2Sensor) -> Self {
+        Self { sensor }
+    }
+}
+
+impl Collector for Co2SensorCollector {
+    fn name(&self) -> &'static str {
+        "co2"
+    }
+
+    fn collect(&mut self, tgph: &mut TGPH) -> Result<(), TgphError> {
+        let co2_data = self.sensor.poll();
+        append_described(
+            tgph,
+            co2_data.is_some() as u32,
+            "CO2 Sensor Up",
+            MetricKind::Gauge,
+            "",
+            "CO2 sensor up",
+        )?;
+
+        if let Some(co2_data) = co2_data {
+            tgph.replace(co2_data.timings, CO2_TIMESTAMP_CONTAINER)?;
+            tgph.set_metadata(
+                CO2_TIMESTAMP_CONTAINER,
+                ContainerMetadata::new(MetricKind::Gauge, "s", "CO2 reading time"),
+            );
+            tgph.replace(co2_data.readings, "CO2 Concentration [ppm]")?;
+            tgph.set_metadata(
+                "CO2 Concentration [ppm]",
+                ContainerMetadata::new(MetricKind::Gauge, "ppm", "CO2 concentration")
+                    .with_time_axis(CO2_TIMESTAMP_CONTAINER),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod disk {
+    use crate::collectors::*;
+
+    #[test]
+    fn legacy_sizes_are_moved_into_byte_containers() {
+        let mut tgph = TGPH::new(3);
+        tgph.append(2_u32, "Disk sda Total Space [GB]").unwrap();
+        tgph.append(3_u32, "Disk sda Total Space [GB]").unwrap();
+        tgph.append(4 * BYTES_PER_GB, "Disk sda Total Space [B]")
+            .unwrap();
+        tgph.append(5 * BYTES_PER_GB, "Disk sda Total Space [B]")
+            .unwrap();
+        tgph.append(1.5_f64, "Rollup 60s avg Disk sda Total Space [GB]")
+            .unwrap();
+
+        migrate_disk_containers(&mut tgph);
+
+        assert!(tgph.container("Disk sda Total Space [GB]").is_none());
+        let bytes = &tgph.container("Disk sda Total Space [B]").unwrap().elements;
+        let gb: Vec<u64> = bytes
+            .as_u64()
+            .unwrap()
+            .iter()
+            .map(|b| b / BYTES_PER_GB)
+            .collect();
+        assert_eq!(gb, [3, 4, 5]);
+
+        let rollup = &tgph
+            .container("Rollup 60s avg Disk sda Total Space [B]")
+            .unwrap();
+        assert_eq!(
+            rollup.elements.as_f64().unwrap(),
+            &[1.5 * BYTES_PER_GB as f64]
+        );
+    }
+
+    #[test]
+    fn legacy_sizes_are_widened_to_the_byte_containers() {
+        let mut tgph = TGPH::new(10);
+        tgph.append(2_u32, "Disk sda Total Space [GB]").unwrap();
+        tgph.append(2.5_f64, "Disk sda Total Space [B]").unwrap();
+        tgph.append(1_u32, "Disk sdb Total Space [GB]").unwrap();
+        tgph.append("full".to_string(), "Disk sdb Total Space [B]")
+            .unwrap();
+
+        migrate_disk_containers(&mut tgph);
+
+        let bytes = &tgph.container("Disk sda Total Space [B]").unwrap().elements;
+        assert_eq!(bytes.as_f64().unwrap(), &[2.0 * BYTES_PER_GB as f64, 2.5]);
+        // Nothing is lost when the two cannot be joined.
+        let legacy = &tgph
+            .container("Disk sdb Total Space [GB]")
+            .unwrap()
+            .elements;
+        assert_eq!(legacy.as_u32().unwrap(), &[1]);
+        assert!(tgph.container("Disk sdb Total Space [B]").is_some());
+    }
+}
+
+#[cfg(test)]
+mod network {
+    use crate::collectors::*;
+
+    #[test]
+    fn counter_delta_of_growing_counter() {
+        for wraps_at_32_bits in [true, false] {
+            assert_eq!(counter_delta(100, 100, wraps_at_32_bits), 0);
+            assert_eq!(counter_delta(100, 250, wraps_at_32_bits), 150);
+            assert_eq!(counter_delta(1 << 40, (1 << 40) + 5, wraps_at_32_bits), 5);
+        }
+    }
+
+    #[test]
+    fn counter_delta_of_wrapped_32_bit_counter() {
+        assert_eq!(counter_delta(u32::MAX as u64 - 9, 5, true), 15);
+        assert_eq!(counter_delta(u32::MAX as u64, 0, true), 1);
+    }
+
+    #[test]
+    fn counter_delta_of_reset_counter() {
+        assert_eq!(counter_delta(1 << 40, 1000, true), 1000);
+        // 64-bit totals never wrap, so small ones going down were reset.
+        assert_eq!(counter_delta(u32::MAX as u64 - 9, 5, false), 5);
+        assert_eq!(counter_delta(3_000_000, 1000, false), 1000);
+    }
+}
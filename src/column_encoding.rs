@@ -0,0 +1,457 @@
+//! Bit level encodings of numeric containers, modelled after the ones of
+//! Facebook's Gorilla time series database.
+//!
+//! Integers (and timestamps) are stored as the difference between successive
+//! deltas, which is 0 for anything sampled at a fixed period or growing at a
+//! constant rate. Floats are stored as the XOR with the previous value, which
+//! only has a few meaningful bits for slowly moving gauges.
+//!
+//! Both encoders work on the raw bits widened to a u64, so the same code is
+//! used for every integer and float width.
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// Bits already used in the last byte, 0 when a new byte is needed.
+    used: u8,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            used: 0,
+        }
+    }
+
+    fn bit(&mut self, bit: bool) {
+        if self.used == 0 {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> self.used;
+        }
+        self.used = (self.used + 1) % 8;
+    }
+
+    /// Writes the lowest `count` bits of `value`, most significant first.
+    fn bits(&mut self, value: u64, count: u32) {
+        for i in (0..count).rev() {
+            self.bit((value >> i) & 1 == 1);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8], position: usize) -> Self {
+        Self { bytes, position }
+    }
+
+    fn bit(&mut self) -> Option<bool> {
+        let byte = self.bytes.get(self.position / 8)?;
+        let bit = byte & (0x80 >> (self.position % 8)) != 0;
+        self.position += 1;
+        Some(bit)
+    }
+
+    fn bits(&mut self, count: u32) -> Option<u64> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = (value << 1) | self.bit()? as u64;
+        }
+        Some(value)
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// Payload sizes of the delta-of-delta buckets, selected by a prefix of as
+/// many 1 bits as the bucket index, terminated by a 0 except for the last one.
+const DELTA_OF_DELTA_BUCKETS: [u32; 5] = [7, 9, 12, 32, 64];
+
+pub fn encode_integers<I: IntoIterator<Item = u64>>(values: I) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    let mut values = values.into_iter();
+
+    if let Some(first) = values.next() {
+        writer.bits(first, 64);
+
+        let mut previous = first;
+        let mut previous_delta: i64 = 0;
+        for value in values {
+            let delta = value.wrapping_sub(previous) as i64;
+            let delta_of_delta = zigzag(delta.wrapping_sub(previous_delta));
+            previous = value;
+            previous_delta = delta;
+
+            if delta_of_delta == 0 {
+                writer.bit(false);
+                continue;
+            }
+
+            let bucket = DELTA_OF_DELTA_BUCKETS
+                .iter()
+                .position(|&size| size == 64 || delta_of_delta < 1 << size)
+                .unwrap();
+            writer.bits(u64::MAX, bucket as u32 + 1);
+            if bucket + 1 < DELTA_OF_DELTA_BUCKETS.len() {
+                writer.bit(false);
+            }
+            writer.bits(delta_of_delta, DELTA_OF_DELTA_BUCKETS[bucket]);
+        }
+    }
+
+    writer.bytes
+}
+
+/// Everything an `IntegerDecoder` needs to carry on besides the payload, so
+/// decoding can be suspended until more of it has arrived.
+#[derive(Clone, Copy, Debug)]
+pub struct IntegerState {
+    remaining: u32,
+    /// `None` before the first value.
+    previous: Option<u64>,
+    previous_delta: i64,
+}
+
+impl IntegerState {
+    pub fn new(count: u32) -> Self {
+        Self {
+            remaining: count,
+            previous: None,
+            previous_delta: 0,
+        }
+    }
+
+    /// Values still to be decoded.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+}
+
+/// Iterator over the integers written by `encode_integers`, decoded as they
+/// are read. Ends early if `bytes` does.
+#[derive(Clone)]
+pub struct IntegerDecoder<'a> {
+    reader: BitReader<'a>,
+    state: IntegerState,
+}
+
+impl<'a> IntegerDecoder<'a> {
+    pub fn new(bytes: &'a [u8], count: u32) -> Self {
+        Self::resume(bytes, 0, IntegerState::new(count))
+    }
+
+    /// Continues at bit `position` of `bytes` with the `state` returned by
+    /// `suspend`.
+    pub fn resume(bytes: &'a [u8], position: usize, state: IntegerState) -> Self {
+        Self {
+            reader: BitReader::new(bytes, position),
+            state,
+        }
+    }
+
+    /// Bit position of the next value and the state to `resume` with.
+    pub fn suspend(&self) -> (usize, IntegerState) {
+        (self.reader.position, self.state)
+    }
+
+    fn decode_next(&mut self) -> Option<u64> {
+        let Some(previous) = self.state.previous else {
+            let first = self.reader.bits(64)?;
+            self.state.previous = Some(first);
+            return Some(first);
+        };
+
+        let mut ones = 0;
+        while ones < DELTA_OF_DELTA_BUCKETS.len() && self.reader.bit()? {
+            ones += 1;
+        }
+
+        let delta_of_delta = match ones {
+            0 => 0,
+            _ => unzigzag(self.reader.bits(DELTA_OF_DELTA_BUCKETS[ones - 1])?),
+        };
+        let delta = self.state.previous_delta.wrapping_add(delta_of_delta);
+        let value = previous.wrapping_add(delta as u64);
+        self.state.previous_delta = delta;
+        self.state.previous = Some(value);
+        Some(value)
+    }
+}
+
+impl Iterator for IntegerDecoder<'_> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.state.remaining == 0 {
+            return None;
+        }
+        let value = self.decode_next();
+        self.state.remaining = if value.is_some() {
+            self.state.remaining - 1
+        } else {
+            0
+        };
+        value
+    }
+}
+
+/// Reads `count` integers written by `encode_integers`, `None` if `bytes`
+/// ends early.
+pub fn decode_integers(bytes: &[u8], count: u32) -> Option<Vec<u64>> {
+    let mut values = Vec::with_capacity(count.min(1 << 16) as usize);
+    values.extend(IntegerDecoder::new(bytes, count));
+    (values.len() == count as usize).then_some(values)
+}
+
+/// Leading zeros and length of the meaningful bits of the last XOR written
+/// with an explicit window.
+#[derive(Clone, Copy, Debug)]
+struct XorWindow {
+    leading: u32,
+    length: u32,
+}
+
+impl XorWindow {
+    fn trailing(self) -> u32 {
+        64 - self.leading - self.length
+    }
+}
+
+pub fn encode_floats<I: IntoIterator<Item = u64>>(values: I) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    let mut values = values.into_iter();
+
+    if let Some(first) = values.next() {
+        writer.bits(first, 64);
+
+        let mut previous = first;
+        let mut window: Option<XorWindow> = None;
+        for value in values {
+            let xor = value ^ previous;
+            previous = value;
+
+            if xor == 0 {
+                writer.bit(false);
+                continue;
+            }
+            writer.bit(true);
+
+            let leading = xor.leading_zeros();
+            let trailing = xor.trailing_zeros();
+            match window {
+                Some(window) if leading >= window.leading && trailing >= window.trailing() => {
+                    writer.bit(false);
+                    writer.bits(xor >> window.trailing(), window.length);
+                }
+                _ => {
+                    let length = 64 - leading - trailing;
+                    writer.bit(true);
+                    writer.bits(leading as u64, 6);
+                    writer.bits(length as u64 - 1, 6);
+                    writer.bits(xor >> trailing, length);
+                    window = Some(XorWindow { leading, length });
+                }
+            }
+        }
+    }
+
+    writer.bytes
+}
+
+/// Everything a `FloatDecoder` needs to carry on besides the payload, see
+/// `IntegerState`.
+#[derive(Clone, Copy, Debug)]
+pub struct FloatState {
+    remaining: u32,
+    /// `None` before the first value.
+    previous: Option<u64>,
+    window: Option<XorWindow>,
+}
+
+impl FloatState {
+    pub fn new(count: u32) -> Self {
+        Self {
+            remaining: count,
+            previous: None,
+            window: None,
+        }
+    }
+
+    /// Values still to be decoded.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+}
+
+/// Iterator over the float bit patterns written by `encode_floats`, decoded
+/// as they are read. Ends early if `bytes` ends or is not a valid encoding.
+#[derive(Clone)]
+pub struct FloatDecoder<'a> {
+    reader: BitReader<'a>,
+    state: FloatState,
+}
+
+impl<'a> FloatDecoder<'a> {
+    pub fn new(bytes: &'a [u8], count: u32) -> Self {
+        Self::resume(bytes, 0, FloatState::new(count))
+    }
+
+    /// Continues at bit `position` of `bytes` with the `state` returned by
+    /// `suspend`.
+    pub fn resume(bytes: &'a [u8], position: usize, state: FloatState) -> Self {
+        Self {
+            reader: BitReader::new(bytes, position),
+            state,
+        }
+    }
+
+    /// Bit position of the next value and the state to `resume` with.
+    pub fn suspend(&self) -> (usize, FloatState) {
+        (self.reader.position, self.state)
+    }
+
+    fn decode_next(&mut self) -> Option<u64> {
+        let Some(mut value) = self.state.previous else {
+            let first = self.reader.bits(64)?;
+            self.state.previous = Some(first);
+            return Some(first);
+        };
+
+        let mut window = self.state.window;
+        if self.reader.bit()? {
+            if self.reader.bit()? {
+                let leading = self.reader.bits(6)? as u32;
+                let length = self.reader.bits(6)? as u32 + 1;
+                if leading + length > 64 {
+                    return None;
+                }
+                window = Some(XorWindow { leading, length });
+            }
+
+            let window = window?;
+            value ^= self.reader.bits(window.length)? << window.trailing();
+        }
+        self.state.window = window;
+        self.state.previous = Some(value);
+        Some(value)
+    }
+}
+
+impl Iterator for FloatDecoder<'_> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.state.remaining == 0 {
+            return None;
+        }
+        let value = self.decode_next();
+        self.state.remaining = if value.is_some() {
+            self.state.remaining - 1
+        } else {
+            0
+        };
+        value
+    }
+}
+
+/// Reads `count` float bit patterns written by `encode_floats`, `None` if
+/// `bytes` ends early or is not a valid encoding.
+pub fn decode_floats(bytes: &[u8], count: u32) -> Option<Vec<u64>> {
+    let mut values = Vec::with_capacity(count.min(1 << 16) as usize);
+    values.extend(FloatDecoder::new(bytes, count));
+    (values.len() == count as usize).then_some(values)
+}
+
+#[cfg(test)]
+mod roundtrip {
+    use crate::column_encoding::*;
+
+    #[test]
+    fn fixed_period_timestamps_take_a_bit_each() {
+        let timestamps: Vec<u64> = (0..1000).map(|i| 1_700_000_000 + i * 60).collect();
+        let encoded = encode_integers(timestamps.iter().copied());
+
+        // 64 bits for the first value, one bucket for the first delta and
+        // a single 0 bit for every other one.
+        assert!(encoded.len() < 8 + 8 + 1000 / 8 + 1);
+        assert_eq!(decode_integers(&encoded, 1000).unwrap(), timestamps);
+    }
+
+    #[test]
+    fn integers_of_every_bucket() {
+        let values: Vec<u64> = vec![
+            0,
+            5,
+            5,
+            100,
+            1000,
+            u32::MAX as u64,
+            u64::MAX,
+            0,
+            (-7_i64) as u64,
+            i64::MIN as u64,
+            42,
+        ];
+        let encoded = encode_integers(values.iter().copied());
+
+        assert_eq!(
+            decode_integers(&encoded, values.len() as u32).unwrap(),
+            values
+        );
+    }
+
+    #[test]
+    fn floats() {
+        let values: Vec<u64> = [12.5_f64, 12.5, 12.75, 13.0, -1e300, 0.0, f64::NAN, 12.75]
+            .iter()
+            .map(|v| v.to_bits())
+            .collect();
+        let encoded = encode_floats(values.iter().copied());
+
+        assert_eq!(
+            decode_floats(&encoded, values.len() as u32).unwrap(),
+            values
+        );
+    }
+
+    #[test]
+    fn slowly_moving_floats_are_small() {
+        let values: Vec<u64> = (0..1000)
+            .map(|i| (20.0_f32 + (i / 100) as f32 * 0.5).to_bits() as u64)
+            .collect();
+        let encoded = encode_floats(values.iter().copied());
+
+        assert!(encoded.len() < 1000 / 4);
+        assert_eq!(decode_floats(&encoded, 1000).unwrap(), values);
+    }
+
+    #[test]
+    fn empty_columns() {
+        assert!(encode_integers([]).is_empty());
+        assert!(encode_floats([]).is_empty());
+        assert_eq!(decode_integers(&[], 0).unwrap(), Vec::<u64>::new());
+        assert_eq!(decode_floats(&[], 0).unwrap(), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn truncated_columns() {
+        let encoded = encode_integers([1, 2, 30000, 4]);
+        assert!(decode_integers(&encoded[..encoded.len() - 2], 4).is_none());
+
+        let encoded = encode_floats([1.0_f64.to_bits(), 2.0_f64.to_bits()]);
+        assert!(decode_floats(&encoded[..8], 2).is_none());
+    }
+}
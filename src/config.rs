@@ -0,0 +1,361 @@
+use std::{collections::HashMap, net::SocketAddr, path::Path};
+
+use serde::Deserialize;
+
+use crate::collectors::COLLECTOR_NAMES;
+use crate::rollup::{RollupFunction, RollupTier};
+use tinygraph::tgph_format::Retention;
+
+/// Contents of the `--config` TOML file. Everything is optional, values given
+/// on the command line take precedence over the ones read from here.
+///
+/// ```toml
+/// output_path = "data.tgph.gz"
+/// entry_limit = 10000
+/// period = 60
+/// compression_level = 9
+///
+/// [retention]
+/// max_age = 2592000
+///
+/// [retention.containers]
+/// "CO2 Concentration [ppm]" = 86400
+///
+/// [[rollups]]
+/// interval = 3600
+/// max_age = 31536000
+/// functions = ["min", "avg", "max"]
+///
+/// [http]
+/// listen = "0.0.0.0:8080"
+///
+/// [collectors]
+/// enabled = ["disk", "network", "cpu", "memory", "co2"]
+///
+/// [collectors.disk]
+/// exclude = ["overlay"]
+///
+/// [collectors.network]
+/// include = ["eth0", "wlan0"]
+///
+/// [collectors.co2]
+/// address = "192.168.1.15:6969"
+/// timeout = 5
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub output_path: Option<String>,
+    pub entry_limit: Option<usize>,
+    /// Seconds between each system state read.
+    pub period: Option<u64>,
+    pub keep_backup: bool,
+    pub widen_mismatched_types: bool,
+    /// Store numeric containers in the compact encoding instead of as plain
+    /// values. Needs format version 4, which older readers reject.
+    pub compact_columns: bool,
+    /// Write an index of the containers at the end of the output file, for
+    /// readers seeking to single containers. Needs format version 6.
+    pub container_index: bool,
+    /// Store a CRC32C of every container and of the whole file, readable
+    /// since format version 5.
+    pub checksums: bool,
+    /// Log every sample and only rewrite the output file every
+    /// `compaction_period` seconds.
+    pub append_log: bool,
+    pub compaction_period: Option<u64>,
+    /// libdeflate compression level of the output file, 0 to 12.
+    pub compression_level: Option<i32>,
+    pub retention: RetentionConfig,
+    pub rollups: Vec<RollupConfig>,
+    pub http: HttpConfig,
+    pub collectors: CollectorsConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Address to serve the dashboard and the current data on, nothing is
+    /// served if unset.
+    pub listen: Option<SocketAddr>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Seconds to keep samples for, 0 to keep them until `entry_limit` is
+    /// reached.
+    pub max_age: u64,
+    /// `max_age` of single containers, by container name.
+    pub containers: HashMap<String, u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RollupConfig {
+    /// Seconds summarized by each element of the tier.
+    pub interval: u64,
+    /// Seconds to keep the tier for, 0 to keep it until `entry_limit` is
+    /// reached.
+    #[serde(default)]
+    pub max_age: u64,
+    #[serde(default = "default_rollup_functions")]
+    pub functions: Vec<RollupFunction>,
+}
+
+fn default_rollup_functions() -> Vec<RollupFunction> {
+    vec![RollupFunction::Avg]
+}
+
+impl RollupConfig {
+    pub fn tier(&self) -> RollupTier {
+        RollupTier {
+            interval: self.interval,
+            functions: self.functions.clone(),
+        }
+    }
+}
+
+impl Config {
+    /// Global retention, together with the `max_age` of every rollup tier.
+    pub fn retention(&self) -> Retention {
+        let mut retention = self.retention.retention();
+        for rollup in &self.rollups {
+            let max_age = (rollup.max_age > 0).then_some(rollup.max_age);
+            retention
+                .prefix_overrides
+                .push((rollup.tier().prefix(), max_age));
+        }
+        retention
+    }
+}
+
+impl RetentionConfig {
+    pub fn retention(&self) -> Retention {
+        let max_age = |age: u64| (age > 0).then_some(age);
+        Retention {
+            max_age: max_age(self.max_age),
+            overrides: self
+                .containers
+                .iter()
+                .map(|(name, &age)| (name.clone(), max_age(age)))
+                .collect(),
+            prefix_overrides: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectorsConfig {
+    pub enabled: Vec<String>,
+    pub disk: NameFilter,
+    pub network: NameFilter,
+    pub co2: Co2Config,
+}
+
+impl Default for CollectorsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: COLLECTOR_NAMES.map(String::from).to_vec(),
+            disk: NameFilter::default(),
+            network: NameFilter::default(),
+            co2: Co2Config::default(),
+        }
+    }
+}
+
+/// Picks disks or interfaces by name. An empty `include` list lets everything
+/// through, `exclude` is applied afterwards.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NameFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl NameFilter {
+    pub fn allows(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|n| n == name))
+            && !self.exclude.iter().any(|n| n == name)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Co2Config {
+    pub address: SocketAddr,
+    /// Seconds to wait for the sensor to connect and answer.
+    pub timeout: u64,
+    /// Longest time in seconds to wait before retrying a failing sensor.
+    pub max_backoff: u64,
+}
+
+impl Default for Co2Config {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([192, 168, 1, 15], 6969)),
+            timeout: 10,
+            max_backoff: 300,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, std::io::Error> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: {e}", path.display()),
+            )
+        })
+    }
+
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+}
+
+#[cfg(test)]
+mod parse {
+    use crate::config::*;
+
+    #[test]
+    fn empty_config_uses_defaults() {
+        let config = Config::parse("").unwrap();
+
+        assert_eq!(config.output_path, None);
+        assert_eq!(config.entry_limit, None);
+        assert!(!config.keep_backup);
+        assert_eq!(config.collectors.enabled, COLLECTOR_NAMES);
+        assert_eq!(
+            config.collectors.co2.address.to_string(),
+            "192.168.1.15:6969"
+        );
+        assert_eq!(config.collectors.co2.timeout, 10);
+    }
+
+    #[test]
+    fn full_config() {
+        let config = Config::parse(
+            r#"
+            output_path = "/var/lib/tinygraph/data.tgph.gz"
+            entry_limit = 10000
+            period = 30
+            keep_backup = true
+            compression_level = 12
+
+            [http]
+            listen = "127.0.0.1:8080"
+
+            [collectors]
+            enabled = ["disk", "network", "co2"]
+
+            [collectors.disk]
+            exclude = ["overlay"]
+
+            [collectors.network]
+            include = ["eth0"]
+
+            [collectors.co2]
+            address = "10.0.0.2:7000"
+            max_backoff = 60
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.output_path.as_deref(),
+            Some("/var/lib/tinygraph/data.tgph.gz")
+        );
+        assert_eq!(config.entry_limit, Some(10000));
+        assert_eq!(config.period, Some(30));
+        assert!(config.keep_backup);
+        assert_eq!(config.compression_level, Some(12));
+        assert_eq!(
+            config.http.listen.map(|a| a.to_string()).as_deref(),
+            Some("127.0.0.1:8080")
+        );
+        assert_eq!(config.collectors.enabled, ["disk", "network", "co2"]);
+        assert_eq!(config.collectors.disk.exclude, ["overlay"]);
+        assert_eq!(config.collectors.network.include, ["eth0"]);
+        assert_eq!(config.collectors.co2.address.to_string(), "10.0.0.2:7000");
+        assert_eq!(config.collectors.co2.timeout, 10);
+        assert_eq!(config.collectors.co2.max_backoff, 60);
+    }
+
+    #[test]
+    fn retention() {
+        let config = Config::parse(
+            r#"
+            [retention]
+            max_age = 3600
+
+            [retention.containers]
+            "Hostname" = 0
+            "CO2 Concentration [ppm]" = 60
+            "#,
+        )
+        .unwrap();
+        let retention = config.retention.retention();
+
+        assert_eq!(retention.max_age_of("Used memory [MB]"), Some(3600));
+        assert_eq!(retention.max_age_of("Hostname"), None);
+        assert_eq!(retention.max_age_of("CO2 Concentration [ppm]"), Some(60));
+        assert_eq!(
+            Config::parse("").unwrap().retention.retention().max_age,
+            None
+        );
+    }
+
+    #[test]
+    fn rollups() {
+        let config = Config::parse(
+            r#"
+            [retention]
+            max_age = 86400
+
+            [[rollups]]
+            interval = 60
+            max_age = 604800
+
+            [[rollups]]
+            interval = 3600
+            functions = ["min", "avg", "max"]
+            "#,
+        )
+        .unwrap();
+        let retention = config.retention();
+
+        assert_eq!(config.rollups[0].functions, [RollupFunction::Avg]);
+        assert_eq!(config.rollups[1].functions.len(), 3);
+        assert_eq!(retention.max_age_of("Used memory [MB]"), Some(86400));
+        assert_eq!(
+            retention.max_age_of("Rollup 60s avg Used memory [MB]"),
+            Some(604800)
+        );
+        assert_eq!(retention.max_age_of("Rollup 3600s Unix timestamp"), None);
+        assert!(Config::parse("[[rollups]]\nfunctions = [\"median\"]").is_err());
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(Config::parse("entry_limt = 10").is_err());
+        assert!(Config::parse("[collectors.disk]\ninclud = [\"sda\"]").is_err());
+    }
+
+    #[test]
+    fn name_filter() {
+        let everything = NameFilter::default();
+        assert!(everything.allows("eth0"));
+
+        let filter = NameFilter {
+            include: vec!["eth0".into(), "lo".into()],
+            exclude: vec!["lo".into()],
+        };
+        assert!(filter.allows("eth0"));
+        assert!(!filter.allows("lo"));
+        assert!(!filter.allows("wlan0"));
+    }
+}
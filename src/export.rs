@@ -0,0 +1,206 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+
+use crate::query::{json_values, to_json, Query};
+use tinygraph::tgph_format::TGPH;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// One row per time, one column per container
+    Csv,
+    /// The whole containers, as returned by /api/query?format=json
+    Json,
+    /// One JSON object per time, like a row of the CSV
+    Ndjson,
+}
+
+/// Column name of the times in CSV and NDJSON exports.
+pub const TIMESTAMP_COLUMN: &str = "timestamp";
+
+/// `TGPH::rows` with the values of the cells, as written by the CSV and
+/// NDJSON writers.
+fn rows(tgph: &TGPH) -> (Vec<&str>, BTreeMap<u64, Vec<Option<Value>>>) {
+    let (columns, rows) = tgph.rows();
+    let values: Vec<_> = columns.iter().map(|c| json_values(&c.elements)).collect();
+
+    let rows = rows
+        .into_iter()
+        .map(|(time, row)| {
+            let cells = row
+                .into_iter()
+                .zip(&values)
+                .map(|(index, values)| index.map(|i| values[i].clone()))
+                .collect();
+            (time, cells)
+        })
+        .collect();
+    let names = columns.iter().map(|c| c.name.as_str()).collect();
+    (names, rows)
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Writes the containers of `tgph` that have a time axis as CSV, with a
+/// `timestamp` column followed by one column per container. Samples missing
+/// at a time are empty, not a number is `NaN`.
+pub fn write_csv<W: Write>(tgph: &TGPH, out: &mut W) -> std::io::Result<()> {
+    let (names, rows) = rows(tgph);
+
+    let header: Vec<String> = std::iter::once(TIMESTAMP_COLUMN)
+        .chain(names)
+        .map(csv_field)
+        .collect();
+    writeln!(out, "{}", header.join(","))?;
+
+    for (time, row) in rows {
+        let mut line = time.to_string();
+        for cell in row {
+            line.push(',');
+            match cell {
+                Some(Value::String(s)) => line.push_str(&csv_field(&s)),
+                Some(Value::Null) => line.push_str("NaN"),
+                Some(value) => line.push_str(&value.to_string()),
+                None => {}
+            }
+        }
+        writeln!(out, "{line}")?;
+    }
+
+    Ok(())
+}
+
+/// Writes one JSON object per line and time, holding the `timestamp` and the
+/// value of every container sampled at that time.
+pub fn write_ndjson<W: Write>(tgph: &TGPH, out: &mut W) -> std::io::Result<()> {
+    let (names, rows) = rows(tgph);
+
+    for (time, row) in rows {
+        let mut object = Map::new();
+        object.insert(TIMESTAMP_COLUMN.to_string(), time.into());
+        for (name, cell) in names.iter().zip(row) {
+            if let Some(value) = cell {
+                object.insert(name.to_string(), value);
+            }
+        }
+        writeln!(out, "{}", Value::Object(object))?;
+    }
+
+    Ok(())
+}
+
+pub fn write_json<W: Write>(tgph: &TGPH, out: &mut W) -> std::io::Result<()> {
+    writeln!(out, "{:#}", to_json(tgph))
+}
+
+/// Writes the part of the file at `path` selected by `query` to `output`, or
+/// to stdout if there is none.
+pub fn run(
+    path: &Path,
+    format: ExportFormat,
+    query: &Query,
+    output: Option<&Path>,
+) -> Result<(), std::io::Error> {
+    let tgph = tinygraph::load_tgph(path)?;
+    let subset = query.apply(&tgph);
+
+    let mut out: Box<dyn Write> = match output {
+        Some(output) => Box::new(BufWriter::new(std::fs::File::create(output)?)),
+        None => Box::new(BufWriter::new(std::io::stdout().lock())),
+    };
+    match format {
+        ExportFormat::Csv => write_csv(&subset, &mut out)?,
+        ExportFormat::Json => write_json(&subset, &mut out)?,
+        ExportFormat::Ndjson => write_ndjson(&subset, &mut out)?,
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod formats {
+    use crate::export::*;
+    use tinygraph::tgph_format::{ContainerMetadata, MetricKind};
+
+    fn tgph() -> TGPH {
+        let mut tgph = TGPH::new(100);
+        for time in [100_u32, 160, 220] {
+            tgph.append(time, "time").unwrap();
+        }
+        for load in [0.1_f32, f32::NAN] {
+            tgph.append(load, "CPU load").unwrap();
+        }
+        tgph.append(250_u32, "time CO2").unwrap();
+        tgph.append(410_u32, "CO2, indoors").unwrap();
+        tgph.append("pi \"4\"".to_string(), "Hostname").unwrap();
+        for (name, axis) in [("CPU load", "time"), ("CO2, indoors", "time CO2")] {
+            tgph.set_metadata(
+                name,
+                ContainerMetadata::new(MetricKind::Gauge, "", "").with_time_axis(axis),
+            );
+        }
+        tgph.set_metadata(
+            "Hostname",
+            ContainerMetadata::new(MetricKind::Info, "", "").with_time_axis("time"),
+        );
+        tgph
+    }
+
+    #[test]
+    fn csv_joins_time_axes() {
+        let mut csv = Vec::new();
+        write_csv(&tgph(), &mut csv).unwrap();
+
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "timestamp,CPU load,\"CO2, indoors\",Hostname\n\
+             160,0.1,,\n\
+             220,NaN,,\"pi \"\"4\"\"\"\n\
+             250,,410,\n"
+        );
+    }
+
+    #[test]
+    fn ndjson_has_a_line_per_time() {
+        let mut ndjson = Vec::new();
+        write_ndjson(&tgph(), &mut ndjson).unwrap();
+        let lines: Vec<Value> = String::from_utf8(ndjson)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+
+        // Nothing was sampled at 100 besides the time itself.
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            serde_json::json!({"timestamp": 160, "CPU load": 0.1})
+        );
+        assert_eq!(
+            lines[2],
+            serde_json::json!({"timestamp": 250, "CO2, indoors": 410})
+        );
+    }
+
+    #[test]
+    fn filters_apply_before_export() {
+        let query = Query::parse("match=CO2&from=200").unwrap();
+        let mut csv = Vec::new();
+        write_csv(&query.apply(&tgph()), &mut csv).unwrap();
+
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "timestamp,\"CO2, indoors\"\n250,410\n"
+        );
+    }
+}
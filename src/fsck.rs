@@ -0,0 +1,117 @@
+use std::{
+    io::{ErrorKind, Read},
+    path::Path,
+};
+
+use flate2::read::MultiGzDecoder;
+
+use tinygraph::tgph_format::{Salvage, TGPH};
+use tinygraph::CompressionLvl;
+
+/// As much of the gzip stream as can be decompressed, and the error that
+/// stopped it if it did not reach the end.
+fn decompress_partially(compressed: &[u8]) -> (Vec<u8>, Option<std::io::Error>) {
+    let mut decoder = MultiGzDecoder::new(compressed);
+    let mut decompressed = Vec::new();
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        match decoder.read(&mut chunk) {
+            Ok(0) => return (decompressed, None),
+            Ok(read) => decompressed.extend_from_slice(&chunk[..read]),
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return (decompressed, Some(err)),
+        }
+    }
+}
+
+/// One line per container and one per problem of the whole file.
+fn report(salvage: &Salvage, gzip_error: Option<&std::io::Error>) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some(err) = gzip_error {
+        lines.push(format!("gzip: {err}"));
+    }
+    for check in &salvage.containers {
+        let name = check.name.as_deref().unwrap_or("?");
+        lines.push(match &check.error {
+            None => format!("ok       {name}"),
+            Some(err) => format!("DAMAGED  {name} at byte {}: {err}", check.offset),
+        });
+    }
+    if salvage.lost > 0 {
+        lines.push(format!(
+            "LOST     {} containers after a damaged one, the file has no index to find them",
+            salvage.lost
+        ));
+    }
+    if let Some(err) = &salvage.file_error {
+        lines.push(format!("file: {err}"));
+    }
+    lines
+}
+
+/// Checks the file at `path` and prints which containers are damaged. The
+/// intact ones are written to `salvage_path` if given. Fails if anything is
+/// damaged, after writing them.
+pub fn run(path: &Path, salvage_path: Option<&Path>) -> Result<(), std::io::Error> {
+    let compressed = std::fs::read(path)?;
+    let (decompressed, gzip_error) = decompress_partially(&compressed);
+    let salvage = TGPH::salvage(&decompressed)?;
+
+    let intact = salvage.tgph.containers.len();
+    let total = salvage.containers.len() + salvage.lost as usize;
+    println!(
+        "{}: format version {}, {intact} of {total} containers intact\n",
+        path.display(),
+        salvage.tgph.version(),
+    );
+    for line in report(&salvage, gzip_error.as_ref()) {
+        println!("{line}");
+    }
+
+    if let Some(salvage_path) = salvage_path {
+        let compressed = tinygraph::compress_tgph(&salvage.tgph, CompressionLvl::default());
+        std::fs::write(salvage_path, compressed)?;
+        println!(
+            "\nwrote the {intact} intact containers to {}",
+            salvage_path.display()
+        );
+    }
+
+    match gzip_error.is_none() && salvage.is_intact() {
+        true => Ok(()),
+        false => Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is damaged", path.display()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod damaged {
+    use crate::fsck::*;
+
+    #[test]
+    fn truncated_files_are_salvaged() {
+        let mut tgph = TGPH::new(100);
+        for i in 0..1000_u32 {
+            tgph.append(i, "first").unwrap();
+            tgph.append(i.wrapping_mul(2654435761), "second").unwrap();
+        }
+        tgph.checksums = true;
+        let compressed = tinygraph::compress_tgph(&tgph, CompressionLvl::default());
+
+        let (decompressed, gzip_error) =
+            decompress_partially(&compressed[..compressed.len() * 3 / 4]);
+        assert!(gzip_error.is_some());
+        let salvage = TGPH::salvage(&decompressed).unwrap();
+        assert!(!salvage.is_intact());
+        assert_eq!(salvage.tgph.containers.len(), 1);
+        assert_eq!(salvage.tgph.containers[0], tgph.containers[0]);
+
+        let lines = report(&salvage, gzip_error.as_ref());
+        assert!(lines[0].starts_with("gzip: "));
+        assert_eq!(lines[1], "ok       first");
+        let offset = salvage.containers[1].offset;
+        assert!(lines[2].starts_with(&format!("DAMAGED  ? at byte {offset}: ")));
+    }
+}
@@ -0,0 +1,87 @@
+//! Gzip framing of TGPH files.
+//!
+//! Writing uses libdeflate, which compresses a whole buffer in one call and
+//! has levels up to 12 that make smaller files than zlib's best level, at a
+//! lower CPU cost per rewrite. It cannot decompress without knowing the size
+//! of the output up front, nor in chunks, so reading goes through flate2.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use flate2::read::MultiGzDecoder;
+pub use libdeflater::CompressionLvl;
+use libdeflater::Compressor;
+
+use crate::tgph_format::TGPH;
+
+/// Reads a whole gzip stream and returns the data it holds. Streams of
+/// several members are read to the end.
+pub fn decompress<R: Read>(stream: &mut R) -> Result<Vec<u8>, std::io::Error> {
+    // The size in the gzip footer is only that of the last member, modulo
+    // 4 GiB, so the output grows as needed instead.
+    let mut outbuf = Vec::new();
+    MultiGzDecoder::new(stream).read_to_end(&mut outbuf)?;
+    Ok(outbuf)
+}
+
+/// Compresses `data` into a gzip stream.
+pub fn compress(data: &[u8], compression_level: CompressionLvl) -> Vec<u8> {
+    let mut compressor = Compressor::new(compression_level);
+    let max_sz = compressor.gzip_compress_bound(data.len());
+    let mut compressed_data = vec![0; max_sz];
+    let actual_sz = compressor
+        .gzip_compress(data, &mut compressed_data)
+        .unwrap();
+    compressed_data.resize(actual_sz, 0);
+    compressed_data
+}
+
+/// `tgph` as the contents of a .tgph.gz file.
+pub fn compress_tgph(tgph: &TGPH, compression_level: CompressionLvl) -> Vec<u8> {
+    let mut output_buffer = Vec::new();
+    tgph.serialize_into(&mut output_buffer).unwrap();
+    compress(&output_buffer, compression_level)
+}
+
+/// Reads the .tgph.gz file at `path`.
+pub fn load_tgph(path: &Path) -> Result<TGPH, std::io::Error> {
+    let mut stream = MultiGzDecoder::new(BufReader::new(File::open(path)?));
+    let tgph = TGPH::deserialize_from(&mut stream)?;
+
+    // The checksums of the gzip members are only verified at their end.
+    std::io::copy(&mut stream, &mut std::io::sink())?;
+    Ok(tgph)
+}
+
+#[cfg(test)]
+mod roundtrip {
+    use std::io::Cursor;
+
+    use crate::gzip::*;
+
+    #[test]
+    fn tgph_survives_compression() {
+        let mut tgph = TGPH::new(10);
+        tgph.append(1700000000_u32, "time").unwrap();
+        tgph.append("pi".to_string(), "Hostname").unwrap();
+
+        let compressed = compress_tgph(&tgph, CompressionLvl::default());
+        let decompressed = decompress(&mut compressed.as_slice()).unwrap();
+        let read = TGPH::deserialize_from(&mut Cursor::new(decompressed)).unwrap();
+
+        assert_eq!(read.containers.len(), 2);
+        assert_eq!(read.container("Hostname").unwrap().elements.len(), 1);
+        assert!(decompress(&mut [0x1f_u8, 0x8b].as_slice()).is_err());
+    }
+
+    #[test]
+    fn members_are_concatenated() {
+        let mut gz = compress(b"tiny", CompressionLvl::default());
+        gz.extend(compress(b"graph", CompressionLvl::default()));
+
+        assert_eq!(decompress(&mut gz.as_slice()).unwrap(), b"tinygraph");
+    }
+}
@@ -0,0 +1,658 @@
+use std::{
+    borrow::Cow,
+    io::{BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, RwLock,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use tinygraph::CompressionLvl;
+
+use crate::query::{to_json, Query, QueryFormat};
+use tinygraph::tgph_format::TGPH;
+
+const INDEX_HTML: &str = include_str!("../website/index.html");
+const INDEX_JS: &str = include_str!("../website/index.js");
+
+/// Longest request head (request line and headers) that is read.
+const MAX_REQUEST_HEAD: u64 = 16 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long an event stream may stay silent before a comment is sent, which
+/// keeps proxies from closing it and notices clients that went away.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// Most connections answered at once, each of them takes a thread for as long
+/// as it is open, which for event streams is as long as the client wants.
+const MAX_CONNECTIONS: usize = 32;
+/// Longest time the accepting thread spends on a connection over the limit.
+const REJECT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The collected data gzipped as served to the dashboard.
+struct Gzipped {
+    gzip: Vec<u8>,
+    etag: String,
+}
+
+impl Gzipped {
+    fn new(gzip: Vec<u8>) -> Self {
+        let etag = etag_of(&gzip);
+        Self { gzip, etag }
+    }
+}
+
+fn etag_of(content: &[u8]) -> String {
+    format!("\"{:08x}\"", crc32c::crc32c(content))
+}
+
+/// What `data.tgph.gz` is served from.
+#[derive(Default)]
+enum Served {
+    /// Nothing was collected yet.
+    #[default]
+    Nothing,
+    /// The data changed since it was last gzipped.
+    Changed,
+    Gzipped(Arc<Gzipped>),
+}
+
+/// State shared between the collection loop and the HTTP server.
+#[derive(Default)]
+pub struct Dashboard {
+    /// The TGPH the collection loop merges into, queried by `/api/query`.
+    data: Arc<RwLock<TGPH>>,
+    compression_level: CompressionLvl,
+    served: Mutex<Served>,
+    /// One per open `/api/events` stream.
+    subscribers: Mutex<Vec<Sender<Arc<str>>>>,
+}
+
+impl Dashboard {
+    pub fn new(data: Arc<RwLock<TGPH>>, compression_level: CompressionLvl) -> Self {
+        Self {
+            data,
+            compression_level,
+            ..Self::default()
+        }
+    }
+
+    /// Tells the server the data changed, after the collection loop let go
+    /// of it. `gzip` is the data as just saved, if it was, otherwise it is
+    /// compressed on the next request for `data.tgph.gz`.
+    pub fn publish(&self, gzip: Option<Vec<u8>>) {
+        *self.served.lock().unwrap() = match gzip {
+            Some(gzip) => Served::Gzipped(Arc::new(Gzipped::new(gzip))),
+            None => Served::Changed,
+        };
+    }
+
+    fn is_published(&self) -> bool {
+        !matches!(*self.served.lock().unwrap(), Served::Nothing)
+    }
+
+    fn gzipped(&self) -> Option<Arc<Gzipped>> {
+        // Reading the data first keeps the collection loop from changing it
+        // while it is compressed, requests meanwhile wait for the result.
+        let data = self.data.read().unwrap();
+        let mut served = self.served.lock().unwrap();
+        match &*served {
+            Served::Nothing => None,
+            Served::Gzipped(gzipped) => Some(gzipped.clone()),
+            Served::Changed => {
+                let gzipped = Arc::new(Gzipped::new(tinygraph::compress_tgph(
+                    &data,
+                    self.compression_level,
+                )));
+                *served = Served::Gzipped(gzipped.clone());
+                Some(gzipped)
+            }
+        }
+    }
+
+    /// Pushes the values collected in one period to every `/api/events`
+    /// stream, as `{"entry_limit": ..., "replaced": [...], "containers":
+    /// [...]}` with the containers as returned by `/api/query?format=json`.
+    /// Containers listed in `replaced` hold their whole new contents, the
+    /// others the values appended to them.
+    pub fn broadcast(&self, sample: &TGPH) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+
+        let mut event = to_json(sample);
+        event["entry_limit"] = sample.entry_limit.into();
+        event["replaced"] = sample.replaced_containers().into();
+        let event: Arc<str> = event.to_string().into();
+
+        // Streams whose client went away have dropped their receiver.
+        subscribers.retain(|s| s.send(event.clone()).is_ok());
+    }
+
+    fn subscribe(&self) -> Receiver<Arc<str>> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+}
+
+/// Serves the embedded dashboard and the collected data, so no
+/// separate web server is needed.
+///
+/// Every connection gets its own thread and is closed after one response,
+/// or when the client leaves for event streams, which is plenty for a
+/// handful of dashboards. Connections beyond `max_connections` are answered
+/// with 503 right away.
+pub struct HttpServer {
+    listener: TcpListener,
+    dashboard: Arc<Dashboard>,
+    max_connections: usize,
+    open_connections: Arc<AtomicUsize>,
+}
+
+/// Counts a connection as open until dropped.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    fn take(open_connections: &Arc<AtomicUsize>, max_connections: usize) -> Option<Self> {
+        open_connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| {
+                (open < max_connections).then_some(open + 1)
+            })
+            .ok()
+            .map(|_| Self(open_connections.clone()))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl HttpServer {
+    pub fn bind(address: SocketAddr, dashboard: Arc<Dashboard>) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        Ok(Self {
+            listener,
+            dashboard,
+            max_connections: MAX_CONNECTIONS,
+            open_connections: Arc::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        eprintln!("\nFailed to accept HTTP connection: {e}");
+                        continue;
+                    }
+                };
+
+                let Some(slot) = ConnectionSlot::take(&self.open_connections, self.max_connections)
+                else {
+                    let _ = reject_connection(stream);
+                    continue;
+                };
+
+                let dashboard = self.dashboard.clone();
+                std::thread::spawn(move || {
+                    let _slot = slot;
+                    if let Err(e) = handle_connection(stream, &dashboard) {
+                        eprintln!("\nFailed to answer HTTP request: {e}");
+                    }
+                });
+            }
+        })
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    /// Everything after the `?` of the target, empty if there is none.
+    query: String,
+    if_none_match: Option<String>,
+}
+
+/// Reads the request line and headers, `None` if the client closed the
+/// connection or sent something that is not HTTP/1.x.
+fn read_request<R: Read>(stream: R) -> std::io::Result<Option<Request>> {
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_HEAD));
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Ok(None);
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Ok(None);
+    }
+
+    let mut if_none_match = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("if-none-match") {
+                if_none_match = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let target = target.split('#').next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    Ok(Some(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        if_none_match,
+    }))
+}
+
+struct Response<'a> {
+    status: &'static str,
+    content_type: &'static str,
+    body: Cow<'a, [u8]>,
+    etag: Option<String>,
+}
+
+impl<'a> Response<'a> {
+    fn ok(content_type: &'static str, body: impl Into<Cow<'a, [u8]>>) -> Self {
+        let body = body.into();
+        Response {
+            status: "200 OK",
+            content_type,
+            etag: Some(etag_of(&body)),
+            body,
+        }
+    }
+
+    fn error(status: &'static str, message: impl Into<Cow<'a, [u8]>>) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: message.into(),
+            etag: None,
+        }
+    }
+
+    fn write_to<W: Write>(&self, stream: &mut W, request: &Request) -> std::io::Result<()> {
+        let not_modified = matches!(
+            (&self.etag, &request.if_none_match),
+            (Some(etag), Some(wanted)) if wanted.split(',').any(|w| w.trim() == etag || w.trim() == "*")
+        );
+        let status = if not_modified {
+            "304 Not Modified"
+        } else {
+            self.status
+        };
+
+        let mut head = format!("HTTP/1.1 {status}\r\n");
+        // Everything can change with the next period or the next build,
+        // browsers have to revalidate, which the ETag makes cheap.
+        head.push_str("Cache-Control: no-cache\r\n");
+        if let Some(etag) = &self.etag {
+            head.push_str(&format!("ETag: {etag}\r\n"));
+        }
+        if self.status.starts_with("405") {
+            head.push_str("Allow: GET, HEAD\r\n");
+        }
+        if !not_modified {
+            head.push_str(&format!("Content-Type: {}\r\n", self.content_type));
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("Connection: close\r\n\r\n");
+
+        stream.write_all(head.as_bytes())?;
+        if !not_modified && request.method != "HEAD" {
+            stream.write_all(&self.body)?;
+        }
+        stream.flush()
+    }
+}
+
+fn handle_connection(stream: TcpStream, dashboard: &Dashboard) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let Some(request) = read_request(&stream)? else {
+        return Ok(());
+    };
+
+    if request.method == "GET" && request.path == "/api/events" {
+        return stream_events(stream, dashboard);
+    }
+
+    let published = dashboard.is_published();
+    // Outlives the response borrowing it.
+    let gzipped;
+    let response = match (request.method.as_str(), request.path.as_str()) {
+        ("GET" | "HEAD", "/" | "/index.html") => {
+            Response::ok("text/html; charset=utf-8", INDEX_HTML.as_bytes())
+        }
+        ("GET" | "HEAD", "/index.js") => {
+            Response::ok("text/javascript; charset=utf-8", INDEX_JS.as_bytes())
+        }
+        ("GET" | "HEAD", "/data.tgph.gz" | "/api/query") if !published => Response::error(
+            "503 Service Unavailable",
+            "No data collected yet".as_bytes(),
+        ),
+        // The dashboard decompresses the file itself, so it is sent as is
+        // rather than with a gzip Content-Encoding.
+        ("GET" | "HEAD", "/data.tgph.gz") => {
+            gzipped = dashboard.gzipped().unwrap();
+            Response {
+                etag: Some(gzipped.etag.clone()),
+                ..Response::ok("application/gzip", gzipped.gzip.as_slice())
+            }
+        }
+        ("GET" | "HEAD", "/api/query") => match Query::parse(&request.query) {
+            Ok(query) => {
+                let subset = query.apply(&dashboard.data.read().unwrap());
+                match query.format {
+                    QueryFormat::Tgph => Response::ok(
+                        "application/gzip",
+                        tinygraph::compress_tgph(&subset, CompressionLvl::default()),
+                    ),
+                    QueryFormat::Json => Response::ok(
+                        "application/json",
+                        to_json(&subset).to_string().into_bytes(),
+                    ),
+                }
+            }
+            Err(message) => Response::error("400 Bad Request", message.into_bytes()),
+        },
+        ("GET" | "HEAD", _) => Response::error("404 Not Found", "Not found".as_bytes()),
+        _ => Response::error("405 Method Not Allowed", "Method not allowed".as_bytes()),
+    };
+
+    response.write_to(&mut &stream, &request)
+}
+
+/// Answers 503 on the accepting thread. The request is only skipped briefly
+/// afterwards, as closing with it unread would reset the connection before
+/// the client read the answer.
+fn reject_connection(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_write_timeout(Some(REJECT_TIMEOUT))?;
+    stream.set_read_timeout(Some(REJECT_TIMEOUT))?;
+    let message = "Too many connections";
+    stream.write_all(
+        format!(
+            "HTTP/1.1 503 Service Unavailable\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\
+            Content-Length: {}\r\n\
+            Retry-After: 10\r\n\
+            Connection: close\r\n\r\n{message}",
+            message.len()
+        )
+        .as_bytes(),
+    )?;
+    stream.shutdown(Shutdown::Write)?;
+    std::io::copy(&mut (&stream).take(MAX_REQUEST_HEAD), &mut std::io::sink())?;
+    Ok(())
+}
+
+/// Sends every broadcast sample as a server-sent event until the client
+/// disconnects.
+fn stream_events(mut stream: TcpStream, dashboard: &Dashboard) -> std::io::Result<()> {
+    let samples = dashboard.subscribe();
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\n\
+        Content-Type: text/event-stream\r\n\
+        Cache-Control: no-cache\r\n\
+        Connection: close\r\n\r\n",
+    )?;
+
+    loop {
+        let message = match samples.recv_timeout(KEEP_ALIVE_INTERVAL) {
+            Ok(sample) => format!("event: sample\ndata: {sample}\n\n"),
+            Err(RecvTimeoutError::Timeout) => ": keep-alive\n\n".to_string(),
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        // Failing to write is how a closed event stream shows, not an error.
+        if stream.write_all(message.as_bytes()).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod server {
+    use crate::http::*;
+
+    fn start(dashboard: Arc<Dashboard>) -> SocketAddr {
+        start_with_limit(dashboard, MAX_CONNECTIONS)
+    }
+
+    fn start_with_limit(dashboard: Arc<Dashboard>, max_connections: usize) -> SocketAddr {
+        let mut server = HttpServer::bind("127.0.0.1:0".parse().unwrap(), dashboard).unwrap();
+        server.max_connections = max_connections;
+        let address = server.local_addr().unwrap();
+        server.spawn();
+        address
+    }
+
+    fn request(address: SocketAddr, request: &str) -> (String, Vec<u8>) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+
+        let head_end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(response[..head_end].to_vec()).unwrap();
+        (head, response[head_end + 4..].to_vec())
+    }
+
+    #[test]
+    fn serves_embedded_website() {
+        let address = start(Arc::default());
+
+        let (head, body) = request(address, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains("Content-Type: text/html"));
+        assert_eq!(body, INDEX_HTML.as_bytes());
+
+        let (head, body) = request(address, "GET /index.js?v=2 HTTP/1.1\r\n\r\n");
+        assert!(head.contains("Content-Type: text/javascript"));
+        assert_eq!(body, INDEX_JS.as_bytes());
+
+        let (head, _) = request(address, "GET /missing HTTP/1.1\r\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 404"));
+
+        let (head, _) = request(address, "POST / HTTP/1.1\r\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 405"));
+        assert!(head.contains("Allow: GET, HEAD"));
+    }
+
+    #[test]
+    fn serves_latest_snapshot() {
+        let dashboard = Arc::new(Dashboard::default());
+        let address = start(dashboard.clone());
+
+        let (head, _) = request(address, "GET /data.tgph.gz HTTP/1.1\r\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 503"));
+
+        dashboard.publish(Some(b"first".to_vec()));
+        dashboard.publish(Some(b"second".to_vec()));
+        let (head, body) = request(address, "GET /data.tgph.gz HTTP/1.1\r\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains("Content-Type: application/gzip"));
+        assert!(head.contains("Cache-Control: no-cache"));
+        assert!(head.contains("Content-Length: 6"));
+        assert_eq!(body, b"second");
+
+        let (head, body) = request(address, "HEAD /data.tgph.gz HTTP/1.1\r\n\r\n");
+        assert!(head.contains("Content-Length: 6"));
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn unchanged_snapshot_is_not_modified() {
+        let dashboard = Arc::new(Dashboard::default());
+        let address = start(dashboard.clone());
+        dashboard.publish(Some(b"data".to_vec()));
+
+        let etag = etag_of(b"data");
+        let (head, body) = request(
+            address,
+            &format!("GET /data.tgph.gz HTTP/1.1\r\nIf-None-Match: {etag}\r\n\r\n"),
+        );
+        assert!(head.starts_with("HTTP/1.1 304"));
+        assert!(head.contains(&format!("ETag: {etag}")));
+        assert!(body.is_empty());
+
+        dashboard.publish(Some(b"new data".to_vec()));
+        let (head, body) = request(
+            address,
+            &format!("GET /data.tgph.gz HTTP/1.1\r\nif-none-match: {etag}\r\n\r\n"),
+        );
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert_eq!(body, b"new data");
+    }
+
+    #[test]
+    fn changed_data_is_compressed_when_requested() {
+        let data = Arc::new(RwLock::new(TGPH::default()));
+        let dashboard = Arc::new(Dashboard::new(data.clone(), CompressionLvl::default()));
+        let address = start(dashboard.clone());
+
+        data.write()
+            .unwrap()
+            .append(100_u32, "Unix timestamp")
+            .unwrap();
+        dashboard.publish(None);
+        let (head, body) = request(address, "GET /data.tgph.gz HTTP/1.1\r\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        let read =
+            TGPH::deserialize_from(&mut tinygraph::decompress(&mut &body[..]).unwrap().as_slice())
+                .unwrap();
+        assert_eq!(read.containers, data.read().unwrap().containers);
+
+        // Compressed once until the data changes again.
+        let etag = etag_of(&body);
+        let (head, _) = request(
+            address,
+            &format!("GET /data.tgph.gz HTTP/1.1\r\nIf-None-Match: {etag}\r\n\r\n"),
+        );
+        assert!(head.starts_with("HTTP/1.1 304"));
+
+        data.write()
+            .unwrap()
+            .append(160_u32, "Unix timestamp")
+            .unwrap();
+        dashboard.publish(None);
+        let (head, _) = request(
+            address,
+            &format!("GET /data.tgph.gz HTTP/1.1\r\nIf-None-Match: {etag}\r\n\r\n"),
+        );
+        assert!(head.starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn query() {
+        let mut tgph = TGPH::default();
+        tgph.append(100_u32, "Unix timestamp").unwrap();
+        tgph.append(1.5_f32, "CPU load").unwrap();
+        tgph.append(42_u32, "Used memory [MB]").unwrap();
+        let data = Arc::new(RwLock::new(tgph));
+        let dashboard = Arc::new(Dashboard::new(data, CompressionLvl::default()));
+        let address = start(dashboard.clone());
+        dashboard.publish(None);
+
+        let (head, body) = request(
+            address,
+            "GET /api/query?match=CPU&format=json HTTP/1.1\r\n\r\n",
+        );
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains("Content-Type: application/json"));
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["containers"][0]["name"], "CPU load");
+        assert_eq!(json["containers"][0]["values"][0], 1.5);
+        assert_eq!(json["containers"].as_array().unwrap().len(), 1);
+
+        let (head, body) = request(address, "GET /api/query?step=0 HTTP/1.1\r\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 400"));
+        assert_eq!(body, b"\"step\" has to be at least 1 second");
+    }
+
+    #[test]
+    fn samples_are_streamed() {
+        let dashboard = Arc::new(Dashboard::default());
+        let address = start(dashboard.clone());
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"GET /api/events HTTP/1.1\r\nAccept: text/event-stream\r\n\r\n")
+            .unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut read_until_blank_line = || {
+            let mut lines = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim_end().is_empty() {
+                    return lines;
+                }
+                lines.push(line.trim_end().to_string());
+            }
+        };
+
+        // The stream is subscribed before its head is sent.
+        let head = read_until_blank_line();
+        assert_eq!(head[0], "HTTP/1.1 200 OK");
+        assert!(head.contains(&"Content-Type: text/event-stream".to_string()));
+
+        let mut sample = TGPH::new(50);
+        sample.append(100_u32, "Unix timestamp").unwrap();
+        sample.replace(vec![400_u32, 410], "CO2").unwrap();
+        dashboard.broadcast(&sample);
+
+        let event = read_until_blank_line();
+        assert_eq!(event[0], "event: sample");
+        let data: serde_json::Value =
+            serde_json::from_str(event[1].strip_prefix("data: ").unwrap()).unwrap();
+        assert_eq!(data["entry_limit"], 50);
+        assert_eq!(data["replaced"], serde_json::json!(["CO2"]));
+        assert_eq!(data["containers"][0]["values"], serde_json::json!([100]));
+        assert_eq!(
+            data["containers"][1]["values"],
+            serde_json::json!([400, 410])
+        );
+    }
+
+    #[test]
+    fn connections_over_the_limit_are_rejected() {
+        let address = start_with_limit(Arc::default(), 1);
+
+        let mut events = TcpStream::connect(address).unwrap();
+        events
+            .write_all(b"GET /api/events HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut head = [0; 15];
+        events.read_exact(&mut head).unwrap();
+        assert_eq!(&head, b"HTTP/1.1 200 OK");
+
+        let (head, body) = request(address, "GET / HTTP/1.1\r\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 503"));
+        assert_eq!(body, b"Too many connections");
+    }
+}
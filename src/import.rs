@@ -0,0 +1,652 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::ErrorKind,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use serde_json::Value;
+use tinygraph::CompressionLvl;
+
+use crate::export::TIMESTAMP_COLUMN;
+use crate::query::json_values;
+use crate::sample_log::{self, SampleLog};
+use tinygraph::tgph_format::{
+    ColumnEncoding, ContainerMetadata, ElementArrayType, ElementType, MetricKind, TGPHContainer,
+    TGPH,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ImportFormat {
+    /// A timestamp column and one column per container, as written by export
+    Csv,
+    /// InfluxDB line protocol, one container per measurement, tag set and field
+    Influx,
+}
+
+/// Unit of the timestamps of InfluxDB line protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Precision {
+    S,
+    Ms,
+    Us,
+    Ns,
+}
+
+impl Precision {
+    fn per_second(self) -> u64 {
+        match self {
+            Self::S => 1,
+            Self::Ms => 1_000,
+            Self::Us => 1_000_000,
+            Self::Ns => 1_000_000_000,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ImportArgs {
+    /// The .tgph.gz file to import into, created if it does not exist
+    file: PathBuf,
+
+    /// The file to read the data from
+    input: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = ImportFormat::Csv)]
+    format: ImportFormat,
+
+    /// How many entries per container are kept, the oldest are dropped
+    #[arg(long)]
+    entry_limit: usize,
+
+    /// Container holding the times of the imported containers. Defaults to
+    /// the time axis the imported containers already have, or to
+    /// "Imported timestamp" for new ones
+    #[arg(long)]
+    time_axis: Option<String>,
+
+    /// Column of the CSV holding the times, in seconds since the Unix epoch
+    #[arg(long, default_value = TIMESTAMP_COLUMN)]
+    timestamp_column: String,
+
+    /// Unit of the timestamps of InfluxDB line protocol
+    #[arg(long, value_enum, default_value_t = Precision::Ns)]
+    precision: Precision,
+
+    /// Store numeric containers delta-of-delta and XOR encoded instead of as
+    /// plain values, only readable since format version 4
+    #[arg(long)]
+    compact_columns: bool,
+}
+
+/// Imported values, by container and time in seconds since the Unix epoch.
+#[derive(Debug, Default)]
+pub struct Table {
+    columns: Vec<(String, BTreeMap<u64, Value>)>,
+    index: HashMap<String, usize>,
+}
+
+impl Table {
+    fn insert(&mut self, name: &str, time: u64, value: Value) {
+        let index = *self.index.entry(name.to_string()).or_insert_with(|| {
+            self.columns.push((name.to_string(), BTreeMap::new()));
+            self.columns.len() - 1
+        });
+        self.columns[index].1.insert(time, value);
+    }
+}
+
+/// A number if the cell is one, a string otherwise. Empty cells are `None`.
+fn parse_cell(cell: &str) -> Option<Value> {
+    if cell.is_empty() {
+        return None;
+    }
+    Some(if let Ok(v) = cell.parse::<u64>() {
+        v.into()
+    } else if let Ok(v) = cell.parse::<i64>() {
+        v.into()
+    } else if let Ok(v) = cell.parse::<f64>() {
+        // Not a number becomes null, which is imported as NaN.
+        v.into()
+    } else {
+        cell.into()
+    })
+}
+
+/// Splits CSV into records of fields, as described by RFC 4180.
+fn csv_records(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\r') if chars.peek() == Some(&'\n') => {}
+            (false, '\n') => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("Quoted field is not closed".to_string());
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Reads CSV with a header row, where `timestamp_column` holds the times and
+/// every other column is a container.
+pub fn parse_csv(text: &str, timestamp_column: &str) -> Result<Table, String> {
+    let mut records = csv_records(text)?.into_iter();
+    let header = records.next().ok_or("The file is empty")?;
+    let time_index = header
+        .iter()
+        .position(|h| h == timestamp_column)
+        .ok_or_else(|| format!("There is no \"{timestamp_column}\" column"))?;
+
+    let mut table = Table::default();
+    for (line, record) in records.enumerate() {
+        // The header is line 1.
+        let line = line + 2;
+        if record.len() == 1 && record[0].is_empty() {
+            continue;
+        }
+        if record.len() != header.len() {
+            return Err(format!(
+                "Line {line} has {} fields, the header {}",
+                record.len(),
+                header.len()
+            ));
+        }
+
+        let time = record[time_index]
+            .parse::<u64>()
+            .map_err(|_| format!("Line {line} has no valid timestamp"))?;
+        for (index, (name, cell)) in header.iter().zip(&record).enumerate() {
+            if index == time_index {
+                continue;
+            }
+            if let Some(value) = parse_cell(cell) {
+                table.insert(name, time, value);
+            }
+        }
+    }
+
+    Ok(table)
+}
+
+/// Splits `text` at unescaped occurrences of `separator` outside of double
+/// quotes, keeping the escapes.
+fn split_unescaped(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut escaped, mut quoted) = (0, false, false);
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(&text[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fn parse_field_value(value: &str) -> Option<Value> {
+    if let Some(string) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        return Some(unescape(string).into());
+    }
+    Some(match value {
+        "t" | "T" | "true" | "True" | "TRUE" => 1_u32.into(),
+        "f" | "F" | "false" | "False" | "FALSE" => 0_u32.into(),
+        _ if value.ends_with('i') => value[..value.len() - 1].parse::<i64>().ok()?.into(),
+        _ if value.ends_with('u') => value[..value.len() - 1].parse::<u64>().ok()?.into(),
+        // Numbers without a suffix are floats in line protocol.
+        _ => value.parse::<f64>().ok()?.into(),
+    })
+}
+
+/// Reads InfluxDB line protocol. Every field becomes a container named after
+/// the measurement, the tag set and the field, e.g. `cpu,host=pi usage_idle`.
+/// Lines without a timestamp are imported at `now`.
+pub fn parse_influx(text: &str, precision: Precision, now: u64) -> Result<Table, String> {
+    let mut table = Table::default();
+
+    for (line_number, line) in text.lines().enumerate() {
+        let line_number = line_number + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |what: &str| format!("Line {line_number} has {what}");
+
+        let parts = split_unescaped(line, ' ');
+        let (series, fields, time) = match parts[..] {
+            [series, fields] => (series, fields, None),
+            [series, fields, time] => (series, fields, Some(time)),
+            _ => return Err(invalid("no field set")),
+        };
+        let time = match time {
+            Some(time) => {
+                let time = time
+                    .parse::<i64>()
+                    .map_err(|_| invalid("an invalid timestamp"))?;
+                u64::try_from(time).map_err(|_| invalid("a timestamp before 1970"))?
+                    / precision.per_second()
+            }
+            None => now,
+        };
+
+        let series = unescape(series);
+        for field in split_unescaped(fields, ',') {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| invalid("a field without value"))?;
+            let value = parse_field_value(value).ok_or_else(|| invalid("an invalid value"))?;
+            table.insert(&format!("{series} {}", unescape(key)), time, value);
+        }
+    }
+
+    Ok(table)
+}
+
+fn float_type(value: f64) -> ElementType {
+    // Values like 0.1 that survive the trip through an f32 are stored as
+    // FLOAT32, like the values of the collectors.
+    match (value as f32).to_string().parse::<f64>() {
+        Ok(v) if v == value || value.is_nan() => ElementType::FLOAT32,
+        _ => ElementType::FLOAT64,
+    }
+}
+
+/// Narrowest type for the values of `cells`, which is at least as wide as
+/// `floor`.
+fn infer_type(cells: &[Option<Value>], floor: Option<ElementType>) -> ElementType {
+    let types = cells.iter().flatten().map(|value| match value {
+        Value::Number(n) if n.is_f64() => float_type(n.as_f64().unwrap()),
+        Value::Number(n) => match n.as_u64() {
+            Some(v) if v <= u32::MAX as u64 => ElementType::U32,
+            Some(_) => ElementType::U64,
+            None => ElementType::I64,
+        },
+        Value::Null => ElementType::FLOAT32,
+        _ => ElementType::STRING,
+    });
+
+    let widest = types
+        .chain(floor)
+        .reduce(|a, b| a.widened_with(b).unwrap_or(ElementType::STRING));
+    widest.unwrap_or(ElementType::FLOAT64)
+}
+
+/// Elements of the narrowest type for `cells`. Gaps in floats are NaN, in
+/// every other type they repeat the previous value, so that the containers
+/// of the collectors keep the type they are appended to with.
+fn build_elements(cells: &[Option<Value>], floor: Option<ElementType>) -> ElementArrayType {
+    let number = |cell: &Option<Value>| cell.as_ref().and_then(Value::as_f64).unwrap_or(f64::NAN);
+    let integer = |cell: &Option<Value>| cell.as_ref().and_then(Value::as_u64).unwrap_or_default();
+
+    let element_type = infer_type(cells, floor);
+    let filled: Vec<Option<Value>>;
+    let cells = match element_type {
+        ElementType::FLOAT32 | ElementType::FLOAT64 => cells,
+        _ => {
+            let mut previous = None;
+            filled = cells
+                .iter()
+                .map(|cell| {
+                    if cell.is_some() {
+                        previous = cell.clone();
+                    }
+                    previous.clone()
+                })
+                .collect();
+            &filled
+        }
+    };
+
+    match element_type {
+        ElementType::U32 => {
+            ElementArrayType::U32(cells.iter().map(|c| integer(c) as u32).collect())
+        }
+        ElementType::U64 => ElementArrayType::U64(cells.iter().map(integer).collect()),
+        ElementType::I64 => ElementArrayType::I64(
+            cells
+                .iter()
+                .map(|c| c.as_ref().and_then(Value::as_i64).unwrap_or_default())
+                .collect(),
+        ),
+        ElementType::FLOAT32 => {
+            ElementArrayType::FLOAT32(cells.iter().map(|c| number(c) as f32).collect())
+        }
+        ElementType::FLOAT64 => ElementArrayType::FLOAT64(cells.iter().map(number).collect()),
+        // Strings, which also keep whatever a type added later would hold.
+        _ => ElementArrayType::STRING(
+            cells
+                .iter()
+                .map(|cell| match cell {
+                    Some(Value::String(s)) => s.clone(),
+                    Some(Value::Null) => "NaN".to_string(),
+                    Some(value) => value.to_string(),
+                    None => String::new(),
+                })
+                .collect(),
+        ),
+    }
+}
+
+/// Replaces the elements of the container called `name`, adding it with
+/// `metadata` if there is none.
+fn set_elements(
+    tgph: &mut TGPH,
+    name: &str,
+    elements: ElementArrayType,
+    metadata: ContainerMetadata,
+) {
+    match tgph.containers.iter_mut().find(|c| c.name == name) {
+        Some(container) => container.elements = elements,
+        None => tgph.add_container(TGPHContainer {
+            name: name.to_string(),
+            elements,
+            metadata,
+        }),
+    }
+}
+
+/// Time axis of new containers imported without `--time-axis`. Keeps them
+/// from adding times to the containers of the collectors.
+pub const IMPORT_TIME_AXIS: &str = "Imported timestamp";
+
+/// The time axis the containers of `table` already have, if they all have
+/// the same one, or [`IMPORT_TIME_AXIS`] if none of them exists yet.
+fn default_time_axis(tgph: &TGPH, table: &Table) -> Result<String, String> {
+    let axes: BTreeSet<Option<&str>> = table
+        .columns
+        .iter()
+        .filter_map(|(name, _)| tgph.metadata(name))
+        .map(|m| m.time_axis.as_deref())
+        .collect();
+
+    match axes.into_iter().collect::<Vec<_>>()[..] {
+        [] => Ok(IMPORT_TIME_AXIS.to_string()),
+        [Some(axis)] => Ok(axis.to_string()),
+        _ => Err(
+            "The containers are on different time axes, choose one with --time-axis".to_string(),
+        ),
+    }
+}
+
+/// Merges `table` into the containers on the time axis `axis`.
+///
+/// Times cannot just be appended without shifting the other containers on
+/// the axis, see `TGPH::axis_offset`. Instead the axis and all of its containers are rebuilt from the
+/// union of their times, with imported values replacing existing ones at
+/// the same time. A container only starts at its oldest value, later gaps
+/// are filled as described at [`build_elements`]. Only the newest
+/// `entry_limit` times are kept.
+pub fn merge_table(tgph: &mut TGPH, axis: &str, table: Table) -> Result<(), String> {
+    let mut times: BTreeSet<u64> = BTreeSet::new();
+    let mut columns: Vec<(String, BTreeMap<u64, Value>)> = Vec::new();
+
+    if let Some(axis_container) = tgph.container(axis) {
+        let axis_times: Vec<u64> = (0..axis_container.elements.len())
+            .filter_map(|i| axis_container.elements.get_f64(i).map(|t| t as u64))
+            .collect();
+        times.extend(&axis_times);
+
+        for container in &tgph.containers {
+            if container.metadata.time_axis.as_deref() != Some(axis) {
+                continue;
+            }
+            let offset = tgph.axis_offset(container).unwrap_or_default();
+            let values = json_values(&container.elements);
+            let column = axis_times[offset..].iter().copied().zip(values).collect();
+            columns.push((container.name.clone(), column));
+        }
+    }
+
+    for (name, values) in table.columns {
+        if name == axis {
+            return Err(format!("\"{name}\" is the time axis"));
+        }
+        if let Some(other) = tgph
+            .metadata(&name)
+            .filter(|m| m.time_axis.as_deref() != Some(axis))
+        {
+            return Err(format!(
+                "\"{name}\" is on the time axis {:?}, not \"{axis}\"",
+                other.time_axis.as_deref().unwrap_or("none")
+            ));
+        }
+
+        times.extend(values.keys());
+        match columns.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, column)) => column.extend(values),
+            None => columns.push((name, values)),
+        }
+    }
+
+    let times: Vec<u64> = times.into_iter().collect();
+    let times = &times[times.len().saturating_sub(tgph.entry_limit)..];
+
+    let time_cells: Vec<Option<Value>> = times.iter().map(|&t| Some(t.into())).collect();
+    let floor = tgph.container(axis).map(|c| c.elements.element_type());
+    set_elements(
+        tgph,
+        axis,
+        build_elements(&time_cells, floor),
+        ContainerMetadata::new(MetricKind::Gauge, "s", "Collection time"),
+    );
+
+    for (name, column) in columns {
+        let cells: Vec<Option<Value>> = times.iter().map(|t| column.get(t).cloned()).collect();
+        let first = cells
+            .iter()
+            .position(Option::is_some)
+            .unwrap_or(cells.len());
+        let floor = tgph.container(&name).map(|c| c.elements.element_type());
+        set_elements(
+            tgph,
+            &name,
+            build_elements(&cells[first..], floor),
+            ContainerMetadata::default().with_time_axis(axis),
+        );
+    }
+
+    Ok(())
+}
+
+/// Imports the data of `args.input` into `args.file`, including the samples
+/// still waiting in its sample log, which is emptied afterwards.
+pub fn run(args: &ImportArgs) -> Result<(), std::io::Error> {
+    let invalid = |message: String| {
+        std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: {message}", args.input.display()),
+        )
+    };
+
+    let text = std::fs::read_to_string(&args.input)?;
+    let table = match args.format {
+        ImportFormat::Csv => parse_csv(&text, &args.timestamp_column),
+        ImportFormat::Influx => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            parse_influx(&text, args.precision, now.as_secs())
+        }
+    }
+    .map_err(invalid)?;
+
+    let mut tgph = match tinygraph::load_tgph(&args.file) {
+        Ok(tgph) => tgph,
+        Err(e) if e.kind() == ErrorKind::NotFound => TGPH::default(),
+        Err(e) => return Err(e),
+    };
+    tgph.entry_limit = args.entry_limit;
+    if args.compact_columns {
+        tgph.column_encoding = ColumnEncoding::Compact;
+    }
+
+    // The collector would drop a log that does not continue the new file.
+    let log_path = sample_log::log_path(&args.file);
+    let mut log = None;
+    if log_path.exists() {
+        let snapshot_checksum = std::fs::read(&args.file).map_or(0, |b| crc32c::crc32c(&b));
+        let (opened, samples) = SampleLog::open(&log_path, snapshot_checksum)?;
+        for sample in samples {
+            tgph.merge(sample)?;
+        }
+        log = Some(opened);
+    }
+
+    let time_axis = match &args.time_axis {
+        Some(axis) => axis.clone(),
+        None => default_time_axis(&tgph, &table).map_err(invalid)?,
+    };
+    merge_table(&mut tgph, &time_axis, table).map_err(invalid)?;
+
+    let compressed = tinygraph::compress_tgph(&tgph, CompressionLvl::default());
+    crate::save_snapshot(&compressed, &args.file, false, log.as_mut())
+}
+
+#[cfg(test)]
+mod parse {
+    use crate::import::*;
+
+    fn values(tgph: &TGPH, name: &str) -> Vec<Value> {
+        json_values(&tgph.container(name).unwrap().elements)
+    }
+
+    #[test]
+    fn csv() {
+        let table = parse_csv(
+            "timestamp,load,\"host, \"\"name\"\"\"\r\n100,0.5,pi\r\n160,,\"pi\n2\"\r\n",
+            "timestamp",
+        )
+        .unwrap();
+
+        assert_eq!(table.columns[0].0, "load");
+        assert_eq!(table.columns[0].1.len(), 1);
+        assert_eq!(table.columns[1].0, "host, \"name\"");
+        assert_eq!(table.columns[1].1[&160], "pi\n2");
+
+        assert!(parse_csv("time,load\n100,1\n", "timestamp").is_err());
+        assert!(parse_csv("timestamp,load\n100,1,2\n", "timestamp").is_err());
+        assert!(parse_csv("timestamp,load\nsoon,1\n", "timestamp").is_err());
+    }
+
+    #[test]
+    fn influx() {
+        let table = parse_influx(
+            "# comment\n\
+             cpu,host=pi\\ 4 usage_idle=97.5,cores=4i 1700000000000000000\n\
+             weather,place=home temp=21,ok=t,note=\"a \\\"b\\\", c\" 1700000060000000000\n\
+             cpu,host=pi\\ 4 usage_idle=95\n",
+            Precision::Ns,
+            1700000120,
+        )
+        .unwrap();
+        let column = |name: &str| &table.columns[table.index[name]].1;
+
+        assert_eq!(column("cpu,host=pi 4 usage_idle")[&1700000000], 97.5);
+        assert_eq!(column("cpu,host=pi 4 usage_idle")[&1700000120], 95.0);
+        assert_eq!(column("cpu,host=pi 4 cores")[&1700000000], 4);
+        assert_eq!(column("weather,place=home ok")[&1700000060], 1);
+        assert_eq!(column("weather,place=home note")[&1700000060], "a \"b\", c");
+
+        assert!(parse_influx("cpu", Precision::Ns, 0).is_err());
+        assert!(parse_influx("cpu usage=high 1", Precision::Ns, 0).is_err());
+    }
+
+    #[test]
+    fn types_are_inferred() {
+        let table = parse_csv(
+            "timestamp,count,big,negative,ratio,precise,name\n\
+             100,1,5000000000,-1,0.25,0.1234567891,a\n\
+             160,2,6000000000,3,0.5,1,b\n",
+            "timestamp",
+        )
+        .unwrap();
+        let mut tgph = TGPH::new(100);
+        merge_table(&mut tgph, "time", table).unwrap();
+
+        let types: Vec<&str> = tgph
+            .containers
+            .iter()
+            .map(|c| c.elements.element_type().name())
+            .collect();
+        assert_eq!(
+            types,
+            ["U32", "U32", "U64", "I64", "FLOAT32", "FLOAT64", "STRING"]
+        );
+        assert_eq!(
+            tgph.metadata("count").unwrap().time_axis.as_deref(),
+            Some("time")
+        );
+    }
+
+    #[test]
+    fn merging_keeps_containers_aligned() {
+        let mut tgph = TGPH::new(3);
+        for (time, used) in [(200_u32, 10_u32), (260, 11)] {
+            tgph.append(time, "time").unwrap();
+            tgph.append(used, "used").unwrap();
+        }
+        tgph.set_metadata("used", ContainerMetadata::default().with_time_axis("time"));
+        tgph.append("pi".to_string(), "Hostname").unwrap();
+
+        // Older samples of new containers, and a newer one of "used".
+        let table = parse_csv(
+            "timestamp,old,load,used\n100,1,0.5,\n140,2,0.5,\n260,,,12\n",
+            "timestamp",
+        )
+        .unwrap();
+        assert_eq!(default_time_axis(&tgph, &table).unwrap(), "time");
+        merge_table(&mut tgph, "time", table).unwrap();
+
+        // Only the newest 3 of the 4 times are kept, and gaps in integers
+        // repeat the previous value.
+        assert_eq!(values(&tgph, "time"), [140, 200, 260]);
+        assert_eq!(values(&tgph, "used"), [10, 12]);
+        assert_eq!(values(&tgph, "old"), [2, 2, 2]);
+        assert_eq!(values(&tgph, "load")[0], 0.5);
+        assert!(values(&tgph, "load")[1].is_null());
+
+        let table = parse_csv("timestamp,Hostname\n300,x\n", "timestamp").unwrap();
+        assert!(merge_table(&mut tgph, "time", table).is_err());
+        let table = parse_csv("timestamp,new\n300,1\n", "timestamp").unwrap();
+        assert_eq!(default_time_axis(&tgph, &table).unwrap(), IMPORT_TIME_AXIS);
+    }
+}
@@ -0,0 +1,257 @@
+use std::{io::Cursor, path::Path};
+
+use serde_json::{json, Value};
+
+use tinygraph::tgph_format::{ContainerLayout, TGPHContainer, TGPH};
+
+use crate::collectors::TIMESTAMP_CONTAINER;
+
+/// What `tinygraph inspect` shows about a container.
+#[derive(Debug, PartialEq)]
+pub struct ContainerSummary {
+    pub name: String,
+    pub element_type: &'static str,
+    pub count: usize,
+    /// Times of the oldest and newest element, from the time axis of the
+    /// container or the container itself if it is a time axis. Files
+    /// without time axes are read along the Unix timestamp.
+    pub first_time: Option<u64>,
+    pub last_time: Option<u64>,
+    /// Of the numbers that are not NaN, `None` for strings and empty
+    /// containers.
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    /// Size in the uncompressed file.
+    pub bytes: u64,
+}
+
+fn time_range(tgph: &TGPH, container: &TGPHContainer) -> Option<(u64, u64)> {
+    let has_axes = tgph
+        .containers
+        .iter()
+        .any(|c| c.metadata.time_axis.is_some());
+    let axis = match &container.metadata.time_axis {
+        Some(axis) => tgph.container(axis)?,
+        None if tgph
+            .containers
+            .iter()
+            .any(|c| c.metadata.time_axis.as_ref() == Some(&container.name)) =>
+        {
+            container
+        }
+        // Files from before format version 3 have no metadata, everything
+        // in them was collected along the Unix timestamp.
+        None if !has_axes => tgph.container(TIMESTAMP_CONTAINER)?,
+        None => return None,
+    };
+
+    let last = axis.elements.len().checked_sub(1)?;
+    let first = container.offset_in(axis);
+    let time_at = |index| axis.elements.get_f64(index).map(|t| t as u64);
+    Some((time_at(first)?, time_at(last)?))
+}
+
+pub fn summarize(tgph: &TGPH, layout: &[ContainerLayout]) -> Vec<ContainerSummary> {
+    tgph.containers
+        .iter()
+        .zip(layout)
+        .map(|(container, layout)| {
+            let elements = &container.elements;
+            let values: Vec<f64> = (0..elements.len())
+                .filter_map(|i| elements.get_f64(i))
+                .filter(|v| !v.is_nan())
+                .collect();
+            let (min, max, mean) = match values.is_empty() {
+                true => (None, None, None),
+                false => (
+                    Some(values.iter().copied().fold(f64::INFINITY, f64::min)),
+                    Some(values.iter().copied().fold(f64::NEG_INFINITY, f64::max)),
+                    Some(values.iter().sum::<f64>() / values.len() as f64),
+                ),
+            };
+            let times = time_range(tgph, container);
+
+            ContainerSummary {
+                name: container.name.clone(),
+                element_type: elements.element_type().name(),
+                count: elements.len(),
+                first_time: times.map(|(first, _)| first),
+                last_time: times.map(|(_, last)| last),
+                min,
+                max,
+                mean,
+                bytes: layout.length,
+            }
+        })
+        .collect()
+}
+
+fn to_json(
+    path: &Path,
+    compressed_bytes: usize,
+    uncompressed_bytes: usize,
+    tgph: &TGPH,
+    summaries: &[ContainerSummary],
+) -> Value {
+    let containers: Vec<Value> = summaries
+        .iter()
+        .map(|s| {
+            json!({
+                "name": s.name,
+                "type": s.element_type,
+                "count": s.count,
+                "first_time": s.first_time,
+                "last_time": s.last_time,
+                "min": s.min,
+                "max": s.max,
+                "mean": s.mean,
+                "bytes": s.bytes,
+            })
+        })
+        .collect();
+
+    json!({
+        "path": path.display().to_string(),
+        "version": tgph.version(),
+        "compressed_bytes": compressed_bytes,
+        "uncompressed_bytes": uncompressed_bytes,
+        "containers": containers,
+    })
+}
+
+fn print_table(summaries: &[ContainerSummary]) {
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    let number = |value: Option<f64>| optional(value.map(|v| format!("{v:.2}")));
+
+    let header = [
+        "NAME", "TYPE", "COUNT", "FIRST", "LAST", "MIN", "MAX", "MEAN", "BYTES",
+    ]
+    .map(String::from);
+    let mut rows = vec![header];
+    for s in summaries {
+        rows.push([
+            s.name.clone(),
+            s.element_type.to_string(),
+            s.count.to_string(),
+            optional(s.first_time.map(|t| t.to_string())),
+            optional(s.last_time.map(|t| t.to_string())),
+            number(s.min),
+            number(s.max),
+            number(s.mean),
+            s.bytes.to_string(),
+        ]);
+    }
+
+    let mut widths = [0; 9];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in &rows {
+        // The name is left aligned, everything else is a number.
+        let mut line = format!("{:<1$}", row[0], widths[0]);
+        for (cell, width) in row.iter().zip(widths).skip(1) {
+            line.push_str(&format!("  {cell:>width$}"));
+        }
+        println!("{line}");
+    }
+}
+
+/// Prints what the file at `path` contains, as a table or as JSON.
+pub fn run(path: &Path, json: bool) -> Result<(), std::io::Error> {
+    let compressed = std::fs::read(path)?;
+    let decompressed = tinygraph::decompress(&mut compressed.as_slice())?;
+    let (tgph, layout) = TGPH::deserialize_with_layout_from(&mut Cursor::new(&decompressed))?;
+    let summaries = summarize(&tgph, &layout);
+
+    if json {
+        let json = to_json(
+            path,
+            compressed.len(),
+            decompressed.len(),
+            &tgph,
+            &summaries,
+        );
+        println!("{json:#}");
+        return Ok(());
+    }
+
+    println!(
+        "{}: format version {}, {} containers, {} bytes ({} uncompressed)\n",
+        path.display(),
+        tgph.version(),
+        tgph.containers.len(),
+        compressed.len(),
+        decompressed.len()
+    );
+    print_table(&summaries);
+    Ok(())
+}
+
+#[cfg(test)]
+mod summary {
+    use crate::inspect::*;
+    use tinygraph::tgph_format::{ContainerMetadata, MetricKind};
+
+    #[test]
+    fn containers_are_summarized() {
+        let mut tgph = TGPH::new(100);
+        for time in [100_u32, 160, 220] {
+            tgph.append(time, "time").unwrap();
+        }
+        for load in [1.0_f32, f32::NAN] {
+            tgph.append(load, "CPU load").unwrap();
+        }
+        tgph.set_metadata(
+            "CPU load",
+            ContainerMetadata::new(MetricKind::Gauge, "", "").with_time_axis("time"),
+        );
+        tgph.append("pi".to_string(), "Hostname").unwrap();
+
+        let mut bytes = Vec::new();
+        tgph.serialize_into(&mut bytes).unwrap();
+        let (tgph, layout) = TGPH::deserialize_with_layout_from(&mut Cursor::new(&bytes)).unwrap();
+        let summaries = summarize(&tgph, &layout);
+
+        assert_eq!(summaries[0].first_time, Some(100));
+        assert_eq!(summaries[0].last_time, Some(220));
+        assert_eq!(summaries[0].mean, Some(160.0));
+
+        assert_eq!(summaries[1].element_type, "FLOAT32");
+        assert_eq!(summaries[1].count, 2);
+        assert_eq!(summaries[1].first_time, Some(160));
+        assert_eq!(summaries[1].last_time, Some(220));
+        assert_eq!(summaries[1].max, Some(1.0));
+
+        assert_eq!(summaries[2].first_time, None);
+        assert_eq!(summaries[2].min, None);
+
+        // Magic, version and container count come before the containers.
+        let total: u64 = summaries.iter().map(|s| s.bytes).sum();
+        assert_eq!(total + 7, bytes.len() as u64);
+        assert_eq!(layout[1].offset, 7 + layout[0].length);
+    }
+
+    #[test]
+    fn files_without_metadata_use_the_unix_timestamp() {
+        let mut tgph = TGPH::new(100);
+        for time in [100_u32, 160, 220] {
+            tgph.append(time, TIMESTAMP_CONTAINER).unwrap();
+        }
+        for load in [1.0_f32, 2.0] {
+            tgph.append(load, "CPU load").unwrap();
+        }
+
+        let mut bytes = Vec::new();
+        tgph.serialize_into(&mut bytes).unwrap();
+        let (tgph, layout) = TGPH::deserialize_with_layout_from(&mut Cursor::new(&bytes)).unwrap();
+        assert!(tgph.version() < 3);
+        let summaries = summarize(&tgph, &layout);
+
+        assert_eq!(summaries[0].first_time, Some(100));
+        assert_eq!(summaries[1].first_time, Some(160));
+        assert_eq!(summaries[1].last_time, Some(220));
+    }
+}
@@ -0,0 +1,24 @@
+//! Reading and writing the TGPH files tinygraph collects into, see the
+//! format description in the README.
+//!
+//! ```no_run
+//! let tgph = tinygraph::load_tgph("data.tgph.gz".as_ref())?;
+//! if let Some(load) = tgph.container("CPU load").and_then(|c| c.elements.as_f32()) {
+//!     println!("{} samples, newest {:?}", load.len(), load.back());
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+mod column_encoding;
+pub mod gzip;
+pub mod stream;
+pub mod tgph_format;
+pub mod view;
+
+pub use gzip::{compress, compress_tgph, decompress, load_tgph, CompressionLvl};
+pub use stream::StreamDecoder;
+pub use tgph_format::{
+    ColumnEncoding, ContainerCheck, ContainerLayout, ContainerMetadata, ElementArrayType,
+    ElementType, IndexEntry, MetricKind, Salvage, TGPHContainer, TgphError, TGPH,
+};
+pub use view::TgphView;
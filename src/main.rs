@@ -1,233 +1,553 @@
 use std::{
-    fs::File,
-    io::{Cursor, Read},
-    net::{TcpStream, SocketAddr},
+    io::ErrorKind,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
     thread::sleep,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use byteorder::{LittleEndian, ReadBytesExt};
-use sysinfo::{ComponentExt, CpuExt, DiskExt, NetworkExt, System, SystemExt};
-
 use std::io::{stdout, Write};
 
-use clap::Parser;
-
-use libdeflater::{CompressionLvl, Compressor, Decompressor};
-
-mod tgph_format;
-use tgph_format::TGPH;
+use clap::{Parser, Subcommand};
+
+use tinygraph::CompressionLvl;
+
+mod co2_sensor;
+mod collectors;
+mod config;
+mod export;
+mod fsck;
+mod http;
+mod import;
+mod inspect;
+mod query;
+mod rollup;
+mod sample_log;
+mod storage;
+#[cfg(test)]
+mod testing;
+use co2_sensor::Co2Sensor;
+use collectors::{
+    migrate_disk_containers, Co2SensorCollector, Collector, CpuCollector, DiskCollector,
+    HostInfoCollector, MemoryCollector, NetworkCollector, TemperatureCollector, COLLECTOR_NAMES,
+    TIMESTAMP_CONTAINER,
+};
+use config::{CollectorsConfig, Config};
+use export::ExportFormat;
+use http::{Dashboard, HttpServer};
+use import::ImportArgs;
+use query::Query;
+use sample_log::SampleLog;
+use tinygraph::tgph_format::{
+    ColumnEncoding, ContainerMetadata, ElementType, MetricKind, TypeMismatchPolicy, TGPH,
+};
+use tinygraph::{compress_tgph, decompress};
 
 /// Gather data about system state
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-struct Args {
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Without a subcommand the arguments of `collect` are accepted as well
+    #[command(flatten)]
+    collect: CollectArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Gather data about system state, the default
+    Collect(Box<CollectArgs>),
+
+    /// Print the format version and a summary of every container of a file
+    Inspect {
+        /// The .tgph.gz file to inspect
+        file: PathBuf,
+
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Write the data of a file as CSV, JSON or NDJSON
+    Export {
+        /// The .tgph.gz file to export
+        file: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+
+        /// Only export containers with this in their name, can be given
+        /// multiple times [default: all]
+        #[arg(long = "match")]
+        patterns: Vec<String>,
+
+        /// First time to export, in seconds since the Unix epoch
+        #[arg(long)]
+        from: Option<u64>,
+
+        /// Last time to export, in seconds since the Unix epoch
+        #[arg(long)]
+        to: Option<u64>,
+
+        /// Only export the newest sample of every this many seconds
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        step: Option<u64>,
+
+        /// File to write to [default: stdout]
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+
+    /// Add the data of a CSV or InfluxDB line protocol file to a file
+    Import(ImportArgs),
+
+    /// Check the checksums and structure of a file, reporting the damaged
+    /// containers
+    Fsck {
+        /// The .tgph.gz file to check
+        file: PathBuf,
+
+        /// Write the intact containers to this file
+        #[arg(long)]
+        salvage: Option<PathBuf>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct CollectArgs {
     /// Where to save the data, if file already exists start appending
-    output_path: String,
+    output_path: Option<String>,
 
     /// How many entries per container are allowed
-    entry_limit: usize,
+    entry_limit: Option<usize>,
 
     /// How many seconds between each system state read
-    timeout_period: u64,
+    timeout_period: Option<u64>,
+
+    /// TOML file to read the configuration from, arguments given on the
+    /// command line override values from the file, switches with e.g.
+    /// --keep-backup=false
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Convert a container in place (e.g. U32 to FLOAT32) when a value of
+    /// another numeric type is appended to it, instead of dropping the value
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    widen_mismatched_types: Option<bool>,
+
+    /// Keep the previously saved file next to the output as "<output>.bak"
+    /// and load it on startup if the output cannot be read
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    keep_backup: Option<bool>,
+
+    /// Store numeric containers delta-of-delta and XOR encoded instead of as
+    /// plain values, only readable since format version 4
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    compact_columns: Option<bool>,
+
+    /// Write an index of the containers at the end of the output, so that
+    /// readers can seek to a single container, for readers since format
+    /// version 6
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    container_index: Option<bool>,
+
+    /// Store a CRC32C of every container and of the whole output, checked
+    /// when it is read, for readers since format version 5
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    checksums: Option<bool>,
+
+    /// Append every sample to "<output>.log" and only rewrite the output
+    /// every compaction period, instead of rewriting it every period
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    append_log: Option<bool>,
+
+    /// How many seconds between rewrites of the output with --append-log
+    /// [default: 3600]
+    #[arg(long)]
+    compaction_period: Option<u64>,
+
+    /// How many seconds to keep samples for, on top of the entry limit
+    #[arg(long)]
+    max_age: Option<u64>,
+
+    /// Address to serve the dashboard and the current data on, e.g.
+    /// 0.0.0.0:8080
+    #[arg(long)]
+    listen: Option<SocketAddr>,
+
+    /// Compression level of the output file, from 0 to 12
+    #[arg(long)]
+    compression_level: Option<i32>,
+
+    /// Which collectors to run, as a comma separated list [default: all]
+    #[arg(long, value_delimiter = ',')]
+    collectors: Option<Vec<String>>,
+
+    /// Address of the CO2 sensor to read [default: 192.168.1.15:6969]
+    #[arg(long)]
+    co2_sensor_address: Option<SocketAddr>,
+
+    /// How many seconds to wait for the CO2 sensor to connect and answer
+    /// [default: 10]
+    #[arg(long)]
+    co2_sensor_timeout: Option<u64>,
+
+    /// Longest time in seconds to wait before retrying a failing CO2 sensor
+    /// [default: 300]
+    #[arg(long)]
+    co2_sensor_max_backoff: Option<u64>,
 }
 
-fn decompress<R: Read>(stream: &mut R) -> Result<Vec<u8>, std::io::Error> {
-    let mut gz_data = Vec::new();
-    stream.read_to_end(&mut gz_data)?;
-
-    if gz_data.len() < 10 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "gz data is too short (for magic bytes + footer",
-        ));
+impl CollectArgs {
+    fn into_config(self) -> Result<Config, std::io::Error> {
+        let config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        Ok(self.override_config(config))
     }
 
-    let isize = {
-        let isize_start = gz_data.len() - 4;
-        let isize_bytes = &gz_data[isize_start..];
-        let mut ret: u32 = isize_bytes[0] as u32;
-        ret |= (isize_bytes[1] as u32) << 8;
-        ret |= (isize_bytes[2] as u32) << 16;
-        ret |= (isize_bytes[3] as u32) << 24;
-        ret as usize
-    };
+    /// `config` with everything given on the command line replaced.
+    fn override_config(self, mut config: Config) -> Config {
+        config.output_path = self.output_path.or(config.output_path);
+        config.entry_limit = self.entry_limit.or(config.entry_limit);
+        config.period = self.timeout_period.or(config.period);
+        config.keep_backup = self.keep_backup.unwrap_or(config.keep_backup);
+        config.widen_mismatched_types = self
+            .widen_mismatched_types
+            .unwrap_or(config.widen_mismatched_types);
+        config.compact_columns = self.compact_columns.unwrap_or(config.compact_columns);
+        config.container_index = self.container_index.unwrap_or(config.container_index);
+        config.checksums = self.checksums.unwrap_or(config.checksums);
+        config.append_log = self.append_log.unwrap_or(config.append_log);
+        config.compaction_period = self.compaction_period.or(config.compaction_period);
+        config.compression_level = self.compression_level.or(config.compression_level);
+        if let Some(max_age) = self.max_age {
+            config.retention.max_age = max_age;
+        }
+        config.http.listen = self.listen.or(config.http.listen);
 
-    let mut decompressor = Decompressor::new();
-    let mut outbuf = Vec::new();
-    outbuf.resize(isize, 0);
-    decompressor.gzip_decompress(&gz_data, &mut outbuf).unwrap();
-    Ok(outbuf)
-}
+        let collectors = &mut config.collectors;
+        if let Some(enabled) = self.collectors {
+            collectors.enabled = enabled;
+        }
+        if let Some(address) = self.co2_sensor_address {
+            collectors.co2.address = address;
+        }
+        if let Some(timeout) = self.co2_sensor_timeout {
+            collectors.co2.timeout = timeout;
+        }
+        if let Some(max_backoff) = self.co2_sensor_max_backoff {
+            collectors.co2.max_backoff = max_backoff;
+        }
 
-struct CO2Readings {
-    timings: Vec<u32>,
-    readings: Vec<u32>,
+        config
+    }
 }
 
-fn fetch_co2_data_from_sensor() -> Result<CO2Readings, std::io::Error> {
-    let address: SocketAddr = "192.168.1.15:6969".parse().map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-    let mut stream = TcpStream::connect_timeout(&address, Duration::from_secs(10))?;
+fn missing_setting(name: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("No {name} given on the command line or in the config file"),
+    )
+}
 
-    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+/// Reads the .tgph.gz file at `path`, also returning its compressed bytes.
+fn load_tgph_with_bytes(path: &Path) -> Result<(TGPH, Vec<u8>), std::io::Error> {
+    let bytes = std::fs::read(path)?;
+    let tgph = TGPH::deserialize_from(&mut decompress(&mut bytes.as_slice())?.as_slice())?;
+    Ok((tgph, bytes))
+}
 
-    let mut buffer: Vec<u8> = Default::default();
-    let _bytes_read = stream.read_to_end(&mut buffer)?;
-    let mut cursor = Cursor::new(buffer);
+/// Loads the saved data, falling back to the backup generation when the main
+/// file is missing or damaged. Returns `None` if neither exists, and the
+/// compressed bytes of whichever file was loaded otherwise.
+fn load_tgph_or_backup(path: &Path) -> Result<Option<(TGPH, Vec<u8>)>, std::io::Error> {
+    let err = match load_tgph_with_bytes(path) {
+        Ok(loaded) => return Ok(Some(loaded)),
+        Err(err) => err,
+    };
 
-    let latest_time = cursor.read_u64::<LittleEndian>()?;
-    let read_interval = cursor.read_u64::<LittleEndian>()?;
-    let reading_count = cursor.read_u16::<LittleEndian>()?;
+    let backup = storage::backup_path(path);
+    match load_tgph_with_bytes(&backup) {
+        Ok(loaded) => {
+            eprintln!(
+                "Failed to load {}: {err}, using {} instead",
+                path.display(),
+                backup.display()
+            );
+            Ok(Some(loaded))
+        }
+        Err(_) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(_) => Err(err),
+    }
+}
 
-    let mut timings: Vec<u32> = Default::default();
-    let mut readings: Vec<u32> = Default::default();
-    for i in 0..reading_count {
-        timings.push((latest_time - read_interval * (reading_count - i - 1) as u64) as u32);
-        readings.push(cursor.read_u16::<LittleEndian>()? as u32);
+fn build_collectors(config: &CollectorsConfig) -> Result<Vec<Box<dyn Collector>>, std::io::Error> {
+    let mut collectors: Vec<Box<dyn Collector>> = Vec::new();
+
+    for name in &config.enabled {
+        let collector: Box<dyn Collector> = match name.as_str() {
+            "disk" => Box::new(DiskCollector::new(config.disk.clone())),
+            "network" => Box::new(NetworkCollector::new(config.network.clone())),
+            "temperature" => Box::new(TemperatureCollector::new()),
+            "cpu" => Box::new(CpuCollector::new()),
+            "memory" => Box::new(MemoryCollector::new()),
+            "host" => Box::new(HostInfoCollector::new()),
+            "co2" => Box::new(Co2SensorCollector::new(Co2Sensor::new(
+                config.co2.address,
+                Duration::from_secs(config.co2.timeout),
+                Duration::from_secs(config.co2.max_backoff),
+            ))),
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Unknown collector \"{name}\", expected one of: {}",
+                        COLLECTOR_NAMES.join(", ")
+                    ),
+                ))
+            }
+        };
+        collectors.push(collector);
     }
 
-    Ok(CO2Readings { timings, readings })
+    Ok(collectors)
 }
 
 fn main() -> Result<(), std::io::Error> {
-    let args = Args::parse();
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Collect(args)) => collect(args.into_config()?),
+        Some(Command::Inspect { file, json }) => inspect::run(&file, json),
+        Some(Command::Export {
+            file,
+            format,
+            patterns,
+            from,
+            to,
+            step,
+            output,
+        }) => {
+            let query = Query {
+                patterns,
+                from,
+                to,
+                step,
+                ..Query::default()
+            };
+            export::run(&file, format, &query, output.as_deref())
+        }
+        Some(Command::Import(args)) => import::run(&args),
+        Some(Command::Fsck { file, salvage }) => fsck::run(&file, salvage.as_deref()),
+        None => collect(cli.collect.into_config()?),
+    }
+}
+
+fn collect(config: Config) -> Result<(), std::io::Error> {
+    let output_path = config
+        .output_path
+        .as_deref()
+        .ok_or_else(|| missing_setting("output path"))?;
+    let entry_limit = config
+        .entry_limit
+        .ok_or_else(|| missing_setting("entry limit"))?;
+    let period = config.period.ok_or_else(|| missing_setting("period"))?;
+    let compression_level = match config.compression_level {
+        Some(level) => CompressionLvl::new(level).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid compression level {level}, expected 0 to 12"),
+            )
+        })?,
+        None => CompressionLvl::default(),
+    };
 
-    let mut sys = System::new_all();
+    let mut collectors = build_collectors(&config.collectors)?;
     let mut stdout = stdout();
 
-    if !args.output_path.ends_with(".gz") {
+    if !output_path.ends_with(".gz") {
         return Err(std::io::Error::new(
             std::io::ErrorKind::InvalidData,
             "Expected the output file to be a gzip file with \".gz\" ending",
         ));
     }
 
-    let mut tgph = match File::open(args.output_path.clone()) {
-        Ok(mut compressed_file) => {
-            let decompressed = decompress(&mut compressed_file).unwrap();
-            let mut cursor = Cursor::new(decompressed);
+    let output_path = Path::new(output_path);
 
-            let mut res = TGPH::deserialize_from(&mut cursor).unwrap();
-            res.entry_limit = args.entry_limit;
-            res
+    // The log only continues the snapshot it was started on, see
+    // `SampleLog`, which is the one loaded even if it is the backup.
+    let (mut tgph, snapshot_checksum) = match load_tgph_or_backup(output_path)? {
+        Some((mut res, bytes)) => {
+            res.entry_limit = entry_limit;
+            (res, crc32c::crc32c(&bytes))
         }
-        Err(_) => TGPH::new(args.entry_limit),
+        None => (TGPH::new(entry_limit), 0),
     };
 
-    let mut points_saved = if tgph.containers.len() == 0 {
-        0
+    if config.widen_mismatched_types {
+        tgph.type_mismatch_policy = TypeMismatchPolicy::Widen;
+    }
+    // Timestamps were stored as U32 before they became U64.
+    if let Err(e) = tgph.widen(TIMESTAMP_CONTAINER, ElementType::U64) {
+        eprintln!("Failed to widen {TIMESTAMP_CONTAINER}: {e}");
+    }
+    migrate_disk_containers(&mut tgph);
+    if config.compact_columns {
+        tgph.column_encoding = ColumnEncoding::Compact;
+    }
+    tgph.container_index = config.container_index;
+    tgph.checksums = config.checksums;
+
+    let mut log = if config.append_log {
+        let (log, samples) =
+            SampleLog::open(&sample_log::log_path(output_path), snapshot_checksum)?;
+        for mut sample in samples {
+            migrate_disk_containers(&mut sample);
+            let widened = sample.widen(TIMESTAMP_CONTAINER, ElementType::U64);
+            if let Err(e) = widened.and_then(|_| tgph.merge(sample)) {
+                eprintln!("Failed to replay logged sample: {e}");
+            }
+        }
+        Some(log)
     } else {
-        match &tgph.containers[0].elements {
-            tgph_format::ElementArrayType::U32(v) => v.len(),
-            tgph_format::ElementArrayType::FLOAT32(v) => v.len(),
-            tgph_format::ElementArrayType::STRING(v) => v.len(),
+        None
+    };
+    let retention = config.retention();
+    if config.rollups.iter().any(|r| r.interval == 0) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Rollup intervals have to be at least 1 second",
+        ));
+    }
+    let rollup_tiers: Vec<_> = config.rollups.iter().map(|r| r.tier()).collect();
+    let compaction_period = Duration::from_secs(config.compaction_period.unwrap_or(3600));
+    // Compact right away, so the replayed samples leave the log and the
+    // output exists from the start.
+    let mut last_compaction: Option<Instant> = None;
+
+    let mut points_saved = tgph.containers.first().map_or(0, |c| c.elements.len());
+    // Shared with the dashboard, which answers requests from it between
+    // periods.
+    let data = Arc::new(RwLock::new(tgph));
+
+    let dashboard = match config.http.listen {
+        Some(address) => {
+            let dashboard = Arc::new(Dashboard::new(data.clone(), compression_level));
+            let server = HttpServer::bind(address, dashboard.clone())?;
+            println!("Serving the dashboard on http://{}", server.local_addr()?);
+            server.spawn();
+            Some(dashboard)
         }
+        None => None,
     };
 
     loop {
-        sys.refresh_all();
-
-        tgph.append(
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as u32,
-            "Unix timestamp",
+        let mut sample = TGPH::new(entry_limit);
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        if let Err(e) = sample.append(timestamp, TIMESTAMP_CONTAINER) {
+            eprintln!("\nFailed to record timestamp: {e}");
+        }
+        sample.set_metadata(
+            TIMESTAMP_CONTAINER,
+            ContainerMetadata::new(MetricKind::Gauge, "s", "Collection time"),
         );
 
-        for disk in sys.disks() {
-            tgph.append(
-                (disk.total_space() / 1024 / 1024 / 1024) as u32,
-                &format!("Disk {} Total Space [GB]", disk.name().to_str().unwrap()),
-            );
-            tgph.append(
-                (disk.available_space() / 1024 / 1024 / 1024) as u32,
-                &format!(
-                    "Disk {} Available Space [GB]",
-                    disk.name().to_str().unwrap()
-                ),
-            );
+        for collector in collectors.iter_mut() {
+            if let Err(e) = collector.collect(&mut sample) {
+                eprintln!("\nCollector {} failed: {e}", collector.name());
+            }
         }
 
-        for (interface_name, data) in sys.networks() {
-            tgph.append(
-                data.received() as u32,
-                &format!("Interface {} Received [bytes]", interface_name),
-            );
-            tgph.append(
-                data.transmitted() as u32,
-                &format!("Interface {} Transmitted [bytes]", interface_name),
-            );
+        // A sample that did not make it into the log is only safe once the
+        // whole file is saved.
+        let mut log_failed = false;
+        if let Some(log) = &mut log {
+            if let Err(e) = log.append(&sample) {
+                eprintln!("\nFailed to log sample, saving the output instead: {e}");
+                log_failed = true;
+            }
         }
-
-        for component in sys.components() {
-            tgph.append(
-                component.temperature(),
-                &format!("{} Temperature [C]", component.label()),
-            );
+        if let Some(dashboard) = &dashboard {
+            dashboard.broadcast(&sample);
         }
-
-        tgph.append(sys.cpus().len() as u32, "CPU Count");
-
-        for (i, cpu) in sys.cpus().iter().enumerate() {
-            tgph.append(cpu.cpu_usage(), &format!("CPU {} Usage [%]", i))
+        {
+            let mut tgph = data.write().unwrap();
+            if let Err(e) = tgph.merge(sample) {
+                eprintln!("\nFailed to store sample: {e}");
+            }
+            for tier in &rollup_tiers {
+                if let Err(e) = tier.update(&mut tgph) {
+                    eprintln!("\nFailed to update {}s rollups: {e}", tier.interval);
+                }
+            }
+            tgph.apply_retention(&retention, timestamp);
         }
+        points_saved += 1;
 
-        tgph.append(
-            (sys.total_memory() / 1024 / 1024) as u32,
-            "Total memory [MB]",
-        );
-        tgph.append((sys.used_memory() / 1024 / 1024) as u32, "Used memory [MB]");
-        tgph.append((sys.total_swap() / 1024 / 1024) as u32, "Total swap [MB]");
-        tgph.append((sys.used_swap() / 1024 / 1024) as u32, "Used swap [MB]");
-
-        tgph.append(
-            sys.kernel_version()
-                .unwrap_or("UNDEFINED".to_string())
-                .to_string(),
-            "Kernel Version",
-        );
-        tgph.append(
-            sys.os_version()
-                .unwrap_or("UNDEFINED".to_string())
-                .to_string(),
-            "OS Version",
-        );
-        tgph.append(
-            sys.host_name()
-                .unwrap_or("UNDEFINED".to_string())
-                .to_string(),
-            "Hostname",
-        );
+        let compaction_due = last_compaction.is_none_or(|t| t.elapsed() >= compaction_period);
+        let save_due = log.is_none() || compaction_due || log_failed;
+        let compressed = save_due.then(|| compress_tgph(&data.read().unwrap(), compression_level));
+        if let Some(compressed) = &compressed {
+            save_snapshot(compressed, output_path, config.keep_backup, log.as_mut())?;
+            last_compaction = Some(Instant::now());
+        }
+        // The server always gets the latest data, even if the file on disk
+        // lags behind the log. It only compresses it when asked for it.
+        if let Some(dashboard) = &dashboard {
+            dashboard.publish(compressed);
+        }
 
-        let co2_data = fetch_co2_data_from_sensor()?;
+        print!("\rSaved {points_saved} snapshots");
+        stdout.flush().unwrap();
 
-        tgph.replace(co2_data.timings, "Unix timestamp CO2");
-        tgph.replace(co2_data.readings, "CO2 Concentration [ppm]");
+        sleep(Duration::from_secs(period));
+    }
+}
 
-        let mut output_buffer = Vec::new();
-        tgph.serialize_into(&mut output_buffer).unwrap();
+/// Writes the compressed TGPH to `path`, emptying the log of samples it now
+/// contains.
+fn save_snapshot(
+    compressed_data: &[u8],
+    path: &Path,
+    keep_backup: bool,
+    log: Option<&mut SampleLog>,
+) -> Result<(), std::io::Error> {
+    storage::write_atomically(path, compressed_data, keep_backup)?;
+
+    if let Some(log) = log {
+        log.reset(crc32c::crc32c(compressed_data))?;
+    }
+    Ok(())
+}
 
-        let compressed_data = {
-            let mut compressor = Compressor::new(CompressionLvl::default());
-            let max_sz = compressor.gzip_compress_bound(output_buffer.len());
-            let mut compressed_data = Vec::new();
-            compressed_data.resize(max_sz, 0);
-            let actual_sz = compressor
-                .gzip_compress(&output_buffer, &mut compressed_data)
-                .unwrap();
-            compressed_data.resize(actual_sz, 0);
-            compressed_data
-        };
+#[cfg(test)]
+mod arguments {
+    use crate::*;
 
-        let mut output_file = File::create(args.output_path.clone())?;
-        output_file.write_all(&compressed_data)?;
+    fn config(args: &[&str], file: &str) -> Config {
+        let cli = Cli::try_parse_from([&["tinygraph"], args].concat()).unwrap();
+        cli.collect.override_config(Config::parse(file).unwrap())
+    }
 
-        points_saved += 1;
+    #[test]
+    fn switches_override_the_file() {
+        let file = "keep_backup = true\nappend_log = true\n";
 
-        print!("\rSaved {points_saved} snapshots");
-        stdout.flush().unwrap();
+        let kept = config(&[], file);
+        assert!(kept.keep_backup && kept.append_log && !kept.checksums);
 
-        sleep(Duration::from_secs(args.timeout_period));
+        let overridden = config(&["--keep-backup=false", "--checksums"], file);
+        assert!(!overridden.keep_backup);
+        assert!(overridden.append_log);
+        assert!(overridden.checksums);
     }
 }
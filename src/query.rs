@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use tinygraph::tgph_format::{ElementArrayType, TGPHContainer, TGPH};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QueryFormat {
+    /// A gzipped TGPH, as read by the dashboard.
+    #[default]
+    Tgph,
+    Json,
+}
+
+/// A subset of a TGPH, as requested with
+/// `/api/query?match=CPU&from=1700000000&to=1700086400&step=300`.
+///
+/// Containers are selected by a part of their name, every `match` adds to
+/// the selection and none selects everything. The time axes of the selected
+/// containers are always included, as nothing can be graphed without them.
+///
+/// `from` and `to` (inclusive, seconds since the Unix epoch) and `step`
+/// apply through the time axis of every container, and to the time axes
+/// themselves. With `step` only the newest sample of every `step` seconds is
+/// kept. Other containers without a time axis are returned whole.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Query {
+    pub patterns: Vec<String>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub step: Option<u64>,
+    pub format: QueryFormat,
+}
+
+impl Query {
+    /// Parses the query string of a URL, without the leading `?`.
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let mut result = Self::default();
+
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value =
+                percent_decode(value).ok_or_else(|| format!("Invalid escape in \"{key}\""))?;
+            let number = || {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format!("Expected a number for \"{key}\", got \"{value}\""))
+            };
+
+            match key {
+                "match" => result.patterns.push(value.clone()),
+                "from" => result.from = Some(number()?),
+                "to" => result.to = Some(number()?),
+                "step" => match number()? {
+                    0 => return Err("\"step\" has to be at least 1 second".to_string()),
+                    step => result.step = Some(step),
+                },
+                "format" => {
+                    result.format = match value.as_str() {
+                        "tgph" => QueryFormat::Tgph,
+                        "json" => QueryFormat::Json,
+                        _ => {
+                            return Err(format!(
+                                "Unknown format \"{value}\", expected \"tgph\" or \"json\""
+                            ))
+                        }
+                    }
+                }
+                _ => return Err(format!("Unknown query parameter \"{key}\"")),
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.patterns.is_empty() || self.patterns.iter().any(|p| name.contains(p.as_str()))
+    }
+
+    /// Indices of the elements of the time axis `times` to keep.
+    fn selected_times(&self, times: &ElementArrayType) -> Vec<usize> {
+        let mut indices = Vec::new();
+        let mut last_step = None;
+
+        for index in 0..times.len() {
+            let time = times.get_f64(index).unwrap_or_default() as u64;
+            if self.from.is_some_and(|from| time < from) || self.to.is_some_and(|to| time > to) {
+                continue;
+            }
+            if let Some(step) = self.step {
+                // Times only grow, so a later sample of the same step is
+                // always the newer one.
+                if last_step.replace(time / step) == Some(time / step) {
+                    indices.pop();
+                }
+            }
+            indices.push(index);
+        }
+
+        indices
+    }
+
+    pub fn apply(&self, tgph: &TGPH) -> TGPH {
+        let is_axis = |name: &str| {
+            tgph.containers
+                .iter()
+                .any(|c| c.metadata.time_axis.as_deref() == Some(name))
+        };
+        let mut axes: HashMap<&str, Vec<usize>> = HashMap::new();
+        for container in tgph.containers.iter().filter(|c| self.matches(&c.name)) {
+            // A matched time axis is sliced like the containers on it, even
+            // if none of them matched.
+            let axis_name = match container.metadata.time_axis.as_deref() {
+                Some(axis_name) => axis_name,
+                None if is_axis(&container.name) => container.name.as_str(),
+                None => continue,
+            };
+            if let Some(axis) = tgph.container(axis_name) {
+                axes.entry(axis_name)
+                    .or_insert_with(|| self.selected_times(&axis.elements));
+            }
+        }
+
+        let mut result = TGPH::new(tgph.entry_limit);
+        // Answered in the encoding of the data, so it is as readable as
+        // the stored file.
+        result.column_encoding = tgph.column_encoding;
+        for container in &tgph.containers {
+            let elements = if let Some(indices) = axes.get(container.name.as_str()) {
+                container.elements.select(indices)
+            } else if !self.matches(&container.name) {
+                continue;
+            } else if let (Some(indices), Some(offset)) = (
+                container
+                    .metadata
+                    .time_axis
+                    .as_deref()
+                    .and_then(|axis| axes.get(axis)),
+                tgph.axis_offset(container),
+            ) {
+                let indices: Vec<usize> = indices
+                    .iter()
+                    .filter(|&&i| i >= offset)
+                    .map(|&i| i - offset)
+                    .collect();
+                container.elements.select(&indices)
+            } else {
+                container.elements.clone()
+            };
+
+            result.add_container(TGPHContainer {
+                name: container.name.clone(),
+                elements,
+                metadata: container.metadata.clone(),
+            });
+        }
+
+        result
+    }
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        bytes.push(match byte {
+            b'+' => b' ',
+            b'%' => {
+                let hex = [input.next()?, input.next()?];
+                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            _ => byte,
+        });
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// The elements as JSON values. Not a number and infinities have no JSON
+/// representation and become null.
+pub fn json_values(elements: &ElementArrayType) -> Vec<Value> {
+    match elements {
+        ElementArrayType::U32(arr) => arr.iter().map(|&e| e.into()).collect(),
+        ElementArrayType::U64(arr) => arr.iter().map(|&e| e.into()).collect(),
+        ElementArrayType::I64(arr) => arr.iter().map(|&e| e.into()).collect(),
+        // Going through the shortest decimal form keeps 0.1 from turning
+        // into 0.10000000149011612.
+        ElementArrayType::FLOAT32(arr) => arr
+            .iter()
+            .map(|e| e.to_string().parse::<f64>().unwrap().into())
+            .collect(),
+        ElementArrayType::FLOAT64(arr) => arr.iter().map(|&e| e.into()).collect(),
+        ElementArrayType::STRING(arr) => arr.iter().map(|e| e.as_str().into()).collect(),
+        other => (0..other.len())
+            .map(|i| other.get_f64(i).map_or(Value::Null, Value::from))
+            .collect(),
+    }
+}
+
+/// The containers of `tgph` as a JSON document of the form
+/// `{"containers": [{"name": ..., "type": "U32", "kind": "gauge", "unit": ...,
+/// "label": ..., "time_axis": ..., "values": [...]}]}`.
+pub fn to_json(tgph: &TGPH) -> Value {
+    let containers: Vec<Value> = tgph
+        .containers
+        .iter()
+        .map(|c| {
+            json!({
+                "name": c.name,
+                "type": c.elements.element_type().name(),
+                "kind": c.metadata.kind.name(),
+                "unit": c.metadata.unit,
+                "label": c.metadata.label,
+                "time_axis": c.metadata.time_axis,
+                "values": json_values(&c.elements),
+            })
+        })
+        .collect();
+
+    json!({ "containers": containers })
+}
+
+#[cfg(test)]
+mod subset {
+    use crate::query::*;
+    use tinygraph::tgph_format::{ColumnEncoding, ContainerMetadata, MetricKind};
+
+    fn tgph() -> TGPH {
+        let mut tgph = TGPH::new(100);
+        for time in [100_u32, 160, 220, 280, 340] {
+            tgph.append(time, "time").unwrap();
+        }
+        // Started collecting one sample later than the others.
+        for load in [2.0_f32, 3.0, 4.0, 5.0] {
+            tgph.append(load, "CPU load").unwrap();
+        }
+        for used in [10_u64, 11, 12, 13, 14] {
+            tgph.append(used, "Used memory [MB]").unwrap();
+        }
+        tgph.append("pi".to_string(), "Hostname").unwrap();
+        for name in ["CPU load", "Used memory [MB]"] {
+            tgph.set_metadata(
+                name,
+                ContainerMetadata::new(MetricKind::Gauge, "", "").with_time_axis("time"),
+            );
+        }
+        tgph
+    }
+
+    fn names(tgph: &TGPH) -> Vec<&str> {
+        tgph.containers.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    #[test]
+    fn parse() {
+        let query =
+            Query::parse("match=Used+memory%20%5BMB%5D&match=CPU&from=5&step=60&format=json")
+                .unwrap();
+        assert_eq!(query.patterns, ["Used memory [MB]", "CPU"]);
+        assert_eq!(query.from, Some(5));
+        assert_eq!(query.to, None);
+        assert_eq!(query.step, Some(60));
+        assert_eq!(query.format, QueryFormat::Json);
+
+        assert_eq!(Query::parse("").unwrap(), Query::default());
+        assert!(Query::parse("step=0").is_err());
+        assert!(Query::parse("from=yesterday").is_err());
+        assert!(Query::parse("match=%4").is_err());
+        assert!(Query::parse("limit=10").is_err());
+    }
+
+    #[test]
+    fn matching_containers_come_with_their_time_axis() {
+        let query = Query::parse("match=CPU").unwrap();
+        let result = query.apply(&tgph());
+
+        assert_eq!(names(&result), ["time", "CPU load"]);
+        assert_eq!(result.containers[1].elements.len(), 4);
+        assert_eq!(names(&Query::default().apply(&tgph())).len(), 4);
+    }
+
+    #[test]
+    fn matched_time_axis_is_sliced() {
+        let query = Query::parse("match=time&from=150&to=280").unwrap();
+        let result = query.apply(&tgph());
+
+        assert_eq!(names(&result), ["time"]);
+        let times = json_values(&result.containers[0].elements);
+        assert_eq!(times, [json!(160), json!(220), json!(280)]);
+    }
+
+    #[test]
+    fn subset_keeps_the_column_encoding() {
+        let mut compact = tgph();
+        compact.column_encoding = ColumnEncoding::Compact;
+
+        assert_eq!(Query::default().apply(&tgph()).required_version(), 3);
+        assert_eq!(Query::default().apply(&compact).required_version(), 4);
+    }
+
+    #[test]
+    fn time_range_and_step() {
+        let query = Query::parse("from=150&to=340&step=120").unwrap();
+        let result = query.apply(&tgph());
+        let values = |name: &str| json_values(&result.container(name).unwrap().elements);
+
+        // 160 and 220 fall into the same 120 second step, 220 is newer.
+        assert_eq!(values("time"), [json!(220), json!(340)]);
+        assert_eq!(values("CPU load"), [json!(3.0), json!(5.0)]);
+        assert_eq!(values("Used memory [MB]"), [json!(12), json!(14)]);
+        assert_eq!(values("Hostname"), [json!("pi")]);
+    }
+
+    #[test]
+    fn json() {
+        let query = Query::parse("match=memory&to=100").unwrap();
+        let json = to_json(&query.apply(&tgph()));
+
+        assert_eq!(json["containers"][1]["name"], "Used memory [MB]");
+        assert_eq!(json["containers"][1]["type"], "U64");
+        assert_eq!(json["containers"][1]["kind"], "gauge");
+        assert_eq!(json["containers"][1]["time_axis"], "time");
+        assert_eq!(json["containers"][1]["values"], json!([10]));
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use tinygraph::tgph_format::{ContainerMetadata, MetricKind, TgphError, TGPH};
+
+/// Start of the name of every container written by a rollup tier.
+pub const ROLLUP_PREFIX: &str = "Rollup ";
+
+/// How the samples of one bucket are summarized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RollupFunction {
+    Avg,
+    Min,
+    Max,
+    /// The newest sample, the natural summary of a counter.
+    Last,
+}
+
+impl RollupFunction {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Avg => "avg",
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Last => "last",
+        }
+    }
+
+    fn apply(self, values: &[f64]) -> f64 {
+        match self {
+            Self::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Self::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Self::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Self::Last => *values.last().unwrap(),
+        }
+    }
+}
+
+/// A downsampled copy of every gauge and counter, with one element per
+/// `interval` seconds.
+///
+/// For a source container "Used memory [MB]" on the time axis "Unix
+/// timestamp", a 60 second tier with the avg function writes
+/// "Rollup 60s avg Used memory [MB]" on the time axis
+/// "Rollup 60s Unix timestamp", which holds the start of every bucket.
+///
+/// Buckets are only written once a sample of a later bucket exists, and the
+/// tier carries on from the last bucket it wrote, so nothing besides the
+/// containers themselves has to be kept between periods or restarts.
+pub struct RollupTier {
+    pub interval: u64,
+    pub functions: Vec<RollupFunction>,
+}
+
+impl RollupTier {
+    /// Start of the names of all containers of this tier.
+    pub fn prefix(&self) -> String {
+        format!("{ROLLUP_PREFIX}{}s ", self.interval)
+    }
+
+    fn bucket_of(&self, time: u64) -> u64 {
+        time - time % self.interval
+    }
+
+    /// Appends the buckets completed since the last update.
+    pub fn update(&self, tgph: &mut TGPH) -> Result<(), TgphError> {
+        let prefix = self.prefix();
+
+        // Sources grouped by their time axis, as they share the buckets.
+        let mut sources: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+        for (index, container) in tgph.containers.iter().enumerate() {
+            let metadata = &container.metadata;
+            let summarized = matches!(metadata.kind, MetricKind::Gauge | MetricKind::Counter)
+                && !container.name.starts_with(ROLLUP_PREFIX)
+                && container.elements.get_f64(0).is_some();
+            if let (true, Some(axis)) = (summarized, &metadata.time_axis) {
+                sources.entry(axis).or_default().push(index);
+            }
+        }
+
+        let mut appends: Vec<(String, f64, ContainerMetadata)> = Vec::new();
+        let mut buckets: Vec<(String, u64, ContainerMetadata)> = Vec::new();
+        for (axis_name, source_indices) in sources {
+            let Some(axis) = tgph.container(axis_name) else {
+                continue;
+            };
+            let times = &axis.elements;
+            let time_at = |index: usize| times.get_f64(index).unwrap_or(0.0) as u64;
+            let rollup_axis = format!("{prefix}{axis_name}");
+
+            // Everything up to the end of the last written bucket is done.
+            let done_until = tgph
+                .container(&rollup_axis)
+                .and_then(|c| c.elements.get_f64(c.elements.len().checked_sub(1)?))
+                .map(|bucket| bucket as u64 + self.interval);
+            let mut first = times.len();
+            while first > 0 && done_until.is_none_or(|done| time_at(first - 1) >= done) {
+                first -= 1;
+            }
+
+            let mut start = first;
+            while start < times.len() {
+                let bucket = self.bucket_of(time_at(start));
+                let mut end = start;
+                while end < times.len() && self.bucket_of(time_at(end)) == bucket {
+                    end += 1;
+                }
+                // The newest bucket may still get more samples.
+                if end == times.len() {
+                    break;
+                }
+
+                buckets.push((
+                    rollup_axis.clone(),
+                    bucket,
+                    ContainerMetadata::new(
+                        MetricKind::Gauge,
+                        "s",
+                        &format!("Start of {}s buckets", self.interval),
+                    ),
+                ));
+
+                for &source_index in &source_indices {
+                    let source = &tgph.containers[source_index];
+                    let offset = tgph.axis_offset(source).unwrap_or_default();
+                    let values: Vec<f64> = (start.max(offset)..end)
+                        .filter_map(|i| source.elements.get_f64(i - offset))
+                        .collect();
+                    if values.is_empty() {
+                        continue;
+                    }
+
+                    for &function in &self.functions {
+                        let label = if source.metadata.label.is_empty() {
+                            &source.name
+                        } else {
+                            &source.metadata.label
+                        };
+                        let metadata = ContainerMetadata::new(
+                            source.metadata.kind,
+                            &source.metadata.unit,
+                            &format!("{label} ({} over {}s)", function.name(), self.interval),
+                        )
+                        .with_time_axis(&rollup_axis);
+                        appends.push((
+                            format!("{prefix}{} {}", function.name(), source.name),
+                            function.apply(&values),
+                            metadata,
+                        ));
+                    }
+                }
+
+                start = end;
+            }
+        }
+
+        for (name, bucket, metadata) in buckets {
+            tgph.append(bucket, &name)?;
+            tgph.set_metadata(&name, metadata);
+        }
+        for (name, value, metadata) in appends {
+            tgph.append(value, &name)?;
+            tgph.set_metadata(&name, metadata);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tier {
+    use crate::rollup::*;
+    use tinygraph::tgph_format::ElementArrayType;
+
+    fn f64_elements(tgph: &TGPH, name: &str) -> Vec<f64> {
+        match &tgph.container(name).unwrap().elements {
+            ElementArrayType::FLOAT64(elements) => elements.iter().copied().collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn u64_elements(tgph: &TGPH, name: &str) -> Vec<u64> {
+        match &tgph.container(name).unwrap().elements {
+            ElementArrayType::U64(elements) => elements.iter().copied().collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn append_sample(tgph: &mut TGPH, time: u32, value: f32) {
+        tgph.append(time, "time").unwrap();
+        tgph.append(value, "value").unwrap();
+        tgph.set_metadata(
+            "value",
+            ContainerMetadata::new(MetricKind::Gauge, "C", "Value").with_time_axis("time"),
+        );
+        tgph.append(value, "info").unwrap();
+        tgph.set_metadata(
+            "info",
+            ContainerMetadata::new(MetricKind::Info, "", "").with_time_axis("time"),
+        );
+    }
+
+    fn tier() -> RollupTier {
+        RollupTier {
+            interval: 60,
+            functions: vec![
+                RollupFunction::Min,
+                RollupFunction::Avg,
+                RollupFunction::Max,
+            ],
+        }
+    }
+
+    #[test]
+    fn complete_buckets_are_summarized() {
+        let mut tgph = TGPH::new(100);
+        for (time, value) in [(0, 1.0), (20, 2.0), (40, 6.0), (60, 10.0), (130, 3.0)] {
+            append_sample(&mut tgph, time, value);
+        }
+        tier().update(&mut tgph).unwrap();
+
+        assert_eq!(u64_elements(&tgph, "Rollup 60s time"), [0, 60]);
+        assert_eq!(f64_elements(&tgph, "Rollup 60s min value"), [1.0, 10.0]);
+        assert_eq!(f64_elements(&tgph, "Rollup 60s avg value"), [3.0, 10.0]);
+        assert_eq!(f64_elements(&tgph, "Rollup 60s max value"), [6.0, 10.0]);
+        assert!(tgph.container("Rollup 60s avg info").is_none());
+
+        let metadata = tgph.metadata("Rollup 60s avg value").unwrap();
+        assert_eq!(metadata.unit, "C");
+        assert_eq!(metadata.label, "Value (avg over 60s)");
+        assert_eq!(metadata.time_axis.as_deref(), Some("Rollup 60s time"));
+    }
+
+    #[test]
+    fn updates_carry_on_from_last_bucket() {
+        let mut tgph = TGPH::new(100);
+        let tier = tier();
+        for (time, value) in [(0, 1.0), (30, 3.0), (60, 5.0)] {
+            append_sample(&mut tgph, time, value);
+            tier.update(&mut tgph).unwrap();
+        }
+        for (time, value) in [(90, 7.0), (120, 0.0)] {
+            append_sample(&mut tgph, time, value);
+            tier.update(&mut tgph).unwrap();
+        }
+        tier.update(&mut tgph).unwrap();
+
+        assert_eq!(u64_elements(&tgph, "Rollup 60s time"), [0, 60]);
+        assert_eq!(f64_elements(&tgph, "Rollup 60s avg value"), [2.0, 6.0]);
+    }
+
+    #[test]
+    fn rollups_are_not_rolled_up_again() {
+        let mut tgph = TGPH::new(100);
+        for time in (0..=600).step_by(30) {
+            append_sample(&mut tgph, time, 1.0);
+        }
+        tier().update(&mut tgph).unwrap();
+        let hourly = RollupTier {
+            interval: 300,
+            functions: vec![RollupFunction::Last],
+        };
+        hourly.update(&mut tgph).unwrap();
+
+        assert_eq!(f64_elements(&tgph, "Rollup 300s last value"), [1.0, 1.0]);
+        assert!(tgph
+            .containers
+            .iter()
+            .all(|c| !c.name.starts_with("Rollup 300s last Rollup")));
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Cursor, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use tinygraph::tgph_format::{TgphError, TGPH};
+
+/// "TGWL" in little endian, at the start of every log.
+const LOG_MAGIC: u32 = 0x4c574754;
+const LOG_HEADER_LEN: usize = 8;
+const RECORD_HEADER_LEN: usize = 8;
+
+/// Where samples collected since the last snapshot of `path` are logged,
+/// e.g. `data.tgph.gz.log`.
+pub fn log_path(path: &Path) -> PathBuf {
+    let mut log = path.as_os_str().to_owned();
+    log.push(".log");
+    PathBuf::from(log)
+}
+
+/// Append-only log of the samples collected between two snapshots, so every
+/// period only writes its own few bytes instead of the whole history.
+///
+/// The log starts with a header of the magic and the CRC32C of the snapshot
+/// file it continues, 0 if there is none. A log whose checksum does not match
+/// the snapshot on disk was already compacted into it and is ignored.
+///
+/// Every sample is a record of its payload length and CRC32C (both u32)
+/// followed by the payload: a u16 count of replaced container names, each
+/// a u16 length and UTF-8 bytes, and the uncompressed TGPH of the sample.
+pub struct SampleLog {
+    file: File,
+}
+
+impl SampleLog {
+    /// Opens or creates the log at `path`, returning it together with the
+    /// samples logged on top of the snapshot with `snapshot_checksum`.
+    ///
+    /// A record cut short by a crash ends the log, it is truncated there so
+    /// that new records do not end up behind garbage.
+    pub fn open(path: &Path, snapshot_checksum: u32) -> std::io::Result<(Self, Vec<TGPH>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        let header_matches = contents.len() >= LOG_HEADER_LEN
+            && contents[..4] == LOG_MAGIC.to_le_bytes()
+            && contents[4..8] == snapshot_checksum.to_le_bytes();
+        if !header_matches {
+            let mut log = Self { file };
+            log.reset(snapshot_checksum)?;
+            return Ok((log, Vec::new()));
+        }
+
+        let mut samples = Vec::new();
+        let mut valid_len = LOG_HEADER_LEN;
+        while let Some((sample, record_len)) = read_record(&contents[valid_len..]) {
+            samples.push(sample);
+            valid_len += record_len;
+        }
+
+        if valid_len < contents.len() {
+            eprintln!(
+                "Dropping {} damaged bytes at the end of {}",
+                contents.len() - valid_len,
+                path.display()
+            );
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::End(0))?;
+
+        Ok((Self { file }, samples))
+    }
+
+    pub fn append(&mut self, sample: &TGPH) -> std::io::Result<()> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(sample.replaced_containers().len() as u16).to_le_bytes());
+        for name in sample.replaced_containers() {
+            payload.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            payload.extend_from_slice(name.as_bytes());
+        }
+        sample.serialize_into(&mut payload)?;
+
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc32c::crc32c(&payload).to_le_bytes());
+        record.extend_from_slice(&payload);
+
+        self.file.write_all(&record)?;
+        self.file.sync_data()
+    }
+
+    /// Empties the log once its samples are part of a snapshot, which was
+    /// written with `snapshot_checksum`.
+    pub fn reset(&mut self, snapshot_checksum: u32) -> std::io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&LOG_MAGIC.to_le_bytes())?;
+        self.file.write_all(&snapshot_checksum.to_le_bytes())?;
+        self.file.sync_all()
+    }
+}
+
+/// Parses the record at the start of `bytes`, returning the sample and the
+/// length of the record, or `None` if it is incomplete or damaged.
+fn read_record(bytes: &[u8]) -> Option<(TGPH, usize)> {
+    let header = bytes.get(..RECORD_HEADER_LEN)?;
+    let payload_len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+    let payload = bytes.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + payload_len)?;
+    if crc32c::crc32c(payload) != checksum {
+        return None;
+    }
+
+    let sample = read_sample(&mut Cursor::new(payload)).ok()?;
+    Some((sample, RECORD_HEADER_LEN + payload_len))
+}
+
+fn read_sample(payload: &mut Cursor<&[u8]>) -> Result<TGPH, TgphError> {
+    let mut len = [0; 2];
+    let read_error = |e: std::io::Error| TgphError::Io {
+        offset: 0,
+        source: e,
+    };
+
+    payload.read_exact(&mut len).map_err(read_error)?;
+    let mut replaced = Vec::new();
+    for _ in 0..u16::from_le_bytes(len) {
+        payload.read_exact(&mut len).map_err(read_error)?;
+        let mut name = vec![0; u16::from_le_bytes(len) as usize];
+        payload.read_exact(&mut name).map_err(read_error)?;
+        replaced.push(String::from_utf8(name).map_err(|_| TgphError::InvalidUtf8 { offset: 0 })?);
+    }
+
+    let mut sample = TGPH::deserialize_from(payload)?;
+    for name in &replaced {
+        if let Some(index) = sample.containers.iter().position(|c| &c.name == name) {
+            let container = sample.containers.remove(index);
+            sample.replace_container(container);
+        }
+    }
+    Ok(sample)
+}
+
+#[cfg(test)]
+mod log {
+    use crate::sample_log::*;
+    use crate::testing::scratch_dir;
+    use std::fs;
+
+    fn sample(value: u32) -> TGPH {
+        let mut sample = TGPH::default();
+        sample.append(value, "value").unwrap();
+        sample.replace(vec![value, value + 1], "history").unwrap();
+        sample
+    }
+
+    #[test]
+    fn samples_are_replayed() {
+        let dir = scratch_dir("log-replay");
+        let path = log_path(&dir.join("data.tgph.gz"));
+
+        let (mut log, samples) = SampleLog::open(&path, 7).unwrap();
+        assert!(samples.is_empty());
+        log.append(&sample(1)).unwrap();
+        log.append(&sample(2)).unwrap();
+        drop(log);
+
+        let (_, samples) = SampleLog::open(&path, 7).unwrap();
+        let mut tgph = TGPH::default();
+        for sample in samples {
+            tgph.merge(sample).unwrap();
+        }
+
+        assert_eq!(tgph.containers[0].elements.len(), 2);
+        assert_eq!(tgph.containers[1].elements.len(), 2);
+        assert_eq!(tgph.replaced_containers(), ["history"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn log_of_other_snapshot_is_dropped() {
+        let dir = scratch_dir("log-compacted");
+        let path = log_path(&dir.join("data.tgph.gz"));
+
+        let (mut log, _) = SampleLog::open(&path, 7).unwrap();
+        log.append(&sample(1)).unwrap();
+        drop(log);
+
+        let (_, samples) = SampleLog::open(&path, 8).unwrap();
+        assert!(samples.is_empty());
+        assert_eq!(fs::metadata(&path).unwrap().len(), LOG_HEADER_LEN as u64);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn torn_record_is_truncated() {
+        let dir = scratch_dir("log-torn");
+        let path = log_path(&dir.join("data.tgph.gz"));
+
+        let (mut log, _) = SampleLog::open(&path, 0).unwrap();
+        log.append(&sample(1)).unwrap();
+        let good_len = fs::metadata(&path).unwrap().len();
+        log.append(&sample(2)).unwrap();
+        drop(log);
+
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(good_len + 5).unwrap();
+        drop(file);
+
+        let (mut log, samples) = SampleLog::open(&path, 0).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(fs::metadata(&path).unwrap().len(), good_len);
+
+        log.append(&sample(3)).unwrap();
+        drop(log);
+        let (_, samples) = SampleLog::open(&path, 0).unwrap();
+        assert_eq!(samples.len(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/// Where the previous generation of `path` is kept, e.g. `data.tgph.gz.bak`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    PathBuf::from(backup)
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    PathBuf::from(temporary)
+}
+
+/// Replaces the contents of `path` with `data` so that a crash at any point
+/// leaves either the old or the new file behind, never a truncated one.
+///
+/// The data goes to a temporary file in the same directory which is fsynced
+/// and then renamed over `path`. With `keep_backup` the file being replaced
+/// stays around as its `.bak` generation.
+pub fn write_atomically(path: &Path, data: &[u8], keep_backup: bool) -> std::io::Result<()> {
+    let temporary = temporary_path(path);
+
+    let mut file = File::create(&temporary)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+
+    if keep_backup && path.exists() {
+        let backup = backup_path(path);
+        match fs::remove_file(&backup) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        // A hard link keeps `path` in place the whole time, fall back to a
+        // copy on filesystems that do not support them.
+        if fs::hard_link(path, &backup).is_err() {
+            fs::copy(path, &backup)?;
+        }
+    }
+
+    fs::rename(&temporary, path)?;
+
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    sync_directory(directory)
+}
+
+#[cfg(unix)]
+fn sync_directory(directory: &Path) -> std::io::Result<()> {
+    File::open(directory)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_directory: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod atomic_write {
+    use crate::storage::*;
+    use crate::testing::scratch_dir;
+
+    #[test]
+    fn write_replaces_contents() {
+        let dir = scratch_dir("replace");
+        let path = dir.join("data.tgph.gz");
+
+        write_atomically(&path, b"first", false).unwrap();
+        write_atomically(&path, b"second", false).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert!(!backup_path(&path).exists());
+        assert!(!temporary_path(&path).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_keeps_previous_generation() {
+        let dir = scratch_dir("backup");
+        let path = dir.join("data.tgph.gz");
+
+        write_atomically(&path, b"first", true).unwrap();
+        assert!(!backup_path(&path).exists());
+
+        write_atomically(&path, b"second", true).unwrap();
+        write_atomically(&path, b"third", true).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"third");
+        assert_eq!(fs::read(backup_path(&path)).unwrap(), b"second");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,785 @@
+//! Push based decoding of .tgph.gz files, for files too large to hold in
+//! memory or data arriving over the network.
+//!
+//! Compressed chunks of any size go into `StreamDecoder::push`, which
+//! decompresses them (including gzip files of several members and files over
+//! 4 GiB) and parses as far as the data allows. What was parsed comes out of
+//! `StreamDecoder::next_event` as `Event`s. Only the unparsed rest of the
+//! decompressed data is kept between chunks, which is never more than a
+//! container head or a string, so memory use depends on the chunk size and
+//! not on the size of the file.
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+
+use flate2::write::MultiGzDecoder;
+
+use crate::column_encoding::{FloatDecoder, FloatState, IntegerDecoder, IntegerState};
+use crate::tgph_format::{
+    ContainerMetadata, ElementArrayType, ElementType, TgphError, TGPH_VERSION_5,
+};
+use crate::view::{split_string, Input, Number};
+
+/// Most elements in one `Event::Elements`.
+pub const BATCH_SIZE: usize = 4096;
+
+/// Parts of a TGPH, in the order they are stored in.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Header {
+        version: u8,
+        container_count: u16,
+    },
+    /// Start of a container, whose elements follow in `Elements` events.
+    Container {
+        name: String,
+        metadata: ContainerMetadata,
+        element_type: ElementType,
+        element_count: u32,
+    },
+    /// The next elements of the current container, oldest first and at most
+    /// `BATCH_SIZE` of them.
+    Elements(ElementArrayType),
+    /// The last container is complete. Anything after it is ignored.
+    End,
+}
+
+/// Raw elements as read from the stream.
+fn raw_elements(element_type: ElementType, bytes: &[u8]) -> ElementArrayType {
+    match element_type {
+        ElementType::U32 => {
+            ElementArrayType::U32(bytes.chunks_exact(4).map(u32::from_le_slice).collect())
+        }
+        ElementType::FLOAT32 => {
+            ElementArrayType::FLOAT32(bytes.chunks_exact(4).map(f32::from_le_slice).collect())
+        }
+        ElementType::U64 => {
+            ElementArrayType::U64(bytes.chunks_exact(8).map(u64::from_le_slice).collect())
+        }
+        ElementType::I64 => {
+            ElementArrayType::I64(bytes.chunks_exact(8).map(i64::from_le_slice).collect())
+        }
+        ElementType::FLOAT64 => {
+            ElementArrayType::FLOAT64(bytes.chunks_exact(8).map(f64::from_le_slice).collect())
+        }
+        ElementType::STRING => unreachable!("strings are not fixed size"),
+    }
+}
+
+/// Elements decoded from the compact encoding, `None` if a value does not
+/// fit the element type.
+fn encoded_elements(element_type: ElementType, bits: &[u64]) -> Option<ElementArrayType> {
+    fn convert<T: Number, C: FromIterator<T>>(bits: &[u64]) -> Option<C> {
+        bits.iter().map(|&b| T::from_encoded(b)).collect()
+    }
+
+    Some(match element_type {
+        ElementType::U32 => ElementArrayType::U32(convert::<u32, _>(bits)?),
+        ElementType::FLOAT32 => ElementArrayType::FLOAT32(convert::<f32, _>(bits)?),
+        ElementType::U64 => ElementArrayType::U64(convert::<u64, _>(bits)?),
+        ElementType::I64 => ElementArrayType::I64(convert::<i64, _>(bits)?),
+        ElementType::FLOAT64 => ElementArrayType::FLOAT64(convert::<f64, _>(bits)?),
+        ElementType::STRING => unreachable!("strings are never encoded"),
+    })
+}
+
+/// Decodes up to `BATCH_SIZE` values. Stops early when the payload received
+/// so far is used up, leaving `decoder` at the last complete value. `None`
+/// if the payload is `complete` and still does not hold the values.
+fn decode_batch<D>(
+    decoder: &mut D,
+    remaining: impl Fn(&D) -> u32,
+    complete: bool,
+) -> Option<Vec<u64>>
+where
+    D: Iterator<Item = u64> + Clone,
+{
+    let mut bits = Vec::new();
+    while bits.len() < BATCH_SIZE && remaining(decoder) > 0 {
+        let before = decoder.clone();
+        match decoder.next() {
+            Some(value) => bits.push(value),
+            None if complete => return None,
+            None => {
+                *decoder = before;
+                break;
+            }
+        }
+    }
+    Some(bits)
+}
+
+#[derive(Clone, Copy)]
+enum CompactState {
+    Integers(IntegerState),
+    Floats(FloatState),
+}
+
+impl CompactState {
+    fn remaining(&self) -> u32 {
+        match self {
+            Self::Integers(state) => state.remaining(),
+            Self::Floats(state) => state.remaining(),
+        }
+    }
+}
+
+enum State {
+    Header,
+    ContainerHead,
+    Raw {
+        element_type: ElementType,
+        remaining: u32,
+    },
+    Strings {
+        remaining: u32,
+    },
+    Compact {
+        element_type: ElementType,
+        decoder: CompactState,
+        /// Bit position of the next value in `Parser::payload`.
+        position: usize,
+        /// Bytes of the payload not yet moved into `Parser::payload`.
+        payload_left: u32,
+        payload_offset: u64,
+    },
+    /// The CRC32C after a container since version 5.
+    ContainerChecksum,
+    /// The CRC32C after the last container since version 5.
+    FileChecksum,
+    End,
+}
+
+/// Receives the decompressed data and turns it into events.
+struct Parser {
+    /// Decompressed data not parsed yet, from `pos` on.
+    buffer: Vec<u8>,
+    pos: usize,
+    /// Offset of `buffer[0]` in the decompressed stream.
+    consumed: u64,
+    version: u8,
+    containers_left: u16,
+    state: State,
+    /// The part of a compact payload that is not decoded yet.
+    payload: Vec<u8>,
+    /// CRC32C of everything parsed so far, and of the current container.
+    checksum: u32,
+    container_checksum: u32,
+    container_name: String,
+    events: VecDeque<Event>,
+    error: Option<TgphError>,
+}
+
+/// `err`, found `by` bytes into the stream in a slice that starts later.
+fn shifted(err: TgphError, by: u64) -> TgphError {
+    match err {
+        TgphError::UnsupportedVersion { version, offset } => TgphError::UnsupportedVersion {
+            version,
+            offset: offset + by,
+        },
+        TgphError::UnknownElementType {
+            element_type,
+            offset,
+        } => TgphError::UnknownElementType {
+            element_type,
+            offset: offset + by,
+        },
+        TgphError::UnknownMetricKind { kind, offset } => TgphError::UnknownMetricKind {
+            kind,
+            offset: offset + by,
+        },
+        TgphError::InvalidUtf8 { offset } => TgphError::InvalidUtf8 {
+            offset: offset + by,
+        },
+        TgphError::InvalidEncoding { offset } => TgphError::InvalidEncoding {
+            offset: offset + by,
+        },
+        TgphError::Truncated { offset } => TgphError::Truncated {
+            offset: offset + by,
+        },
+        TgphError::Io { offset, source } => TgphError::Io {
+            offset: offset + by,
+            source,
+        },
+        err => err,
+    }
+}
+
+impl Parser {
+    fn new() -> Self {
+        Self {
+            buffer: Vec::new(),
+            pos: 0,
+            consumed: 0,
+            version: 0,
+            containers_left: 0,
+            state: State::Header,
+            payload: Vec::new(),
+            checksum: 0,
+            container_checksum: 0,
+            container_name: String::new(),
+            events: VecDeque::new(),
+            error: None,
+        }
+    }
+
+    fn offset(&self) -> u64 {
+        self.consumed + self.pos as u64
+    }
+
+    fn next_container(&mut self) -> State {
+        if self.containers_left == 0 {
+            if self.version >= TGPH_VERSION_5 {
+                return State::FileChecksum;
+            }
+            self.events.push_back(Event::End);
+            return State::End;
+        }
+        self.containers_left -= 1;
+        State::ContainerHead
+    }
+
+    fn container_done(&mut self) -> State {
+        match self.version >= TGPH_VERSION_5 {
+            true => State::ContainerChecksum,
+            false => self.next_container(),
+        }
+    }
+
+    /// Parses a stored checksum, `None` if it is not complete yet.
+    fn stored_checksum(&mut self) -> Option<u32> {
+        let checksum = Input::new(&self.buffer[self.pos..]).u32().ok()?;
+        self.pos += 4;
+        Some(checksum)
+    }
+
+    /// Parses the next part of the buffer like `step`, keeping the checksums
+    /// of what it parsed.
+    fn checked_step(&mut self) -> Result<bool, TgphError> {
+        let start = self.pos;
+        let result = self.step();
+        let parsed = &self.buffer[start..self.pos];
+        self.checksum = crc32c::crc32c_append(self.checksum, parsed);
+        self.container_checksum = crc32c::crc32c_append(self.container_checksum, parsed);
+        result
+    }
+
+    /// Parses the next part of the buffer, returning whether there was
+    /// enough data for one.
+    fn step(&mut self) -> Result<bool, TgphError> {
+        let offset = self.offset();
+        let mut input = Input::new(&self.buffer[self.pos..]);
+
+        self.state = match self.state {
+            State::Header => match input.header() {
+                Ok((version, container_count)) => {
+                    self.pos += input.offset;
+                    self.version = version;
+                    self.containers_left = container_count;
+                    self.events.push_back(Event::Header {
+                        version,
+                        container_count,
+                    });
+                    self.next_container()
+                }
+                Err(TgphError::Truncated { .. }) => return Ok(false),
+                Err(err) => return Err(shifted(err, offset)),
+            },
+            State::ContainerHead => {
+                let head = match input.container_head(self.version) {
+                    Ok(head) => head,
+                    Err(TgphError::Truncated { .. }) => return Ok(false),
+                    Err(err) => return Err(shifted(err, offset)),
+                };
+                let payload_length = match head.encoded {
+                    true => match input.u32() {
+                        Ok(length) => length,
+                        Err(_) => return Ok(false),
+                    },
+                    false => 0,
+                };
+
+                let (element_type, count) = (head.element_type, head.count);
+                self.container_checksum = 0;
+                self.container_name = head.name.to_string();
+                self.events.push_back(Event::Container {
+                    name: head.name.to_string(),
+                    metadata: head.metadata(),
+                    element_type,
+                    element_count: count,
+                });
+                self.pos += input.offset;
+
+                if head.encoded {
+                    State::Compact {
+                        element_type,
+                        decoder: match element_type {
+                            ElementType::FLOAT32 | ElementType::FLOAT64 => {
+                                CompactState::Floats(FloatState::new(count))
+                            }
+                            _ => CompactState::Integers(IntegerState::new(count)),
+                        },
+                        position: 0,
+                        payload_left: payload_length,
+                        payload_offset: self.offset(),
+                    }
+                } else if element_type == ElementType::STRING {
+                    State::Strings { remaining: count }
+                } else {
+                    State::Raw {
+                        element_type,
+                        remaining: count,
+                    }
+                }
+            }
+            State::Raw { remaining: 0, .. } | State::Strings { remaining: 0 } => {
+                self.container_done()
+            }
+            State::Raw {
+                element_type,
+                remaining,
+            } => {
+                let size = match element_type {
+                    ElementType::U32 | ElementType::FLOAT32 => 4,
+                    _ => 8,
+                };
+                let count = (remaining as usize)
+                    .min((self.buffer.len() - self.pos) / size)
+                    .min(BATCH_SIZE);
+                if count == 0 {
+                    return Ok(false);
+                }
+
+                let bytes = &self.buffer[self.pos..self.pos + count * size];
+                self.events
+                    .push_back(Event::Elements(raw_elements(element_type, bytes)));
+                self.pos += count * size;
+                State::Raw {
+                    element_type,
+                    remaining: remaining - count as u32,
+                }
+            }
+            State::Strings { mut remaining } => {
+                let mut strings = VecDeque::new();
+                while strings.len() < BATCH_SIZE && remaining > 0 {
+                    let rest = &self.buffer[self.pos..];
+                    let Some((string, after)) = split_string(rest) else {
+                        break;
+                    };
+                    let string_offset = rest.len() - after.len() - string.len();
+                    let string =
+                        std::str::from_utf8(string).map_err(|_| TgphError::InvalidUtf8 {
+                            offset: self.offset() + string_offset as u64,
+                        })?;
+                    strings.push_back(string.to_string());
+                    self.pos = self.buffer.len() - after.len();
+                    remaining -= 1;
+                }
+                if strings.is_empty() {
+                    return Ok(false);
+                }
+
+                self.events
+                    .push_back(Event::Elements(ElementArrayType::STRING(strings)));
+                State::Strings { remaining }
+            }
+            State::Compact {
+                element_type,
+                mut decoder,
+                mut position,
+                mut payload_left,
+                payload_offset,
+            } => {
+                let available = (self.buffer.len() - self.pos).min(payload_left as usize);
+                self.payload
+                    .extend_from_slice(&self.buffer[self.pos..self.pos + available]);
+                self.pos += available;
+                payload_left -= available as u32;
+
+                if decoder.remaining() == 0 {
+                    // Only padding of the last byte or garbage is left.
+                    self.payload.clear();
+                    if payload_left == 0 {
+                        self.state = self.container_done();
+                        return Ok(true);
+                    }
+                    return Ok(available > 0);
+                }
+
+                let invalid = TgphError::InvalidEncoding {
+                    offset: payload_offset,
+                };
+                let complete = payload_left == 0;
+                let bits = match &mut decoder {
+                    CompactState::Integers(state) => {
+                        let mut d = IntegerDecoder::resume(&self.payload, position, *state);
+                        let bits = decode_batch(&mut d, |d| d.suspend().1.remaining(), complete);
+                        (position, *state) = d.suspend();
+                        bits
+                    }
+                    CompactState::Floats(state) => {
+                        let mut d = FloatDecoder::resume(&self.payload, position, *state);
+                        let bits = decode_batch(&mut d, |d| d.suspend().1.remaining(), complete);
+                        (position, *state) = d.suspend();
+                        bits
+                    }
+                }
+                .ok_or(invalid)?;
+
+                // Whole bytes before the next value are not needed anymore.
+                self.payload.drain(..position / 8);
+                position %= 8;
+
+                let progressed = available > 0 || !bits.is_empty();
+                if !bits.is_empty() {
+                    let elements = encoded_elements(element_type, &bits).ok_or(
+                        TgphError::InvalidEncoding {
+                            offset: payload_offset,
+                        },
+                    )?;
+                    self.events.push_back(Event::Elements(elements));
+                }
+                self.state = State::Compact {
+                    element_type,
+                    decoder,
+                    position,
+                    payload_left,
+                    payload_offset,
+                };
+                return Ok(progressed);
+            }
+            State::ContainerChecksum => match self.stored_checksum() {
+                None => return Ok(false),
+                Some(checksum) if checksum != self.container_checksum => {
+                    return Err(TgphError::ChecksumMismatch {
+                        container: Some(self.container_name.clone()),
+                        offset,
+                    })
+                }
+                Some(_) => self.next_container(),
+            },
+            State::FileChecksum => match self.stored_checksum() {
+                None => return Ok(false),
+                Some(checksum) if checksum != self.checksum => {
+                    return Err(TgphError::ChecksumMismatch {
+                        container: None,
+                        offset,
+                    })
+                }
+                Some(_) => {
+                    self.events.push_back(Event::End);
+                    State::End
+                }
+            },
+            State::End => {
+                self.pos = self.buffer.len();
+                return Ok(false);
+            }
+        };
+
+        Ok(true)
+    }
+}
+
+impl Write for Parser {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        if self.error.is_some() {
+            return Err(std::io::Error::other("the TGPH stream is invalid"));
+        }
+
+        self.buffer.extend_from_slice(bytes);
+        let result = loop {
+            match self.checked_step() {
+                Ok(true) => continue,
+                Ok(false) => break Ok(bytes.len()),
+                Err(err) => {
+                    self.error = Some(err);
+                    break Err(std::io::Error::other("the TGPH stream is invalid"));
+                }
+            }
+        };
+
+        self.buffer.drain(..self.pos);
+        self.consumed += self.pos as u64;
+        self.pos = 0;
+        result
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decodes a .tgph.gz file pushed to it in chunks, see the module
+/// documentation.
+pub struct StreamDecoder {
+    gzip: MultiGzDecoder<Parser>,
+}
+
+impl Default for StreamDecoder {
+    fn default() -> Self {
+        Self {
+            gzip: MultiGzDecoder::new(Parser::new()),
+        }
+    }
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn error(&mut self, err: std::io::Error) -> TgphError {
+        let parser = self.gzip.get_mut();
+        parser.error.take().unwrap_or(TgphError::Io {
+            offset: parser.consumed,
+            source: err,
+        })
+    }
+
+    /// Decompresses and parses the next `chunk` of the file. Once this
+    /// failed, the stream cannot be continued.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<(), TgphError> {
+        let result = self.gzip.write_all(chunk).and_then(|_| self.gzip.flush());
+        result.map_err(|err| self.error(err))
+    }
+
+    /// The oldest event not taken yet.
+    pub fn next_event(&mut self) -> Option<Event> {
+        self.gzip.get_mut().events.pop_front()
+    }
+
+    /// Checks that the stream is complete, to be called after the last
+    /// chunk. Fails if the gzip data or the TGPH stops early.
+    pub fn finish(&mut self) -> Result<(), TgphError> {
+        let result = self.gzip.try_finish();
+        result.map_err(|err| self.error(err))?;
+
+        let parser = self.gzip.get_ref();
+        match parser.state {
+            State::End => Ok(()),
+            _ => Err(TgphError::Truncated {
+                offset: parser.consumed + parser.buffer.len() as u64,
+            }),
+        }
+    }
+}
+
+/// Reads the .tgph.gz data of `reader` in chunks, handing every event to
+/// `on_event` as soon as it is parsed.
+pub fn decode<R: Read, F: FnMut(Event)>(reader: &mut R, mut on_event: F) -> Result<(), TgphError> {
+    let mut decoder = StreamDecoder::new();
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(decoder.error(err)),
+        };
+        decoder.push(&chunk[..read])?;
+        while let Some(event) = decoder.next_event() {
+            on_event(event);
+        }
+    }
+
+    decoder.finish()?;
+    while let Some(event) = decoder.next_event() {
+        on_event(event);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod streaming {
+    use std::io::Cursor;
+
+    use crate::gzip::{compress, CompressionLvl};
+    use crate::stream::*;
+    use crate::tgph_format::{ColumnEncoding, TGPHContainer, TGPH};
+
+    fn tgph(encoding: ColumnEncoding) -> Vec<u8> {
+        let mut tgph = TGPH::new(10_000);
+        tgph.column_encoding = encoding;
+        for i in 0..5000_u32 {
+            tgph.append(1700000000 + i * 60, "time").unwrap();
+            tgph.append((i % 7) as f32 * 0.5, "CPU load").unwrap();
+            tgph.append(u64::MAX - i as u64, "Received").unwrap();
+        }
+        for i in 0..3 {
+            tgph.append(-i as i64, "Offset").unwrap();
+            tgph.append(1.0 / (i + 1) as f64, "Ratio").unwrap();
+        }
+        tgph.append("pi".to_string(), "Hostname").unwrap();
+        tgph.append("x".repeat(300), "Hostname").unwrap();
+        tgph.set_metadata(
+            "CPU load",
+            ContainerMetadata::default().with_time_axis("time"),
+        );
+
+        let mut bytes = Vec::new();
+        tgph.serialize_into(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn extend(elements: &mut ElementArrayType, batch: ElementArrayType) {
+        match (elements, batch) {
+            (ElementArrayType::U32(a), ElementArrayType::U32(b)) => a.extend(b),
+            (ElementArrayType::FLOAT32(a), ElementArrayType::FLOAT32(b)) => a.extend(b),
+            (ElementArrayType::STRING(a), ElementArrayType::STRING(b)) => a.extend(b),
+            (ElementArrayType::U64(a), ElementArrayType::U64(b)) => a.extend(b),
+            (ElementArrayType::I64(a), ElementArrayType::I64(b)) => a.extend(b),
+            (ElementArrayType::FLOAT64(a), ElementArrayType::FLOAT64(b)) => a.extend(b),
+            _ => panic!("batch of another element type"),
+        }
+    }
+
+    /// The containers described by `events`, none of which may be empty.
+    fn containers(events: Vec<Event>) -> Vec<TGPHContainer> {
+        let mut containers: Vec<TGPHContainer> = Vec::new();
+        let mut first_batch = false;
+        for event in events {
+            match event {
+                Event::Container { name, metadata, .. } => {
+                    containers.push(TGPHContainer {
+                        name,
+                        elements: ElementArrayType::U32(VecDeque::new()),
+                        metadata,
+                    });
+                    first_batch = true;
+                }
+                Event::Elements(batch) => {
+                    assert!(batch.len() <= BATCH_SIZE);
+                    let container = containers.last_mut().unwrap();
+                    if std::mem::take(&mut first_batch) {
+                        container.elements = batch;
+                    } else {
+                        extend(&mut container.elements, batch);
+                    }
+                }
+                Event::Header { .. } | Event::End => {}
+            }
+        }
+        containers
+    }
+
+    #[test]
+    fn chunks_of_several_members() {
+        for encoding in [ColumnEncoding::Raw, ColumnEncoding::Compact] {
+            let bytes = tgph(encoding);
+            let expected = TGPH::deserialize_from(&mut Cursor::new(&bytes)).unwrap();
+
+            // Two gzip members, split in the middle of a container.
+            let (first, second) = bytes.split_at(bytes.len() / 2);
+            let mut gz = compress(first, CompressionLvl::default());
+            gz.extend(compress(second, CompressionLvl::default()));
+
+            let mut decoder = StreamDecoder::new();
+            let mut events = Vec::new();
+            for chunk in gz.chunks(7) {
+                decoder.push(chunk).unwrap();
+                events.extend(std::iter::from_fn(|| decoder.next_event()));
+                // Nothing but the beginning of a string or value is kept.
+                assert!(decoder.gzip.get_ref().buffer.len() < 512);
+                assert!(decoder.gzip.get_ref().payload.len() < 64);
+            }
+            decoder.finish().unwrap();
+
+            assert_eq!(
+                events[0],
+                Event::Header {
+                    version: expected.version(),
+                    container_count: 6
+                }
+            );
+            assert_eq!(events.last(), Some(&Event::End));
+            assert_eq!(containers(events), expected.containers);
+        }
+    }
+
+    #[test]
+    fn decode_from_a_reader() {
+        let bytes = tgph(ColumnEncoding::Compact);
+        let gz = compress(&bytes, CompressionLvl::default());
+
+        let mut batches = 0;
+        decode(&mut gz.as_slice(), |event| {
+            if let Event::Elements(_) = event {
+                batches += 1;
+            }
+        })
+        .unwrap();
+        // 5000 elements take two batches in each of the first three containers.
+        assert_eq!(batches, 3 * 2 + 3);
+    }
+
+    #[test]
+    fn incomplete_streams_fail() {
+        let bytes = tgph(ColumnEncoding::Compact);
+
+        // Complete gzip, but the TGPH ends early.
+        let gz = compress(&bytes[..bytes.len() - 10], CompressionLvl::default());
+        let mut decoder = StreamDecoder::new();
+        decoder.push(&gz).unwrap();
+        assert!(matches!(decoder.finish(), Err(TgphError::Truncated { .. })));
+
+        // The gzip stops early.
+        let gz = compress(&bytes, CompressionLvl::default());
+        let mut decoder = StreamDecoder::new();
+        decoder.push(&gz[..gz.len() - 4]).unwrap();
+        assert!(decoder.finish().is_err());
+
+        let mut bytes = bytes;
+        bytes[4] = 9;
+        let gz = compress(&bytes, CompressionLvl::default());
+        assert!(matches!(
+            StreamDecoder::new().push(&gz),
+            Err(TgphError::UnsupportedVersion {
+                version: 9,
+                offset: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn checksums_are_verified() {
+        let bytes = tgph(ColumnEncoding::Compact);
+        let mut checked = TGPH::deserialize_from(&mut Cursor::new(&bytes)).unwrap();
+        checked.column_encoding = ColumnEncoding::Compact;
+        checked.checksums = true;
+        let mut bytes = Vec::new();
+        checked.serialize_into(&mut bytes).unwrap();
+
+        let mut decoder = StreamDecoder::new();
+        let mut events = Vec::new();
+        for chunk in compress(&bytes, CompressionLvl::default()).chunks(7) {
+            decoder.push(chunk).unwrap();
+            events.extend(std::iter::from_fn(|| decoder.next_event()));
+        }
+        decoder.finish().unwrap();
+        assert_eq!(events.last(), Some(&Event::End));
+        assert_eq!(containers(events), checked.containers);
+
+        // In the last element of "Ratio", just before its checksum.
+        let (_, layout) = TGPH::deserialize_with_layout_from(&mut Cursor::new(&bytes)).unwrap();
+        let mut damaged = bytes.clone();
+        damaged[(layout[4].offset + layout[4].length) as usize - 5] ^= 0x01;
+        let gz = compress(&damaged, CompressionLvl::default());
+        assert!(matches!(
+            StreamDecoder::new().push(&gz),
+            Err(TgphError::ChecksumMismatch { container: Some(name), .. }) if name == "Ratio"
+        ));
+
+        let mut damaged = bytes;
+        let end = damaged.len() - 1;
+        damaged[end] ^= 0x01;
+        let gz = compress(&damaged, CompressionLvl::default());
+        assert!(matches!(
+            StreamDecoder::new().push(&gz),
+            Err(TgphError::ChecksumMismatch {
+                container: None,
+                ..
+            })
+        ));
+    }
+}
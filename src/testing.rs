@@ -0,0 +1,12 @@
+//! Helpers shared by the tests of the binary.
+
+use std::{fs, path::PathBuf};
+
+/// An empty directory for a test, named after the process so that
+/// concurrent test runs do not share it.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tinygraph-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -1,733 +1,3453 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::io::{Read, Write};
+#![allow(clippy::upper_case_acronyms)]
 
-pub trait BaseContainerElementType {
-    fn push_element(&self, tgph: &mut TGPH, name: &str);
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::column_encoding::{decode_floats, decode_integers, encode_floats, encode_integers};
+
+pub(crate) const TGPH_MAGIC: u32 = 0x48504754;
+/// U32, FLOAT32 and STRING elements only.
+pub(crate) const TGPH_VERSION_1: u8 = 1;
+/// Adds the U64, I64 and FLOAT64 element types.
+const TGPH_VERSION_2: u8 = 2;
+/// Adds per container metadata, see `ContainerMetadata`.
+pub(crate) const TGPH_VERSION_3: u8 = 3;
+/// Adds the compact encodings of numeric containers, see `ColumnEncoding`.
+pub(crate) const TGPH_VERSION_4: u8 = 4;
+/// Adds a CRC32C after every container and after the last one, see
+/// `TGPH::checksums`.
+pub(crate) const TGPH_VERSION_5: u8 = 5;
+/// Adds a flags byte after the container count, see `INDEX_FLAG`.
+pub(crate) const TGPH_VERSION_6: u8 = 6;
+pub(crate) const TGPH_LATEST_VERSION: u8 = TGPH_VERSION_6;
+
+/// Set in the element type byte of containers stored with
+/// `ColumnEncoding::Compact`.
+pub(crate) const ENCODED_FLAG: u8 = 0x80;
+
+/// Set in the header flags of a TGPH written with `TGPH::container_index`.
+const INDEX_FLAG: u8 = 0x01;
+/// Last 4 bytes of a TGPH written with `TGPH::container_index`, "TGPI".
+const INDEX_MAGIC: u32 = 0x49504754;
+/// Index length, index checksum and magic at the very end of an indexed TGPH.
+const INDEX_TRAILER_SIZE: u64 = 12;
+
+/// Length of the magic, version, container count and, since version 6, the
+/// flags.
+fn header_size(version: u8) -> u64 {
+    match version >= TGPH_VERSION_6 {
+        true => 8,
+        false => 7,
+    }
 }
 
-impl BaseContainerElementType for String {
-    fn push_element(&self, tgph: &mut TGPH, name: &str) {
-        let container = match tgph.containers.iter_mut().find(|c| c.name == name) {
-            Some(v) => v,
-            None => {
-                let new = TGPHContainer {
-                    name: name.to_string(),
-                    elements: ElementArrayType::STRING(Vec::new()),
-                };
+/// Everything that can go wrong while reading or appending to a TGPH. Offsets
+/// are byte positions in the uncompressed stream at which the problem was
+/// detected.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TgphError {
+    BadMagic {
+        found: u32,
+    },
+    UnsupportedVersion {
+        version: u8,
+        offset: u64,
+    },
+    UnknownElementType {
+        element_type: u8,
+        offset: u64,
+    },
+    UnknownMetricKind {
+        kind: u8,
+        offset: u64,
+    },
+    InvalidUtf8 {
+        offset: u64,
+    },
+    InvalidEncoding {
+        offset: u64,
+    },
+    Truncated {
+        offset: u64,
+    },
+    Io {
+        offset: u64,
+        source: std::io::Error,
+    },
+    TypeMismatch {
+        name: String,
+        container_type: &'static str,
+        element_type: &'static str,
+    },
+    /// The container index does not match its checksum, points outside of
+    /// the containers or at another container than it names.
+    InvalidIndex {
+        offset: u64,
+    },
+    /// The CRC32C stored at `offset` does not match the bytes before it, of
+    /// `container` or of the whole file if there is none.
+    ChecksumMismatch {
+        container: Option<String>,
+        offset: u64,
+    },
+}
 
-                tgph.add_container(new);
-                tgph.containers.last_mut().unwrap()
+impl fmt::Display for TgphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic { found } => write!(f, "bad magic 0x{found:08x}, not a TGPH stream"),
+            Self::UnsupportedVersion { version, offset } => {
+                write!(f, "unsupported TGPH version {version} at byte {offset}")
             }
-        };
+            Self::UnknownElementType {
+                element_type,
+                offset,
+            } => write!(f, "unknown element type {element_type} at byte {offset}"),
+            Self::UnknownMetricKind { kind, offset } => {
+                write!(f, "unknown metric kind {kind} at byte {offset}")
+            }
+            Self::InvalidUtf8 { offset } => write!(f, "invalid UTF-8 string at byte {offset}"),
+            Self::InvalidEncoding { offset } => {
+                write!(f, "invalid compact encoding at byte {offset}")
+            }
+            Self::Truncated { offset } => write!(f, "stream truncated at byte {offset}"),
+            Self::Io { offset, source } => write!(f, "I/O error at byte {offset}: {source}"),
+            Self::TypeMismatch {
+                name,
+                container_type,
+                element_type,
+            } => write!(
+                f,
+                "cannot append {element_type} to {container_type} container \"{name}\""
+            ),
+            Self::InvalidIndex { offset } => write!(f, "invalid container index at byte {offset}"),
+            Self::ChecksumMismatch {
+                container: Some(name),
+                offset,
+            } => write!(
+                f,
+                "checksum mismatch of container \"{name}\" at byte {offset}, it is damaged"
+            ),
+            Self::ChecksumMismatch {
+                container: None,
+                offset,
+            } => write!(
+                f,
+                "checksum mismatch of the file at byte {offset}, it is damaged"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TgphError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<TgphError> for std::io::Error {
+    fn from(err: TgphError) -> Self {
+        match err {
+            TgphError::Io { source, .. } => source,
+            err => std::io::Error::new(std::io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+/// Reader wrapper keeping the CRC32C of everything read and of the current
+/// container, to verify the checksums of version 5. Files of older versions
+/// have none, so it only passes their bytes through.
+struct ChecksumReader<'a, R: Read> {
+    inner: &'a mut R,
+    checksum: u32,
+    container_checksum: u32,
+    active: bool,
+}
+
+impl<'a, R: Read> ChecksumReader<'a, R> {
+    /// For a stream starting with the header, which is part of the file
+    /// checksum. Call `read_version` once it was read.
+    fn new(inner: &'a mut R) -> Self {
+        Self {
+            inner,
+            checksum: 0,
+            container_checksum: 0,
+            active: true,
+        }
+    }
+
+    /// For the containers of a file of `version`.
+    fn for_version(inner: &'a mut R, version: u8) -> Self {
+        Self {
+            active: version >= TGPH_VERSION_5,
+            ..Self::new(inner)
+        }
+    }
+
+    fn read_version(&mut self, version: u8) {
+        self.active = version >= TGPH_VERSION_5;
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if self.active {
+            self.checksum = crc32c::crc32c_append(self.checksum, &buf[..read]);
+            self.container_checksum = crc32c::crc32c_append(self.container_checksum, &buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+/// Reader wrapper that remembers how many bytes were consumed so far, so that
+/// errors can point at the place in the stream where they happened.
+struct OffsetReader<'a, R: Read> {
+    inner: &'a mut R,
+    offset: u64,
+}
+
+impl<'a, R: Read> OffsetReader<'a, R> {
+    fn new(inner: &'a mut R) -> Self {
+        Self::at(inner, 0)
+    }
+
+    /// For a stream positioned at `offset`.
+    fn at(inner: &'a mut R, offset: u64) -> Self {
+        Self { inner, offset }
+    }
 
-        if let ElementArrayType::STRING(elements) = &mut container.elements {
-            elements.push(self.clone());
-            while elements.len() > tgph.entry_limit {
-                elements.remove(0);
+    fn error(&self, err: std::io::Error) -> TgphError {
+        if err.kind() == std::io::ErrorKind::UnexpectedEof {
+            TgphError::Truncated {
+                offset: self.offset,
             }
         } else {
-            unreachable!();
+            TgphError::Io {
+                offset: self.offset,
+                source: err,
+            }
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, TgphError> {
+        let v = self.inner.read_u8().map_err(|e| self.error(e))?;
+        self.offset += 1;
+        Ok(v)
+    }
+
+    fn u16(&mut self) -> Result<u16, TgphError> {
+        let v = self
+            .inner
+            .read_u16::<LittleEndian>()
+            .map_err(|e| self.error(e))?;
+        self.offset += 2;
+        Ok(v)
+    }
+
+    fn u32(&mut self) -> Result<u32, TgphError> {
+        let v = self
+            .inner
+            .read_u32::<LittleEndian>()
+            .map_err(|e| self.error(e))?;
+        self.offset += 4;
+        Ok(v)
+    }
+
+    fn u64(&mut self) -> Result<u64, TgphError> {
+        let v = self
+            .inner
+            .read_u64::<LittleEndian>()
+            .map_err(|e| self.error(e))?;
+        self.offset += 8;
+        Ok(v)
+    }
+
+    fn i64(&mut self) -> Result<i64, TgphError> {
+        let v = self
+            .inner
+            .read_i64::<LittleEndian>()
+            .map_err(|e| self.error(e))?;
+        self.offset += 8;
+        Ok(v)
+    }
+
+    fn f64(&mut self) -> Result<f64, TgphError> {
+        let v = self
+            .inner
+            .read_f64::<LittleEndian>()
+            .map_err(|e| self.error(e))?;
+        self.offset += 8;
+        Ok(v)
+    }
+
+    fn f32(&mut self) -> Result<f32, TgphError> {
+        let v = self
+            .inner
+            .read_f32::<LittleEndian>()
+            .map_err(|e| self.error(e))?;
+        self.offset += 4;
+        Ok(v)
+    }
+
+    fn bytes(&mut self, buf: &mut [u8]) -> Result<(), TgphError> {
+        self.inner.read_exact(buf).map_err(|e| self.error(e))?;
+        self.offset += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Reads `length` bytes without trusting a possibly corrupted `length`
+    /// enough to allocate all of it up front.
+    fn byte_vec(&mut self, length: u32) -> Result<Vec<u8>, TgphError> {
+        let mut buf = Vec::with_capacity(length.min(1 << 16) as usize);
+        let read = (&mut *self.inner)
+            .take(length as u64)
+            .read_to_end(&mut buf)
+            .map_err(|e| self.error(e))?;
+        self.offset += read as u64;
+
+        if read < length as usize {
+            return Err(TgphError::Truncated {
+                offset: self.offset,
+            });
         }
+        Ok(buf)
     }
 }
 
-impl BaseContainerElementType for u32 {
-    fn push_element(&self, tgph: &mut TGPH, name: &str) {
-        let container = match tgph.containers.iter_mut().find(|c| c.name == name) {
-            Some(v) => v,
-            None => {
-                let new = TGPHContainer {
-                    name: name.to_string(),
-                    elements: ElementArrayType::U32(Vec::new()),
-                };
+pub trait BaseContainerElementType {
+    fn push_element(&self, tgph: &mut TGPH, name: &str) -> Result<(), TgphError>;
+}
 
-                tgph.add_container(new);
-                tgph.containers.last_mut().unwrap()
-            }
-        };
+fn push_trimmed<T>(elements: &mut VecDeque<T>, element: T, entry_limit: usize) {
+    elements.push_back(element);
+    while elements.len() > entry_limit {
+        elements.pop_front();
+    }
+}
+
+/// A single element on its way into a container.
+enum ElementValue {
+    U32(u32),
+    FLOAT32(f32),
+    STRING(String),
+    U64(u64),
+    I64(i64),
+    FLOAT64(f64),
+}
+
+impl ElementValue {
+    fn element_type(&self) -> ElementType {
+        match self {
+            Self::U32(_) => ElementType::U32,
+            Self::FLOAT32(_) => ElementType::FLOAT32,
+            Self::STRING(_) => ElementType::STRING,
+            Self::U64(_) => ElementType::U64,
+            Self::I64(_) => ElementType::I64,
+            Self::FLOAT64(_) => ElementType::FLOAT64,
+        }
+    }
+
+    fn as_f64(&self) -> f64 {
+        match *self {
+            Self::U32(v) => v as f64,
+            Self::FLOAT32(v) => v as f64,
+            Self::U64(v) => v as f64,
+            Self::I64(v) => v as f64,
+            Self::FLOAT64(v) => v,
+            Self::STRING(_) => unreachable!("strings are never converted"),
+        }
+    }
+
+    fn as_i64(&self) -> i64 {
+        match *self {
+            Self::U32(v) => v as i64,
+            Self::U64(v) => v as i64,
+            Self::I64(v) => v,
+            _ => self.as_f64() as i64,
+        }
+    }
+
+    fn as_u64(&self) -> u64 {
+        match *self {
+            Self::U32(v) => v as u64,
+            Self::U64(v) => v,
+            _ => self.as_i64() as u64,
+        }
+    }
+}
 
-        if let ElementArrayType::U32(elements) = &mut container.elements {
-            elements.push(*self);
-            while elements.len() > tgph.entry_limit {
-                elements.remove(0);
+fn push_value(tgph: &mut TGPH, name: &str, value: ElementValue) -> Result<(), TgphError> {
+    let (entry_limit, policy) = (tgph.entry_limit, tgph.type_mismatch_policy);
+    let value_type = value.element_type();
+    let container = tgph.container_or_insert(name, || value_type.empty_elements());
+
+    let container_type = container.elements.element_type();
+    if container_type != value_type {
+        match container_type.widened_with(value_type) {
+            Some(target) if policy == TypeMismatchPolicy::Widen => {
+                container.elements.convert_to(target)
             }
-        } else {
-            unreachable!();
+            _ => return Err(container.type_mismatch(value_type)),
+        }
+    }
+
+    // The container now either has the type of the value, or was widened to
+    // a type the value can be converted to without losing its magnitude.
+    match (&mut container.elements, value) {
+        (ElementArrayType::STRING(elements), ElementValue::STRING(v)) => {
+            push_trimmed(elements, v, entry_limit)
+        }
+        (ElementArrayType::U32(elements), ElementValue::U32(v)) => {
+            push_trimmed(elements, v, entry_limit)
+        }
+        (ElementArrayType::FLOAT32(elements), v) => {
+            push_trimmed(elements, v.as_f64() as f32, entry_limit)
         }
+        (ElementArrayType::U64(elements), v) => push_trimmed(elements, v.as_u64(), entry_limit),
+        (ElementArrayType::I64(elements), v) => push_trimmed(elements, v.as_i64(), entry_limit),
+        (ElementArrayType::FLOAT64(elements), v) => push_trimmed(elements, v.as_f64(), entry_limit),
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+impl BaseContainerElementType for String {
+    fn push_element(&self, tgph: &mut TGPH, name: &str) -> Result<(), TgphError> {
+        push_value(tgph, name, ElementValue::STRING(self.clone()))
+    }
+}
+
+impl BaseContainerElementType for u32 {
+    fn push_element(&self, tgph: &mut TGPH, name: &str) -> Result<(), TgphError> {
+        push_value(tgph, name, ElementValue::U32(*self))
     }
 }
 
 impl BaseContainerElementType for f32 {
-    fn push_element(&self, tgph: &mut TGPH, name: &str) {
-        let container = match tgph.containers.iter_mut().find(|c| c.name == name) {
-            Some(v) => v,
-            None => {
-                let new = TGPHContainer {
-                    name: name.to_string(),
-                    elements: ElementArrayType::FLOAT32(Vec::new()),
-                };
+    fn push_element(&self, tgph: &mut TGPH, name: &str) -> Result<(), TgphError> {
+        push_value(tgph, name, ElementValue::FLOAT32(*self))
+    }
+}
 
-                tgph.add_container(new);
-                tgph.containers.last_mut().unwrap()
-            }
-        };
+impl BaseContainerElementType for u64 {
+    fn push_element(&self, tgph: &mut TGPH, name: &str) -> Result<(), TgphError> {
+        push_value(tgph, name, ElementValue::U64(*self))
+    }
+}
 
-        if let ElementArrayType::FLOAT32(elements) = &mut container.elements {
-            elements.push(*self);
-            while elements.len() > tgph.entry_limit {
-                elements.remove(0);
-            }
-        } else {
-            unreachable!();
+impl BaseContainerElementType for i64 {
+    fn push_element(&self, tgph: &mut TGPH, name: &str) -> Result<(), TgphError> {
+        push_value(tgph, name, ElementValue::I64(*self))
+    }
+}
+
+impl BaseContainerElementType for f64 {
+    fn push_element(&self, tgph: &mut TGPH, name: &str) -> Result<(), TgphError> {
+        push_value(tgph, name, ElementValue::FLOAT64(*self))
+    }
+}
+
+/// What `TGPH::append` does with a value whose type differs from the type of
+/// the container it is appended to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TypeMismatchPolicy {
+    /// Leave the container untouched and return `TgphError::TypeMismatch`.
+    #[default]
+    Reject,
+    /// Convert numeric containers in place to a type that fits both the old
+    /// elements and the new value, e.g. U32 becomes U64 or FLOAT32 and
+    /// FLOAT32 becomes FLOAT64. Strings are never converted.
+    Widen,
+}
+
+/// How `TGPH::serialize_into` stores the elements of numeric containers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColumnEncoding {
+    /// Every element as a plain little endian value, readable by any version.
+    #[default]
+    Raw,
+    /// Delta-of-delta for integers and XOR for floats (see
+    /// `column_encoding`), which needs a version 4 reader. Strings are always
+    /// stored raw.
+    Compact,
+}
+
+/// How long elements are kept, on top of `TGPH::entry_limit`. Ages are
+/// measured with the time axis of each container, see `ContainerMetadata`.
+#[derive(Clone, Debug, Default)]
+pub struct Retention {
+    /// Seconds, `None` keeps elements until `entry_limit` trims them.
+    pub max_age: Option<u64>,
+    /// `max_age` of single containers, by container name.
+    pub overrides: HashMap<String, Option<u64>>,
+    /// `max_age` of all containers whose name starts with the prefix, used
+    /// when there is no override for the exact name.
+    pub prefix_overrides: Vec<(String, Option<u64>)>,
+}
+
+impl Retention {
+    pub fn max_age_of(&self, name: &str) -> Option<u64> {
+        if let Some(&max_age) = self.overrides.get(name) {
+            return max_age;
         }
+        self.prefix_overrides
+            .iter()
+            .find(|(prefix, _)| name.starts_with(prefix.as_str()))
+            .map_or(self.max_age, |&(_, max_age)| max_age)
+    }
+}
+
+/// The longer of two ages, where `None` is forever.
+fn longest_age(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    Some(a?.max(b?))
+}
+
+/// Byte range of a container in an uncompressed TGPH.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContainerLayout {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// A container as listed in the index at the end of a TGPH written with
+/// `TGPH::container_index`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub name: String,
+    pub element_type: ElementType,
+    pub element_count: u32,
+    pub layout: ContainerLayout,
+}
+
+/// What `TGPH::salvage` found out about one container.
+#[derive(Debug)]
+pub struct ContainerCheck {
+    /// `None` if the container is too damaged to tell and the TGPH has no
+    /// index naming it.
+    pub name: Option<String>,
+    pub offset: u64,
+    /// Why the container was not salvaged, `None` if it is intact.
+    pub error: Option<TgphError>,
+}
+
+/// Result of `TGPH::salvage`.
+pub struct Salvage {
+    /// The intact containers.
+    pub tgph: TGPH,
+    /// Every container that could be found, in the order they are stored in.
+    pub containers: Vec<ContainerCheck>,
+    /// Containers that could not be found after a damaged one, only happens
+    /// without an index.
+    pub lost: u16,
+    /// Mismatch of the checksum of the whole file, or why it could not be
+    /// read. Not checked if the last container is damaged.
+    pub file_error: Option<TgphError>,
+}
+
+impl Salvage {
+    pub fn is_intact(&self) -> bool {
+        self.lost == 0
+            && self.file_error.is_none()
+            && self.containers.iter().all(|c| c.error.is_none())
+    }
+}
+
+/// Writer wrapper counting the bytes written, to know where every container
+/// starts, and keeping their CRC32C like `ChecksumReader`.
+struct CountingWriter<'a, W: Write> {
+    inner: &'a mut W,
+    count: u64,
+    checksum: u32,
+    container_checksum: u32,
+}
+
+impl<W: Write> Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        self.checksum = crc32c::crc32c_append(self.checksum, &buf[..written]);
+        self.container_checksum = crc32c::crc32c_append(self.container_checksum, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
     }
 }
 
+#[derive(Clone)]
 pub struct TGPH {
     magic: u32,
     version: u8,
     pub containers: Vec<TGPHContainer>,
 
     pub entry_limit: usize,
+    pub type_mismatch_policy: TypeMismatchPolicy,
+    pub column_encoding: ColumnEncoding,
+    /// Write an index of the containers after the last one, so that
+    /// `TGPH::read_container` can seek straight to a single container.
+    /// Needs format version 6, which marks it in the header. Set when read
+    /// from a file that has one.
+    pub container_index: bool,
+    /// Write a CRC32C after every container and one of the whole file,
+    /// checked when reading. Needs format version 5. Set when read from a
+    /// file that has them.
+    pub checksums: bool,
+    replaced: Vec<String>,
 }
 
 impl Default for TGPH {
     fn default() -> Self {
         Self {
-            magic: 0x48504754,
-            version: 1,
+            magic: TGPH_MAGIC,
+            version: TGPH_VERSION_1,
             containers: Vec::default(),
             entry_limit: 1000,
+            type_mismatch_policy: TypeMismatchPolicy::default(),
+            column_encoding: ColumnEncoding::default(),
+            container_index: false,
+            checksums: false,
+            replaced: Vec::new(),
         }
     }
 }
 
 impl TGPH {
     pub fn new(entry_limit: usize) -> Self {
-        let mut def = Self::default();
-        def.entry_limit = entry_limit;
-        def
+        Self {
+            entry_limit,
+            ..Self::default()
+        }
     }
 }
 
 impl TGPH {
     pub fn serialize_into<W: Write>(self: &TGPH, stream: &mut W) -> Result<(), std::io::Error> {
+        let mut stream = CountingWriter {
+            inner: stream,
+            count: 0,
+            checksum: 0,
+            container_checksum: 0,
+        };
         stream.write_all(&self.magic.to_le_bytes())?;
-        stream.write_all(&self.version.to_le_bytes())?;
+        let version = self.required_version();
+        stream.write_all(&version.to_le_bytes())?;
         stream.write_all(&(self.containers.len() as u16).to_le_bytes())?;
+        if version >= TGPH_VERSION_6 {
+            let flags = match self.container_index {
+                true => INDEX_FLAG,
+                false => 0,
+            };
+            stream.write_all(&flags.to_le_bytes())?;
+        }
+
+        let mut index = Vec::new();
         for container in self.containers.iter() {
-            container.serialize_into(stream)?;
+            let offset = stream.count;
+            stream.container_checksum = 0;
+            container.serialize_into(&mut stream, version, self.column_encoding)?;
+            if version >= TGPH_VERSION_5 {
+                let checksum = stream.container_checksum;
+                stream.write_all(&checksum.to_le_bytes())?;
+            }
+            index.push(IndexEntry {
+                name: container.name.clone(),
+                element_type: container.elements.element_type(),
+                element_count: container.elements.len() as u32,
+                layout: ContainerLayout {
+                    offset,
+                    length: stream.count - offset,
+                },
+            });
+        }
+        if version >= TGPH_VERSION_5 {
+            let checksum = stream.checksum;
+            stream.write_all(&checksum.to_le_bytes())?;
+        }
+
+        if self.container_index {
+            Self::serialize_index_into(stream.inner, &index)?;
         }
         Ok(())
     }
 
-    pub fn deserialize_from<R: Read>(stream: &mut R) -> Result<Self, std::io::Error> {
-        let mut result = Self::default();
-        result.magic = stream.read_u32::<LittleEndian>()?;
-        result.version = stream.read_u8()?;
+    /// Writes the entry count, the entries, the length and CRC32C of both and
+    /// `INDEX_MAGIC`.
+    fn serialize_index_into<W: Write>(
+        stream: &mut W,
+        index: &[IndexEntry],
+    ) -> Result<(), std::io::Error> {
+        let mut bytes = Vec::new();
+        bytes.extend((index.len() as u16).to_le_bytes());
+        for entry in index {
+            TGPHContainer::serialize_string_into(&mut bytes, &entry.name)?;
+            bytes.push(entry.element_type as u8);
+            bytes.extend(entry.element_count.to_le_bytes());
+            bytes.extend(entry.layout.offset.to_le_bytes());
+            bytes.extend(entry.layout.length.to_le_bytes());
+        }
 
-        let container_num = stream.read_u16::<LittleEndian>()?;
+        stream.write_all(&bytes)?;
+        stream.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        stream.write_all(&crc32c::crc32c(&bytes).to_le_bytes())?;
+        stream.write_all(&INDEX_MAGIC.to_le_bytes())
+    }
 
-        for _ in 0..container_num {
-            result
-                .containers
-                .push(TGPHContainer::deserialize_from(stream)?);
+    /// Magic, version, container count and flags, which are 0 before
+    /// version 6.
+    fn deserialize_header_from<R: Read>(
+        stream: &mut OffsetReader<R>,
+    ) -> Result<(u32, u8, u16, u8), TgphError> {
+        let magic = stream.u32()?;
+        if magic != TGPH_MAGIC {
+            return Err(TgphError::BadMagic { found: magic });
+        }
+
+        let version_offset = stream.offset;
+        let version = stream.u8()?;
+        if !(TGPH_VERSION_1..=TGPH_LATEST_VERSION).contains(&version) {
+            return Err(TgphError::UnsupportedVersion {
+                version,
+                offset: version_offset,
+            });
         }
 
-        Ok(result)
+        let container_num = stream.u16()?;
+        let flags = match version >= TGPH_VERSION_6 {
+            true => stream.u8()?,
+            false => 0,
+        };
+        Ok((magic, version, container_num, flags))
     }
 
-    pub fn remove_container(&mut self, name: &str) {
-        match self.containers.iter_mut().position(|c| c.name == name) {
-            Some(index) => {
-                self.containers.remove(index);
+    /// Reads a container and, since version 5, verifies the checksum after
+    /// it.
+    fn deserialize_container_from<R: Read>(
+        stream: &mut OffsetReader<ChecksumReader<R>>,
+        version: u8,
+    ) -> Result<TGPHContainer, TgphError> {
+        stream.inner.container_checksum = 0;
+        let container = TGPHContainer::deserialize_from(stream, version)?;
+        if version >= TGPH_VERSION_5 {
+            let computed = stream.inner.container_checksum;
+            let offset = stream.offset;
+            if stream.u32()? != computed {
+                return Err(TgphError::ChecksumMismatch {
+                    container: Some(container.name),
+                    offset,
+                });
             }
-            None => {}
         }
+        Ok(container)
     }
 
-    pub fn add_container(&mut self, container: TGPHContainer) {
-        self.containers.push(container);
-    }
-
-    pub fn append<T: BaseContainerElementType>(&mut self, data: T, name: &str) {
-        data.push_element(self, name);
+    /// Verifies the checksum of the whole file following the last container
+    /// since version 5.
+    fn verify_checksum_from<R: Read>(
+        stream: &mut OffsetReader<ChecksumReader<R>>,
+        version: u8,
+    ) -> Result<(), TgphError> {
+        if version < TGPH_VERSION_5 {
+            return Ok(());
+        }
+        let computed = stream.inner.checksum;
+        let offset = stream.offset;
+        match stream.u32()? == computed {
+            true => Ok(()),
+            false => Err(TgphError::ChecksumMismatch {
+                container: None,
+                offset,
+            }),
+        }
     }
 
-    pub fn replace<T: BaseContainerElementType>(&mut self, data: Vec<T>, name: &str) {
-        self.remove_container(name);
-        for elem in data {
-            self.append(elem, name);
+    /// The index at the end of `stream`, `None` if it was written without
+    /// `container_index`.
+    pub fn read_index<R: Read + Seek>(
+        stream: &mut R,
+    ) -> Result<Option<Vec<IndexEntry>>, TgphError> {
+        let io_error = |offset| move |source| TgphError::Io { offset, source };
+
+        stream.seek(SeekFrom::Start(0)).map_err(io_error(0))?;
+        let (_, version, _, flags) = Self::deserialize_header_from(&mut OffsetReader::new(stream))?;
+        if flags & INDEX_FLAG == 0 {
+            return Ok(None);
         }
-    }
-}
 
-pub enum ElementArrayType {
-    U32(Vec<u32>),
-    FLOAT32(Vec<f32>),
-    STRING(Vec<String>),
-}
+        let end = stream.seek(SeekFrom::End(0)).map_err(io_error(0))?;
+        let Some(trailer) = end.checked_sub(INDEX_TRAILER_SIZE) else {
+            return Err(TgphError::Truncated { offset: end });
+        };
+        stream
+            .seek(SeekFrom::Start(trailer))
+            .map_err(io_error(trailer))?;
+        let mut reader = OffsetReader::at(stream, trailer);
+        let length = reader.u32()? as u64;
+        let checksum = reader.u32()?;
+        if reader.u32()? != INDEX_MAGIC || header_size(version) + length > trailer {
+            return Err(TgphError::InvalidIndex { offset: trailer });
+        }
 
-impl ElementArrayType {
-    fn get_index(&self) -> u8 {
-        match self {
-            Self::U32(_) => 1,
-            Self::FLOAT32(_) => 2,
-            Self::STRING(_) => 3,
+        let start = trailer - length;
+        stream
+            .seek(SeekFrom::Start(start))
+            .map_err(io_error(start))?;
+        let bytes = OffsetReader::at(stream, start).byte_vec(length as u32)?;
+        if crc32c::crc32c(&bytes) != checksum {
+            return Err(TgphError::InvalidIndex { offset: trailer });
         }
-    }
-}
 
-pub struct TGPHContainer {
-    pub name: String,
-    pub elements: ElementArrayType,
-}
+        let mut bytes = bytes.as_slice();
+        let mut reader = OffsetReader::at(&mut bytes, start);
+        let count = reader.u16()?;
+        let mut index = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let entry_offset = reader.offset;
+            let name = TGPHContainer::deserialize_string_from(&mut reader)?;
+            let type_offset = reader.offset;
+            let element_type = reader.u8()?;
+            let element_type =
+                ElementType::from_index(element_type).ok_or(TgphError::UnknownElementType {
+                    element_type,
+                    offset: type_offset,
+                })?;
+            let element_count = reader.u32()?;
+            let layout = ContainerLayout {
+                offset: reader.u64()?,
+                length: reader.u64()?,
+            };
 
-impl TGPHContainer {
-    fn serialize_string_into<W: Write>(stream: &mut W, string: &str) -> Result<(), std::io::Error> {
-        if string.len() >= 255 {
-            stream.write_all(&0xff_u8.to_le_bytes())?;
-            stream.write_all(&(string.len() as u16).to_le_bytes())?;
-            stream.write_all(string.as_bytes())?;
-        } else {
-            stream.write_all(&(string.len() as u8).to_le_bytes())?;
-            stream.write_all(string.as_bytes())?;
+            let in_range = layout.offset >= header_size(version)
+                && layout
+                    .offset
+                    .checked_add(layout.length)
+                    .is_some_and(|e| e <= start);
+            if !in_range {
+                return Err(TgphError::InvalidIndex {
+                    offset: entry_offset,
+                });
+            }
+            index.push(IndexEntry {
+                name,
+                element_type,
+                element_count,
+                layout,
+            });
         }
 
-        Ok(())
+        Ok(Some(index))
     }
 
-    pub fn serialize_into<W: Write>(&self, stream: &mut W) -> Result<(), std::io::Error> {
-        TGPHContainer::serialize_string_into(stream, &self.name)?;
+    /// Reads just the container called `name`. Seeks straight to it if the
+    /// TGPH has an index, and reads through the containers before it
+    /// otherwise.
+    pub fn read_container<R: Read + Seek>(
+        stream: &mut R,
+        name: &str,
+    ) -> Result<Option<TGPHContainer>, TgphError> {
+        stream
+            .seek(SeekFrom::Start(0))
+            .map_err(|source| TgphError::Io { offset: 0, source })?;
+        let (_, version, container_num, _) =
+            Self::deserialize_header_from(&mut OffsetReader::new(stream))?;
+
+        let Some(index) = Self::read_index(stream)? else {
+            let start = header_size(version);
+            stream
+                .seek(SeekFrom::Start(start))
+                .map_err(|source| TgphError::Io {
+                    offset: start,
+                    source,
+                })?;
+            let mut stream = ChecksumReader::for_version(stream, version);
+            let mut reader = OffsetReader::at(&mut stream, start);
+            for _ in 0..container_num {
+                let container = Self::deserialize_container_from(&mut reader, version)?;
+                if container.name == name {
+                    return Ok(Some(container));
+                }
+            }
+            return Ok(None);
+        };
 
-        stream.write_all(&self.elements.get_index().to_le_bytes())?;
-        let elements_len = match &self.elements {
-            ElementArrayType::U32(arr) => arr.len() as u32,
-            ElementArrayType::FLOAT32(arr) => arr.len() as u32,
-            ElementArrayType::STRING(arr) => arr.len() as u32,
+        let Some(entry) = index.into_iter().find(|e| e.name == name) else {
+            return Ok(None);
         };
+        let offset = entry.layout.offset;
+        stream
+            .seek(SeekFrom::Start(offset))
+            .map_err(|source| TgphError::Io { offset, source })?;
+        let mut stream = ChecksumReader::for_version(stream, version);
+        let container =
+            Self::deserialize_container_from(&mut OffsetReader::at(&mut stream, offset), version)?;
+        if container.name != name {
+            return Err(TgphError::InvalidIndex { offset });
+        }
+        Ok(Some(container))
+    }
 
-        stream.write_all(&(elements_len).to_le_bytes())?;
+    /// Reads what is still intact of a damaged uncompressed TGPH, checking
+    /// every container on its own. After a container that cannot be parsed
+    /// the next one is found through the index, the containers after it are
+    /// lost without one. Only fails if the header is damaged.
+    pub fn salvage(bytes: &[u8]) -> Result<Salvage, TgphError> {
+        let (magic, version, container_num, flags) =
+            Self::deserialize_header_from(&mut OffsetReader::new(&mut &bytes[..]))?;
+        let index = match Self::read_index(&mut std::io::Cursor::new(bytes)) {
+            Ok(Some(index)) if index.len() == container_num as usize => Some(index),
+            _ => None,
+        };
 
-        if elements_len > 0 {
-            match &self.elements {
-                ElementArrayType::U32(arr) => {
-                    for e in arr {
-                        stream.write_all(&e.to_le_bytes())?;
-                    }
-                }
-                ElementArrayType::FLOAT32(arr) => {
-                    for e in arr {
-                        stream.write_all(&e.to_le_bytes())?;
+        let mut salvage = Salvage {
+            tgph: Self {
+                magic,
+                version,
+                container_index: flags & INDEX_FLAG != 0,
+                checksums: version >= TGPH_VERSION_5,
+                ..Self::default()
+            },
+            containers: Vec::with_capacity(container_num as usize),
+            lost: 0,
+            file_error: None,
+        };
+        // Where the next container starts, unknown after one that could not
+        // be parsed.
+        let mut next = Some(header_size(version));
+        for i in 0..container_num as usize {
+            let entry = index.as_ref().map(|index| &index[i]);
+            let Some(offset) = entry.map(|e| e.layout.offset).or(next) else {
+                salvage.lost = container_num - i as u16;
+                break;
+            };
+
+            let mut rest = bytes.get(offset as usize..).unwrap_or_default();
+            let mut stream = ChecksumReader::for_version(&mut rest, version);
+            let mut reader = OffsetReader::at(&mut stream, offset);
+            let result = Self::deserialize_container_from(&mut reader, version);
+            let end = reader.offset;
+            let check = match result {
+                Ok(container) => {
+                    next = Some(end);
+                    let name = Some(container.name.clone());
+                    salvage.tgph.containers.push(container);
+                    ContainerCheck {
+                        name,
+                        offset,
+                        error: None,
                     }
                 }
-                ElementArrayType::STRING(arr) => {
-                    for e in arr {
-                        TGPHContainer::serialize_string_into(stream, e)?;
+                Err(err) => {
+                    // The checksum comes last, so the container still ends
+                    // where it was read up to.
+                    next = match err {
+                        TgphError::ChecksumMismatch { .. } => Some(end),
+                        _ => None,
+                    };
+                    let name = match &err {
+                        TgphError::ChecksumMismatch { container, .. } => container.clone(),
+                        _ => entry.map(|e| e.name.clone()),
+                    };
+                    ContainerCheck {
+                        name,
+                        offset,
+                        error: Some(err),
                     }
                 }
             };
+            salvage.containers.push(check);
         }
 
-        Ok(())
-    }
-
-    fn deserialize_string_from<R: Read>(stream: &mut R) -> Result<String, std::io::Error> {
-        let mut length: u16 = stream.read_u8()? as u16;
-
-        if length == 0xff {
-            length = stream.read_u16::<LittleEndian>()?;
+        // The file checksum follows the last container, so it can only be
+        // found if that one could be read.
+        if let Some(end) = next.filter(|_| version >= TGPH_VERSION_5 && salvage.lost == 0) {
+            let computed = crc32c::crc32c(&bytes[..end as usize]);
+            let mut rest = &bytes[end as usize..];
+            salvage.file_error = match OffsetReader::at(&mut rest, end).u32() {
+                Ok(checksum) if checksum == computed => None,
+                Ok(_) => Some(TgphError::ChecksumMismatch {
+                    container: None,
+                    offset: end,
+                }),
+                Err(err) => Some(err),
+            };
         }
 
-        let mut buf = vec![0u8; length as usize];
-        stream.read(buf.as_mut_slice())?;
+        Ok(salvage)
+    }
 
-        Ok(String::from_utf8(buf).unwrap())
+    pub fn deserialize_from<R: Read>(stream: &mut R) -> Result<Self, TgphError> {
+        Self::deserialize_with_layout_from(stream).map(|(tgph, _)| tgph)
     }
 
-    pub fn deserialize_from<R: Read>(stream: &mut R) -> Result<Self, std::io::Error> {
+    /// Like `deserialize_from`, also returning where every container was
+    /// found in the uncompressed stream.
+    pub fn deserialize_with_layout_from<R: Read>(
+        stream: &mut R,
+    ) -> Result<(Self, Vec<ContainerLayout>), TgphError> {
+        let mut stream = ChecksumReader::new(stream);
+        let mut stream = OffsetReader::new(&mut stream);
+        let (magic, version, container_num, flags) = Self::deserialize_header_from(&mut stream)?;
+        stream.inner.read_version(version);
+
         let mut result = Self {
-            name: TGPHContainer::deserialize_string_from(stream)?,
-            elements: ElementArrayType::U32(vec![]),
+            magic,
+            version,
+            container_index: flags & INDEX_FLAG != 0,
+            checksums: version >= TGPH_VERSION_5,
+            ..Self::default()
         };
 
-        let element_type = stream.read_u8()?;
-        let element_count = stream.read_u32::<LittleEndian>()?;
+        let mut layout = Vec::with_capacity(container_num as usize);
+        for _ in 0..container_num {
+            let offset = stream.offset;
+            result
+                .containers
+                .push(Self::deserialize_container_from(&mut stream, version)?);
+            layout.push(ContainerLayout {
+                offset,
+                length: stream.offset - offset,
+            });
+        }
+        Self::verify_checksum_from(&mut stream, version)?;
 
-        let elements = match element_type {
-            1 => {
-                let mut elements = vec![];
-                for _ in 0..element_count {
-                    elements.push(stream.read_u32::<LittleEndian>()?);
-                }
-                ElementArrayType::U32(elements)
-            }
-            2 => {
-                let mut elements = vec![];
-                for _ in 0..element_count {
-                    elements.push(stream.read_f32::<LittleEndian>()?);
+        Ok((result, layout))
+    }
+
+    /// Format version the TGPH was read with. It is written with
+    /// `required_version`, which follows the current settings.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Oldest format version able to represent every container, so files
+    /// which do not use newer features stay readable by older readers.
+    pub fn required_version(&self) -> u8 {
+        let mut version = self
+            .containers
+            .iter()
+            .map(|c| c.required_version(self.column_encoding))
+            .max()
+            .unwrap_or(TGPH_VERSION_1);
+        if self.checksums {
+            version = version.max(TGPH_VERSION_5);
+        }
+        if self.container_index {
+            version = version.max(TGPH_VERSION_6);
+        }
+        version
+    }
+
+    pub fn container(&self, name: &str) -> Option<&TGPHContainer> {
+        self.containers.iter().find(|c| c.name == name)
+    }
+
+    /// Index in its time axis of the oldest element of `container`, `None`
+    /// if it has no time axis in this TGPH, see `TGPHContainer::offset_in`.
+    pub fn axis_offset(&self, container: &TGPHContainer) -> Option<usize> {
+        let axis = self.container(container.metadata.time_axis.as_deref()?)?;
+        Some(container.offset_in(axis))
+    }
+
+    /// The containers with a time axis as a table with a row per time, in
+    /// the order of the times, and the containers in column order. A cell is
+    /// the index of the element of its column sampled at that time.
+    ///
+    /// Containers on different time axes are joined on equal times, cells of
+    /// containers without a sample at that time are `None`. Time axes and
+    /// containers without a time axis are no columns, and times at which
+    /// none of the columns has a sample are no rows.
+    pub fn rows(&self) -> (Vec<&TGPHContainer>, BTreeMap<u64, Vec<Option<usize>>>) {
+        let columns: Vec<_> = self
+            .containers
+            .iter()
+            .filter_map(|c| Some((c, self.container(c.metadata.time_axis.as_deref()?)?)))
+            .collect();
+
+        let mut rows: BTreeMap<u64, Vec<Option<usize>>> = BTreeMap::new();
+        for (column, (container, axis)) in columns.iter().enumerate() {
+            let offset = container.offset_in(axis);
+            for index in 0..container.elements.len() {
+                let Some(time) = axis.elements.get_f64(index + offset) else {
+                    continue;
+                };
+                let row = rows
+                    .entry(time as u64)
+                    .or_insert_with(|| vec![None; columns.len()]);
+                row[column] = Some(index);
+            }
+        }
+
+        (columns.into_iter().map(|(c, _)| c).collect(), rows)
+    }
+
+    pub fn metadata(&self, name: &str) -> Option<&ContainerMetadata> {
+        self.container(name).map(|c| &c.metadata)
+    }
+
+    /// Attaches `metadata` to the container called `name`. Does nothing if
+    /// there is no such container yet, so it is meant to be called after the
+    /// first `append`.
+    pub fn set_metadata(&mut self, name: &str, metadata: ContainerMetadata) {
+        if let Some(container) = self.containers.iter_mut().find(|c| c.name == name) {
+            container.metadata = metadata;
+        }
+    }
+
+    pub fn remove_container(&mut self, name: &str) {
+        if let Some(index) = self.containers.iter().position(|c| c.name == name) {
+            self.containers.remove(index);
+        }
+    }
+
+    pub fn add_container(&mut self, container: TGPHContainer) {
+        self.containers.push(container);
+    }
+
+    fn container_or_insert<F>(&mut self, name: &str, empty: F) -> &mut TGPHContainer
+    where
+        F: FnOnce() -> ElementArrayType,
+    {
+        match self.containers.iter().position(|c| c.name == name) {
+            Some(index) => &mut self.containers[index],
+            None => {
+                self.add_container(TGPHContainer {
+                    name: name.to_string(),
+                    elements: empty(),
+                    metadata: ContainerMetadata::default(),
+                });
+                self.containers.last_mut().unwrap()
+            }
+        }
+    }
+
+    pub fn append<T: BaseContainerElementType>(
+        &mut self,
+        data: T,
+        name: &str,
+    ) -> Result<(), TgphError> {
+        data.push_element(self, name)
+    }
+
+    /// Converts the container called `name` to `target`, which has to be able
+    /// to hold its elements like with `TypeMismatchPolicy::Widen`, e.g. to
+    /// keep appending to a container whose values outgrew its type. Does
+    /// nothing if there is no such container.
+    pub fn widen(&mut self, name: &str, target: ElementType) -> Result<(), TgphError> {
+        let Some(container) = self.containers.iter_mut().find(|c| c.name == name) else {
+            return Ok(());
+        };
+        let container_type = container.elements.element_type();
+        if container_type == target {
+            return Ok(());
+        }
+        match container_type.widened_with(target) {
+            Some(widened) if widened == target => {
+                container.elements.convert_to(target);
+                Ok(())
+            }
+            _ => Err(container.type_mismatch(target)),
+        }
+    }
+
+    pub fn replace<T: BaseContainerElementType>(
+        &mut self,
+        data: Vec<T>,
+        name: &str,
+    ) -> Result<(), TgphError> {
+        let metadata = self.metadata(name).cloned();
+        self.remove_container(name);
+        self.mark_replaced(name);
+        for elem in data {
+            self.append(elem, name)?;
+        }
+        if let Some(metadata) = metadata {
+            self.set_metadata(name, metadata);
+        }
+        Ok(())
+    }
+
+    /// Names of the containers whose contents were swapped out with
+    /// `replace`, rather than appended to.
+    pub fn replaced_containers(&self) -> &[String] {
+        &self.replaced
+    }
+
+    /// Puts `container` in place of the one with the same name, like
+    /// `replace` does with its elements.
+    pub fn replace_container(&mut self, container: TGPHContainer) {
+        self.remove_container(&container.name);
+        self.mark_replaced(&container.name);
+        self.add_container(container);
+    }
+
+    fn mark_replaced(&mut self, name: &str) {
+        if !self.replaced.iter().any(|n| n == name) {
+            self.replaced.push(name.to_string());
+        }
+    }
+
+    /// Drops the elements older than allowed by `retention` at time `now`
+    /// (seconds since the Unix epoch). Containers without a time axis are
+    /// left alone, unless they are the time axis of another container.
+    pub fn apply_retention(&mut self, retention: &Retention, now: u64) {
+        // A time axis has to live as long as the longest lived container
+        // using it, or their elements would no longer line up.
+        let mut axis_ages: HashMap<&str, Option<u64>> = HashMap::new();
+        for container in &self.containers {
+            if let Some(axis) = &container.metadata.time_axis {
+                let age = retention.max_age_of(&container.name);
+                axis_ages
+                    .entry(axis)
+                    .and_modify(|a| *a = longest_age(*a, age))
+                    .or_insert_with(|| longest_age(retention.max_age_of(axis), age));
+            }
+        }
+
+        let mut expired = Vec::new();
+        for (index, container) in self.containers.iter().enumerate() {
+            let (axis_name, max_age) = match &container.metadata.time_axis {
+                Some(axis) => (axis.as_str(), retention.max_age_of(&container.name)),
+                None => match axis_ages.get(container.name.as_str()) {
+                    Some(&age) => (container.name.as_str(), age),
+                    None => continue,
+                },
+            };
+            let (Some(max_age), Some(axis)) = (
+                max_age,
+                self.containers.iter().find(|c| c.name == axis_name),
+            ) else {
+                continue;
+            };
+
+            // Both are appended to once per period and trimmed at the front,
+            // so they line up at their newest element.
+            let expired_times = axis.elements.count_older_than(now.saturating_sub(max_age));
+            let offset = axis.elements.len() as i64 - container.elements.len() as i64;
+            if expired_times > 0 {
+                let count =
+                    (expired_times as i64 - offset).clamp(0, container.elements.len() as i64);
+                expired.push((index, count as usize));
+            }
+        }
+
+        for (index, count) in expired {
+            self.containers[index].elements.remove_oldest(count);
+        }
+    }
+
+    /// Adds everything collected into `sample` during one period, as if it
+    /// had been appended to `self` directly: containers `sample` replaced
+    /// replace the ones of `self`, the others are appended to. A failing
+    /// container does not stop the remaining ones from being merged, the
+    /// first error is returned at the end.
+    pub fn merge(&mut self, sample: TGPH) -> Result<(), TgphError> {
+        let mut result = Ok(());
+
+        for container in sample.containers {
+            let name = container.name.as_str();
+            if sample.replaced.iter().any(|n| n == name) {
+                self.remove_container(name);
+                self.mark_replaced(name);
+            }
+
+            for value in container.elements.values() {
+                if let Err(e) = push_value(self, name, value) {
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                    break;
                 }
-                ElementArrayType::FLOAT32(elements)
             }
-            3 => {
-                let mut elements = vec![];
-                for _ in 0..element_count {
-                    elements.push(TGPHContainer::deserialize_string_from(stream)?);
-                }
-                ElementArrayType::STRING(elements)
+
+            if container.metadata != ContainerMetadata::default() {
+                self.set_metadata(name, container.metadata);
+            }
+        }
+
+        result
+    }
+}
+
+/// Type tag of a container, as stored in the element type byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ElementType {
+    U32 = 1,
+    FLOAT32 = 2,
+    STRING = 3,
+    U64 = 4,
+    I64 = 5,
+    FLOAT64 = 6,
+}
+
+impl ElementType {
+    pub(crate) fn from_index(index: u8) -> Option<Self> {
+        match index {
+            1 => Some(Self::U32),
+            2 => Some(Self::FLOAT32),
+            3 => Some(Self::STRING),
+            4 => Some(Self::U64),
+            5 => Some(Self::I64),
+            6 => Some(Self::FLOAT64),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::U32 => "U32",
+            Self::FLOAT32 => "FLOAT32",
+            Self::STRING => "STRING",
+            Self::U64 => "U64",
+            Self::I64 => "I64",
+            Self::FLOAT64 => "FLOAT64",
+        }
+    }
+
+    fn required_version(self) -> u8 {
+        match self {
+            Self::U32 | Self::FLOAT32 | Self::STRING => TGPH_VERSION_1,
+            Self::U64 | Self::I64 | Self::FLOAT64 => TGPH_VERSION_2,
+        }
+    }
+
+    fn empty_elements(self) -> ElementArrayType {
+        match self {
+            Self::U32 => ElementArrayType::U32(VecDeque::new()),
+            Self::FLOAT32 => ElementArrayType::FLOAT32(VecDeque::new()),
+            Self::STRING => ElementArrayType::STRING(VecDeque::new()),
+            Self::U64 => ElementArrayType::U64(VecDeque::new()),
+            Self::I64 => ElementArrayType::I64(VecDeque::new()),
+            Self::FLOAT64 => ElementArrayType::FLOAT64(VecDeque::new()),
+        }
+    }
+
+    /// Smallest numeric type able to hold elements of both types, keeping
+    /// U32 + FLOAT32 as FLOAT32 for compatibility with version 1 readers.
+    pub fn widened_with(self, other: Self) -> Option<Self> {
+        use ElementType::*;
+
+        match (self, other) {
+            (a, b) if a == b => Some(a),
+            (STRING, _) | (_, STRING) => None,
+            (U32, U64) | (U64, U32) => Some(U64),
+            (U32, I64) | (I64, U32) => Some(I64),
+            (U32, FLOAT32) | (FLOAT32, U32) => Some(FLOAT32),
+            _ => Some(FLOAT64),
+        }
+    }
+}
+
+/// What the values of a container represent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MetricKind {
+    /// Not known, always the case for containers read from version 1 and 2
+    /// files.
+    #[default]
+    Unspecified = 0,
+    /// A value that can go up and down, e.g. used memory.
+    Gauge = 1,
+    /// A total that only grows until it is reset, e.g. received packets.
+    Counter = 2,
+    /// Descriptive values that are not meant to be graphed, e.g. the hostname.
+    Info = 3,
+}
+
+impl MetricKind {
+    pub(crate) fn from_index(index: u8) -> Option<Self> {
+        match index {
+            0 => Some(Self::Unspecified),
+            1 => Some(Self::Gauge),
+            2 => Some(Self::Counter),
+            3 => Some(Self::Info),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Unspecified => "unspecified",
+            Self::Gauge => "gauge",
+            Self::Counter => "counter",
+            Self::Info => "info",
+        }
+    }
+}
+
+/// Structured description of a container, so readers do not have to parse
+/// units and relationships out of its name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ContainerMetadata {
+    /// Unit of the values, e.g. "MB" or "B/s". Empty when unitless.
+    pub unit: String,
+    /// Human-readable name to show instead of the container name.
+    pub label: String,
+    pub kind: MetricKind,
+    /// Name of the container holding the timestamp of each element.
+    pub time_axis: Option<String>,
+}
+
+impl ContainerMetadata {
+    pub fn new(kind: MetricKind, unit: &str, label: &str) -> Self {
+        Self {
+            unit: unit.to_string(),
+            label: label.to_string(),
+            kind,
+            time_axis: None,
+        }
+    }
+
+    pub fn with_time_axis(self, time_axis: &str) -> Self {
+        Self {
+            time_axis: Some(time_axis.to_string()),
+            ..self
+        }
+    }
+}
+
+/// Elements are kept oldest to newest, so appending and trimming to
+/// `entry_limit` are both O(1).
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ElementArrayType {
+    U32(VecDeque<u32>),
+    FLOAT32(VecDeque<f32>),
+    STRING(VecDeque<String>),
+    U64(VecDeque<u64>),
+    I64(VecDeque<i64>),
+    FLOAT64(VecDeque<f64>),
+}
+
+impl ElementArrayType {
+    pub fn element_type(&self) -> ElementType {
+        match self {
+            Self::U32(_) => ElementType::U32,
+            Self::FLOAT32(_) => ElementType::FLOAT32,
+            Self::STRING(_) => ElementType::STRING,
+            Self::U64(_) => ElementType::U64,
+            Self::I64(_) => ElementType::I64,
+            Self::FLOAT64(_) => ElementType::FLOAT64,
+        }
+    }
+
+    fn get_index(&self) -> u8 {
+        self.element_type() as u8
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::U32(arr) => arr.len(),
+            Self::FLOAT32(arr) => arr.len(),
+            Self::STRING(arr) => arr.len(),
+            Self::U64(arr) => arr.len(),
+            Self::I64(arr) => arr.len(),
+            Self::FLOAT64(arr) => arr.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The elements if they are U32, `None` for every other type.
+    pub fn as_u32(&self) -> Option<&VecDeque<u32>> {
+        match self {
+            Self::U32(arr) => Some(arr),
+            _ => None,
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<&VecDeque<f32>> {
+        match self {
+            Self::FLOAT32(arr) => Some(arr),
+            _ => None,
+        }
+    }
+
+    pub fn as_strings(&self) -> Option<&VecDeque<String>> {
+        match self {
+            Self::STRING(arr) => Some(arr),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<&VecDeque<u64>> {
+        match self {
+            Self::U64(arr) => Some(arr),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<&VecDeque<i64>> {
+        match self {
+            Self::I64(arr) => Some(arr),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<&VecDeque<f64>> {
+        match self {
+            Self::FLOAT64(arr) => Some(arr),
+            _ => None,
+        }
+    }
+
+    /// The element at `index` converted to f64, `None` for strings.
+    pub fn get_f64(&self, index: usize) -> Option<f64> {
+        match self {
+            Self::U32(arr) => arr.get(index).map(|&e| e as f64),
+            Self::FLOAT32(arr) => arr.get(index).map(|&e| e as f64),
+            Self::U64(arr) => arr.get(index).map(|&e| e as f64),
+            Self::I64(arr) => arr.get(index).map(|&e| e as f64),
+            Self::FLOAT64(arr) => arr.get(index).copied(),
+            Self::STRING(_) => None,
+        }
+    }
+
+    fn values(&self) -> Vec<ElementValue> {
+        match self {
+            Self::U32(arr) => arr.iter().map(|&e| ElementValue::U32(e)).collect(),
+            Self::FLOAT32(arr) => arr.iter().map(|&e| ElementValue::FLOAT32(e)).collect(),
+            Self::STRING(arr) => arr
+                .iter()
+                .map(|e| ElementValue::STRING(e.clone()))
+                .collect(),
+            Self::U64(arr) => arr.iter().map(|&e| ElementValue::U64(e)).collect(),
+            Self::I64(arr) => arr.iter().map(|&e| ElementValue::I64(e)).collect(),
+            Self::FLOAT64(arr) => arr.iter().map(|&e| ElementValue::FLOAT64(e)).collect(),
+        }
+    }
+
+    /// Number of elements at the front that are lower than `cutoff`, for
+    /// containers of timestamps. Always 0 for strings.
+    fn count_older_than(&self, cutoff: u64) -> usize {
+        match self {
+            Self::U32(arr) => arr.iter().take_while(|&&t| (t as u64) < cutoff).count(),
+            Self::U64(arr) => arr.iter().take_while(|&&t| t < cutoff).count(),
+            Self::I64(arr) => arr.iter().take_while(|&&t| t < cutoff as i64).count(),
+            Self::FLOAT32(arr) => arr
+                .iter()
+                .take_while(|&&t| (t as f64) < cutoff as f64)
+                .count(),
+            Self::FLOAT64(arr) => arr.iter().take_while(|&&t| t < cutoff as f64).count(),
+            Self::STRING(_) => 0,
+        }
+    }
+
+    /// The elements at `indices`, which have to be in range.
+    pub fn select(&self, indices: &[usize]) -> Self {
+        match self {
+            Self::U32(arr) => Self::U32(indices.iter().map(|&i| arr[i]).collect()),
+            Self::FLOAT32(arr) => Self::FLOAT32(indices.iter().map(|&i| arr[i]).collect()),
+            Self::STRING(arr) => Self::STRING(indices.iter().map(|&i| arr[i].clone()).collect()),
+            Self::U64(arr) => Self::U64(indices.iter().map(|&i| arr[i]).collect()),
+            Self::I64(arr) => Self::I64(indices.iter().map(|&i| arr[i]).collect()),
+            Self::FLOAT64(arr) => Self::FLOAT64(indices.iter().map(|&i| arr[i]).collect()),
+        }
+    }
+
+    fn remove_oldest(&mut self, count: usize) {
+        match self {
+            Self::U32(arr) => drop(arr.drain(..count)),
+            Self::FLOAT32(arr) => drop(arr.drain(..count)),
+            Self::STRING(arr) => drop(arr.drain(..count)),
+            Self::U64(arr) => drop(arr.drain(..count)),
+            Self::I64(arr) => drop(arr.drain(..count)),
+            Self::FLOAT64(arr) => drop(arr.drain(..count)),
+        }
+    }
+
+    fn convert_to(&mut self, target: ElementType) {
+        let values = self.values();
+        *self = match target {
+            ElementType::U32 => unreachable!("nothing is ever narrowed to U32"),
+            ElementType::STRING => unreachable!("strings are never converted"),
+            ElementType::FLOAT32 => {
+                Self::FLOAT32(values.iter().map(|v| v.as_f64() as f32).collect())
+            }
+            ElementType::U64 => Self::U64(values.iter().map(|v| v.as_u64()).collect()),
+            ElementType::I64 => Self::I64(values.iter().map(|v| v.as_i64()).collect()),
+            ElementType::FLOAT64 => Self::FLOAT64(values.iter().map(|v| v.as_f64()).collect()),
+        };
+    }
+}
+
+/// Preallocates for `count` elements read from the stream, capped so that a
+/// corrupted count cannot request gigabytes up front.
+fn elements_with_capacity<T>(count: u32) -> VecDeque<T> {
+    VecDeque::with_capacity(count.min(1 << 16) as usize)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TGPHContainer {
+    pub name: String,
+    pub elements: ElementArrayType,
+    pub metadata: ContainerMetadata,
+}
+
+impl TGPHContainer {
+    /// Index in `axis`, the time axis of the container, of its oldest
+    /// element. Containers line up with their time axis at the newest
+    /// element, one that started being collected later than the others just
+    /// has fewer elements.
+    pub fn offset_in(&self, axis: &TGPHContainer) -> usize {
+        axis.elements.len().saturating_sub(self.elements.len())
+    }
+
+    fn required_version(&self, encoding: ColumnEncoding) -> u8 {
+        let mut version = self.elements.element_type().required_version();
+        if self.metadata != ContainerMetadata::default() {
+            version = version.max(TGPH_VERSION_3);
+        }
+        if encoding == ColumnEncoding::Compact
+            && self.elements.element_type() != ElementType::STRING
+        {
+            version = version.max(TGPH_VERSION_4);
+        }
+        version
+    }
+
+    /// Elements in the compact encoding, `None` for strings which have none.
+    fn encoded_elements(&self) -> Option<Vec<u8>> {
+        match &self.elements {
+            ElementArrayType::U32(arr) => Some(encode_integers(arr.iter().map(|&e| e as u64))),
+            ElementArrayType::U64(arr) => Some(encode_integers(arr.iter().copied())),
+            ElementArrayType::I64(arr) => Some(encode_integers(arr.iter().map(|&e| e as u64))),
+            ElementArrayType::FLOAT32(arr) => {
+                Some(encode_floats(arr.iter().map(|e| e.to_bits() as u64)))
+            }
+            ElementArrayType::FLOAT64(arr) => Some(encode_floats(arr.iter().map(|e| e.to_bits()))),
+            ElementArrayType::STRING(_) => None,
+        }
+    }
+
+    fn type_mismatch(&self, element_type: ElementType) -> TgphError {
+        TgphError::TypeMismatch {
+            name: self.name.clone(),
+            container_type: self.elements.element_type().name(),
+            element_type: element_type.name(),
+        }
+    }
+
+    fn serialize_string_into<W: Write>(stream: &mut W, string: &str) -> Result<(), std::io::Error> {
+        if string.len() >= 255 {
+            stream.write_all(&0xff_u8.to_le_bytes())?;
+            stream.write_all(&(string.len() as u16).to_le_bytes())?;
+            stream.write_all(string.as_bytes())?;
+        } else {
+            stream.write_all(&(string.len() as u8).to_le_bytes())?;
+            stream.write_all(string.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the container as found in a file of the given `version`, which
+    /// has to be at least `required_version()` for nothing to be lost.
+    pub fn serialize_into<W: Write>(
+        &self,
+        stream: &mut W,
+        version: u8,
+        encoding: ColumnEncoding,
+    ) -> Result<(), std::io::Error> {
+        TGPHContainer::serialize_string_into(stream, &self.name)?;
+
+        if version >= TGPH_VERSION_3 {
+            stream.write_all(&(self.metadata.kind as u8).to_le_bytes())?;
+            TGPHContainer::serialize_string_into(stream, &self.metadata.unit)?;
+            TGPHContainer::serialize_string_into(stream, &self.metadata.label)?;
+            let time_axis = self.metadata.time_axis.as_deref().unwrap_or_default();
+            TGPHContainer::serialize_string_into(stream, time_axis)?;
+        }
+
+        if version >= TGPH_VERSION_4 && encoding == ColumnEncoding::Compact {
+            if let Some(encoded) = self.encoded_elements() {
+                let element_type = self.elements.get_index() | ENCODED_FLAG;
+                stream.write_all(&element_type.to_le_bytes())?;
+                stream.write_all(&(self.elements.len() as u32).to_le_bytes())?;
+                stream.write_all(&(encoded.len() as u32).to_le_bytes())?;
+                stream.write_all(&encoded)?;
+                return Ok(());
+            }
+        }
+
+        stream.write_all(&self.elements.get_index().to_le_bytes())?;
+        let elements_len = self.elements.len() as u32;
+
+        stream.write_all(&(elements_len).to_le_bytes())?;
+
+        if elements_len > 0 {
+            match &self.elements {
+                ElementArrayType::U32(arr) => {
+                    for e in arr {
+                        stream.write_all(&e.to_le_bytes())?;
+                    }
+                }
+                ElementArrayType::FLOAT32(arr) => {
+                    for e in arr {
+                        stream.write_all(&e.to_le_bytes())?;
+                    }
+                }
+                ElementArrayType::STRING(arr) => {
+                    for e in arr {
+                        TGPHContainer::serialize_string_into(stream, e)?;
+                    }
+                }
+                ElementArrayType::U64(arr) => {
+                    for e in arr {
+                        stream.write_all(&e.to_le_bytes())?;
+                    }
+                }
+                ElementArrayType::I64(arr) => {
+                    for e in arr {
+                        stream.write_all(&e.to_le_bytes())?;
+                    }
+                }
+                ElementArrayType::FLOAT64(arr) => {
+                    for e in arr {
+                        stream.write_all(&e.to_le_bytes())?;
+                    }
+                }
+            };
+        }
+
+        Ok(())
+    }
+
+    fn deserialize_string_from<R: Read>(stream: &mut OffsetReader<R>) -> Result<String, TgphError> {
+        let mut length: u16 = stream.u8()? as u16;
+
+        if length == 0xff {
+            length = stream.u16()?;
+        }
+
+        let string_offset = stream.offset;
+        let mut buf = vec![0u8; length as usize];
+        stream.bytes(buf.as_mut_slice())?;
+
+        String::from_utf8(buf).map_err(|_| TgphError::InvalidUtf8 {
+            offset: string_offset,
+        })
+    }
+
+    fn deserialize_metadata_from<R: Read>(
+        stream: &mut OffsetReader<R>,
+    ) -> Result<ContainerMetadata, TgphError> {
+        let kind_offset = stream.offset;
+        let kind = stream.u8()?;
+        let kind = MetricKind::from_index(kind).ok_or(TgphError::UnknownMetricKind {
+            kind,
+            offset: kind_offset,
+        })?;
+        let unit = TGPHContainer::deserialize_string_from(stream)?;
+        let label = TGPHContainer::deserialize_string_from(stream)?;
+        let time_axis = TGPHContainer::deserialize_string_from(stream)?;
+
+        Ok(ContainerMetadata {
+            unit,
+            label,
+            kind,
+            time_axis: (!time_axis.is_empty()).then_some(time_axis),
+        })
+    }
+
+    fn deserialize_encoded_from<R: Read>(
+        stream: &mut OffsetReader<R>,
+        element_type: ElementType,
+        element_count: u32,
+    ) -> Result<ElementArrayType, TgphError> {
+        let length = stream.u32()?;
+        let payload_offset = stream.offset;
+        let payload = stream.byte_vec(length)?;
+        let invalid = || TgphError::InvalidEncoding {
+            offset: payload_offset,
+        };
+
+        let elements = match element_type {
+            ElementType::U32 => ElementArrayType::U32(
+                decode_integers(&payload, element_count)
+                    .ok_or_else(invalid)?
+                    .into_iter()
+                    .map(u32::try_from)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid())?,
+            ),
+            ElementType::U64 => ElementArrayType::U64(
+                decode_integers(&payload, element_count)
+                    .ok_or_else(invalid)?
+                    .into(),
+            ),
+            ElementType::I64 => ElementArrayType::I64(
+                decode_integers(&payload, element_count)
+                    .ok_or_else(invalid)?
+                    .into_iter()
+                    .map(|e| e as i64)
+                    .collect(),
+            ),
+            ElementType::FLOAT32 => ElementArrayType::FLOAT32(
+                decode_floats(&payload, element_count)
+                    .ok_or_else(invalid)?
+                    .into_iter()
+                    .map(|e| u32::try_from(e).map(f32::from_bits))
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid())?,
+            ),
+            ElementType::FLOAT64 => ElementArrayType::FLOAT64(
+                decode_floats(&payload, element_count)
+                    .ok_or_else(invalid)?
+                    .into_iter()
+                    .map(f64::from_bits)
+                    .collect(),
+            ),
+            ElementType::STRING => unreachable!("strings are never encoded"),
+        };
+
+        Ok(elements)
+    }
+
+    fn deserialize_from<R: Read>(
+        stream: &mut OffsetReader<R>,
+        version: u8,
+    ) -> Result<Self, TgphError> {
+        let name = TGPHContainer::deserialize_string_from(stream)?;
+
+        let metadata = if version >= TGPH_VERSION_3 {
+            TGPHContainer::deserialize_metadata_from(stream)?
+        } else {
+            ContainerMetadata::default()
+        };
+
+        let type_offset = stream.offset;
+        let element_type = stream.u8()?;
+        let element_count = stream.u32()?;
+
+        if version >= TGPH_VERSION_4 && element_type & ENCODED_FLAG != 0 {
+            let elements = match ElementType::from_index(element_type & !ENCODED_FLAG) {
+                Some(ElementType::STRING) | None => {
+                    return Err(TgphError::UnknownElementType {
+                        element_type,
+                        offset: type_offset,
+                    })
+                }
+                Some(element_type) => {
+                    TGPHContainer::deserialize_encoded_from(stream, element_type, element_count)?
+                }
+            };
+
+            return Ok(Self {
+                name,
+                elements,
+                metadata,
+            });
+        }
+
+        let elements = match ElementType::from_index(element_type) {
+            Some(ElementType::U32) => {
+                let mut elements = elements_with_capacity(element_count);
+                for _ in 0..element_count {
+                    elements.push_back(stream.u32()?);
+                }
+                ElementArrayType::U32(elements)
+            }
+            Some(ElementType::FLOAT32) => {
+                let mut elements = elements_with_capacity(element_count);
+                for _ in 0..element_count {
+                    elements.push_back(stream.f32()?);
+                }
+                ElementArrayType::FLOAT32(elements)
+            }
+            Some(ElementType::STRING) => {
+                let mut elements = elements_with_capacity(element_count);
+                for _ in 0..element_count {
+                    elements.push_back(TGPHContainer::deserialize_string_from(stream)?);
+                }
+                ElementArrayType::STRING(elements)
+            }
+            Some(ElementType::U64) => {
+                let mut elements = elements_with_capacity(element_count);
+                for _ in 0..element_count {
+                    elements.push_back(stream.u64()?);
+                }
+                ElementArrayType::U64(elements)
+            }
+            Some(ElementType::I64) => {
+                let mut elements = elements_with_capacity(element_count);
+                for _ in 0..element_count {
+                    elements.push_back(stream.i64()?);
+                }
+                ElementArrayType::I64(elements)
+            }
+            Some(ElementType::FLOAT64) => {
+                let mut elements = elements_with_capacity(element_count);
+                for _ in 0..element_count {
+                    elements.push_back(stream.f64()?);
+                }
+                ElementArrayType::FLOAT64(elements)
+            }
+            None => {
+                return Err(TgphError::UnknownElementType {
+                    element_type,
+                    offset: type_offset,
+                })
+            }
+        };
+
+        Ok(Self {
+            name,
+            elements,
+            metadata,
+        })
+    }
+}
+
+#[cfg(test)]
+mod serialize {
+    use crate::tgph_format::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn default_tgph() {
+        let tgph = TGPH::default();
+        assert_eq!(
+            String::from_utf8(tgph.magic.to_le_bytes().to_vec()).unwrap(),
+            "TGPH"
+        );
+    }
+
+    #[test]
+    fn write_default_tgph() {
+        let tgph = TGPH::default();
+        let mut output_buffer = Vec::new();
+        tgph.serialize_into(&mut output_buffer).unwrap();
+        assert_eq!(output_buffer, [0x54, 0x47, 0x50, 0x48, 0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn write_tgph_with_one_empty_container() {
+        let mut tgph = TGPH::default();
+        let container = TGPHContainer {
+            name: "testing".into(),
+            elements: ElementArrayType::U32(VecDeque::new()),
+            metadata: ContainerMetadata::default(),
+        };
+
+        let mut expected: Vec<u8> = Vec::new();
+        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
+        expected.extend_from_slice(&(container.name.len() as u8).to_le_bytes());
+        expected.extend_from_slice(container.name.as_bytes());
+        expected.extend_from_slice(&[1]); // Element Type
+        expected.extend_from_slice(&0_u32.to_le_bytes()); // Element Count
+
+        tgph.add_container(container);
+        let mut output_buffer = Vec::new();
+        tgph.serialize_into(&mut output_buffer).unwrap();
+
+        assert_eq!(output_buffer, expected);
+    }
+
+    #[test]
+    fn write_tgph_with_one_filled_container() {
+        let mut tgph = TGPH::default();
+        let container = TGPHContainer {
+            name: "testing".into(),
+            elements: ElementArrayType::U32(vec![12, 34, 56, 1 << 31].into()),
+            metadata: ContainerMetadata::default(),
+        };
+
+        let mut expected: Vec<u8> = Vec::new();
+        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
+        expected.extend_from_slice(&(container.name.len() as u8).to_le_bytes());
+        expected.extend_from_slice(container.name.as_bytes());
+        expected.extend_from_slice(&[1]); // Element Type
+        expected.extend_from_slice(&4_u32.to_le_bytes()); // Element Count
+        expected.extend_from_slice(&12_u32.to_le_bytes());
+        expected.extend_from_slice(&34_u32.to_le_bytes());
+        expected.extend_from_slice(&56_u32.to_le_bytes());
+        expected.extend_from_slice(&((1 << 31) as u32).to_le_bytes());
+
+        tgph.add_container(container);
+        let mut output_buffer = Vec::new();
+        tgph.serialize_into(&mut output_buffer).unwrap();
+
+        assert_eq!(output_buffer, expected);
+    }
+
+    #[test]
+    fn write_tgph_with_one_filled_container_floats() {
+        let mut tgph = TGPH::default();
+        let container = TGPHContainer {
+            name: "testing".into(),
+            elements: ElementArrayType::FLOAT32(vec![PI, 1.618, 0.3].into()),
+            metadata: ContainerMetadata::default(),
+        };
+
+        let mut expected: Vec<u8> = Vec::new();
+        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
+        expected.extend_from_slice(&(container.name.len() as u8).to_le_bytes());
+        expected.extend_from_slice(container.name.as_bytes());
+        expected.extend_from_slice(&[2]); // Element Type
+        expected.extend_from_slice(&3_u32.to_le_bytes()); // Element Count
+        expected.extend_from_slice(&PI.to_le_bytes());
+        expected.extend_from_slice(&1.618_f32.to_le_bytes());
+        expected.extend_from_slice(&0.3_f32.to_le_bytes());
+
+        tgph.add_container(container);
+        let mut output_buffer = Vec::new();
+        tgph.serialize_into(&mut output_buffer).unwrap();
+
+        assert_eq!(output_buffer, expected);
+    }
+
+    #[test]
+    fn write_tgph_with_one_filled_container_strings() {
+        let mut tgph = TGPH::default();
+        let container = TGPHContainer {
+            name: "testing".into(),
+            elements: ElementArrayType::STRING(
+                vec![
+                    "lorem".into(),
+                    "foxem".into(),
+                    "verylongstringemlatinem".into(),
+                ]
+                .into(),
+            ),
+            metadata: ContainerMetadata::default(),
+        };
+
+        let mut expected: Vec<u8> = Vec::new();
+        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
+        expected.extend_from_slice(&(container.name.len() as u8).to_le_bytes());
+        expected.extend_from_slice(container.name.as_bytes());
+        expected.extend_from_slice(&[3]); // Element Type
+        expected.extend_from_slice(&3_u32.to_le_bytes()); // Element Count
+        expected.extend_from_slice(&5_u8.to_le_bytes());
+        expected.extend_from_slice("lorem".as_bytes());
+        expected.extend_from_slice(&5_u8.to_le_bytes());
+        expected.extend_from_slice("foxem".as_bytes());
+        expected.extend_from_slice(&23_u8.to_le_bytes());
+        expected.extend_from_slice("verylongstringemlatinem".as_bytes());
+
+        tgph.add_container(container);
+        let mut output_buffer = Vec::new();
+        tgph.serialize_into(&mut output_buffer).unwrap();
+
+        assert_eq!(output_buffer, expected);
+    }
+
+    #[test]
+    fn write_tgph_with_multiple_containers_different_types() {
+        let mut tgph = TGPH::default();
+        let container1 = TGPHContainer {
+            name: "integers".into(),
+            elements: ElementArrayType::U32(vec![12, 34, 56, 1 << 31].into()),
+            metadata: ContainerMetadata::default(),
+        };
+        let container2 = TGPHContainer {
+            name: "floats".into(),
+            elements: ElementArrayType::FLOAT32(vec![PI, 1.618, 0.3].into()),
+            metadata: ContainerMetadata::default(),
+        };
+        let container3 = TGPHContainer {
+            name: "strings".into(),
+            elements: ElementArrayType::STRING(
+                vec![
+                    "lorem".into(),
+                    "foxem".into(),
+                    "verylongstringemlatinem".into(),
+                ]
+                .into(),
+            ),
+            metadata: ContainerMetadata::default(),
+        };
+
+        let mut expected: Vec<u8> = Vec::new();
+        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x03, 0x00]);
+        expected.extend_from_slice(&(container1.name.len() as u8).to_le_bytes());
+        expected.extend_from_slice(container1.name.as_bytes());
+        expected.extend_from_slice(&[1]); // Element Type
+        expected.extend_from_slice(&4_u32.to_le_bytes()); // Element Count
+        expected.extend_from_slice(&12_u32.to_le_bytes());
+        expected.extend_from_slice(&34_u32.to_le_bytes());
+        expected.extend_from_slice(&56_u32.to_le_bytes());
+        expected.extend_from_slice(&((1 << 31) as u32).to_le_bytes());
+        expected.extend_from_slice(&(container2.name.len() as u8).to_le_bytes());
+        expected.extend_from_slice(container2.name.as_bytes());
+        expected.extend_from_slice(&[2]); // Element Type
+        expected.extend_from_slice(&3_u32.to_le_bytes()); // Element Count
+        expected.extend_from_slice(&PI.to_le_bytes());
+        expected.extend_from_slice(&1.618_f32.to_le_bytes());
+        expected.extend_from_slice(&0.3_f32.to_le_bytes());
+        expected.extend_from_slice(&(container3.name.len() as u8).to_le_bytes());
+        expected.extend_from_slice(container3.name.as_bytes());
+        expected.extend_from_slice(&[3]); // Element Type
+        expected.extend_from_slice(&3_u32.to_le_bytes()); // Element Count
+        expected.extend_from_slice(&5_u8.to_le_bytes());
+        expected.extend_from_slice("lorem".as_bytes());
+        expected.extend_from_slice(&5_u8.to_le_bytes());
+        expected.extend_from_slice("foxem".as_bytes());
+        expected.extend_from_slice(&23_u8.to_le_bytes());
+        expected.extend_from_slice("verylongstringemlatinem".as_bytes());
+
+        tgph.add_container(container1);
+        tgph.add_container(container2);
+        tgph.add_container(container3);
+        let mut output_buffer = Vec::new();
+        tgph.serialize_into(&mut output_buffer).unwrap();
+
+        assert_eq!(output_buffer, expected);
+    }
+
+    #[test]
+    fn write_tgph_with_u64_container_bumps_version() {
+        let mut tgph = TGPH::default();
+        let container = TGPHContainer {
+            name: "testing".into(),
+            elements: ElementArrayType::U64(vec![12, 1 << 40].into()),
+            metadata: ContainerMetadata::default(),
+        };
+
+        let mut expected: Vec<u8> = Vec::new();
+        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x02, 0x01, 0x00]);
+        expected.extend_from_slice(&(container.name.len() as u8).to_le_bytes());
+        expected.extend_from_slice(container.name.as_bytes());
+        expected.extend_from_slice(&[4]); // Element Type
+        expected.extend_from_slice(&2_u32.to_le_bytes()); // Element Count
+        expected.extend_from_slice(&12_u64.to_le_bytes());
+        expected.extend_from_slice(&(1_u64 << 40).to_le_bytes());
+
+        tgph.add_container(container);
+        let mut output_buffer = Vec::new();
+        tgph.serialize_into(&mut output_buffer).unwrap();
+
+        assert_eq!(output_buffer, expected);
+    }
+
+    #[test]
+    fn write_tgph_with_i64_and_f64_containers() {
+        let mut tgph = TGPH::default();
+        let container1 = TGPHContainer {
+            name: "signed".into(),
+            elements: ElementArrayType::I64(vec![-12, i64::MAX].into()),
+            metadata: ContainerMetadata::default(),
+        };
+        let container2 = TGPHContainer {
+            name: "doubles".into(),
+            elements: ElementArrayType::FLOAT64(vec![std::f64::consts::E, -0.5].into()),
+            metadata: ContainerMetadata::default(),
+        };
+
+        let mut expected: Vec<u8> = Vec::new();
+        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x02, 0x02, 0x00]);
+        expected.extend_from_slice(&(container1.name.len() as u8).to_le_bytes());
+        expected.extend_from_slice(container1.name.as_bytes());
+        expected.extend_from_slice(&[5]); // Element Type
+        expected.extend_from_slice(&2_u32.to_le_bytes()); // Element Count
+        expected.extend_from_slice(&(-12_i64).to_le_bytes());
+        expected.extend_from_slice(&i64::MAX.to_le_bytes());
+        expected.extend_from_slice(&(container2.name.len() as u8).to_le_bytes());
+        expected.extend_from_slice(container2.name.as_bytes());
+        expected.extend_from_slice(&[6]); // Element Type
+        expected.extend_from_slice(&2_u32.to_le_bytes()); // Element Count
+        expected.extend_from_slice(&std::f64::consts::E.to_le_bytes());
+        expected.extend_from_slice(&(-0.5_f64).to_le_bytes());
+
+        tgph.add_container(container1);
+        tgph.add_container(container2);
+        let mut output_buffer = Vec::new();
+        tgph.serialize_into(&mut output_buffer).unwrap();
+
+        assert_eq!(output_buffer, expected);
+    }
+
+    #[test]
+    fn write_tgph_with_metadata_bumps_version() {
+        let mut tgph = TGPH::default();
+        tgph.append(1.5_f32, "Used memory [MB]").unwrap();
+        tgph.set_metadata(
+            "Used memory [MB]",
+            ContainerMetadata::new(MetricKind::Gauge, "MB", "Used memory")
+                .with_time_axis("Unix timestamp"),
+        );
+
+        let mut expected: Vec<u8> = Vec::new();
+        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x03, 0x01, 0x00]);
+        expected.extend_from_slice(&16_u8.to_le_bytes());
+        expected.extend_from_slice("Used memory [MB]".as_bytes());
+        expected.extend_from_slice(&[1]); // Metric Kind
+        expected.extend_from_slice(&2_u8.to_le_bytes());
+        expected.extend_from_slice("MB".as_bytes());
+        expected.extend_from_slice(&11_u8.to_le_bytes());
+        expected.extend_from_slice("Used memory".as_bytes());
+        expected.extend_from_slice(&14_u8.to_le_bytes());
+        expected.extend_from_slice("Unix timestamp".as_bytes());
+        expected.extend_from_slice(&[2]); // Element Type
+        expected.extend_from_slice(&1_u32.to_le_bytes()); // Element Count
+        expected.extend_from_slice(&1.5_f32.to_le_bytes());
+
+        let mut output_buffer = Vec::new();
+        tgph.serialize_into(&mut output_buffer).unwrap();
+
+        assert_eq!(output_buffer, expected);
+    }
+
+    #[test]
+    fn write_compact_tgph_keeps_strings_raw() {
+        let mut tgph = TGPH {
+            column_encoding: ColumnEncoding::Compact,
+            ..TGPH::default()
+        };
+        tgph.append("a".to_string(), "s").unwrap();
+        tgph.append(7_u32, "n").unwrap();
+
+        let mut output_buffer = Vec::new();
+        tgph.serialize_into(&mut output_buffer).unwrap();
+
+        let mut expected: Vec<u8> = Vec::new();
+        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x04, 0x02, 0x00]);
+        expected.extend_from_slice(&[1, b's', 0, 0, 0, 0]); // Name, Metadata
+        expected.extend_from_slice(&[3]); // Element Type
+        expected.extend_from_slice(&1_u32.to_le_bytes()); // Element Count
+        expected.extend_from_slice(&[1, b'a']);
+        expected.extend_from_slice(&[1, b'n', 0, 0, 0, 0]); // Name, Metadata
+        expected.extend_from_slice(&[0x81]); // Element Type
+        expected.extend_from_slice(&1_u32.to_le_bytes()); // Element Count
+        expected.extend_from_slice(&8_u32.to_le_bytes()); // Encoded Length
+        expected.extend_from_slice(&7_u64.to_be_bytes());
+
+        assert_eq!(output_buffer, expected);
+    }
+
+    #[test]
+    fn long_container_name() {
+        let mut tgph = TGPH::default();
+        let container = TGPHContainer {
+            name: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".into(),
+            elements: ElementArrayType::U32(vec![12, 34, 56, 1 << 31].into()),
+            metadata: ContainerMetadata::default(),
+        };
+
+        let mut expected: Vec<u8> = Vec::new();
+        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
+        expected.extend_from_slice(&0xff_u8.to_le_bytes());
+        expected.extend_from_slice(&(container.name.len() as u16).to_le_bytes());
+        expected.extend_from_slice(container.name.as_bytes());
+        expected.extend_from_slice(&[1]); // Element Type
+        expected.extend_from_slice(&4_u32.to_le_bytes()); // Element Count
+        expected.extend_from_slice(&12_u32.to_le_bytes());
+        expected.extend_from_slice(&34_u32.to_le_bytes());
+        expected.extend_from_slice(&56_u32.to_le_bytes());
+        expected.extend_from_slice(&((1 << 31) as u32).to_le_bytes());
+
+        tgph.add_container(container);
+        let mut output_buffer = Vec::new();
+        tgph.serialize_into(&mut output_buffer).unwrap();
+
+        assert_eq!(output_buffer, expected);
+    }
+
+    #[test]
+    fn long_container_name_and_long_string_elements() {
+        let mut tgph = TGPH::default();
+        let container = TGPHContainer {
+            name: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".into(),
+            elements: ElementArrayType::STRING(vec![
+                                               "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".into(),
+                                               "++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++".into(),
+            ].into()),
+            metadata: ContainerMetadata::default(),
+        };
+
+        let mut expected: Vec<u8> = Vec::new();
+        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
+        expected.extend_from_slice(&0xff_u8.to_le_bytes());
+        expected.extend_from_slice(&(container.name.len() as u16).to_le_bytes());
+        expected.extend_from_slice(container.name.as_bytes());
+        expected.extend_from_slice(&[3]); // Element Type
+        expected.extend_from_slice(&2_u32.to_le_bytes()); // Element Count
+        expected.extend_from_slice(&0xff_u8.to_le_bytes());
+        expected.extend_from_slice(&1000_u16.to_le_bytes());
+        expected.extend_from_slice("bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".as_bytes());
+        expected.extend_from_slice(&0xff_u8.to_le_bytes());
+        expected.extend_from_slice(&1024_u16.to_le_bytes());
+        expected.extend_from_slice("++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++".as_bytes());
+
+        tgph.add_container(container);
+        let mut output_buffer = Vec::new();
+        tgph.serialize_into(&mut output_buffer).unwrap();
+
+        assert_eq!(output_buffer, expected);
+    }
+}
+
+#[cfg(test)]
+mod deserialize {
+    use std::f32::consts::PI;
+    use std::io::Cursor;
+
+    use crate::tgph_format::*;
+    #[test]
+    fn deserialize_without_containers() {
+        let bytes: Vec<u8> = vec![0x54, 0x47, 0x50, 0x48, 0x01, 0x00, 0x00];
+        let mut cursor = Cursor::new(bytes);
+        let tgph = TGPH::deserialize_from(&mut cursor).unwrap();
+
+        assert_eq!(
+            String::from_utf8(tgph.magic.to_le_bytes().to_vec()).unwrap(),
+            "TGPH"
+        );
+
+        assert_eq!(tgph.version, 1);
+        assert_eq!(tgph.containers.len(), 0);
+    }
+
+    #[test]
+    fn deserialize_one_empty_container() {
+        let mut bytes: Vec<u8> = Vec::new();
+        let container_name = "test";
+
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
+        bytes.extend_from_slice(&(container_name.len() as u8).to_le_bytes());
+        bytes.extend_from_slice(container_name.as_bytes());
+        bytes.extend_from_slice(&[1]); // Element Type
+        bytes.extend_from_slice(&0_u32.to_le_bytes()); // Element Count
+
+        let mut cursor = Cursor::new(bytes);
+        let tgph = TGPH::deserialize_from(&mut cursor).unwrap();
+
+        assert_eq!(
+            String::from_utf8(tgph.magic.to_le_bytes().to_vec()).unwrap(),
+            "TGPH"
+        );
+
+        assert_eq!(tgph.version, 1);
+        assert_eq!(tgph.containers.len(), 1);
+
+        assert_eq!(tgph.containers[0].name, container_name);
+        if let ElementArrayType::U32(elements) = &tgph.containers[0].elements {
+            assert_eq!(elements.len(), 0);
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn deserialize_one_filled_container() {
+        let mut bytes: Vec<u8> = Vec::new();
+        let container_name = "bigredbarrow";
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
+        bytes.extend_from_slice(&(container_name.len() as u8).to_le_bytes());
+        bytes.extend_from_slice(container_name.as_bytes());
+        bytes.extend_from_slice(&[1]); // Element Type
+        bytes.extend_from_slice(&4_u32.to_le_bytes()); // Element Count
+        bytes.extend_from_slice(&12_u32.to_le_bytes());
+        bytes.extend_from_slice(&34_u32.to_le_bytes());
+        bytes.extend_from_slice(&56_u32.to_le_bytes());
+        bytes.extend_from_slice(&((1 << 31) as u32).to_le_bytes());
+
+        let mut cursor = Cursor::new(bytes);
+        let tgph = TGPH::deserialize_from(&mut cursor).unwrap();
+
+        assert_eq!(
+            String::from_utf8(tgph.magic.to_le_bytes().to_vec()).unwrap(),
+            "TGPH"
+        );
+
+        assert_eq!(tgph.version, 1);
+        assert_eq!(tgph.containers.len(), 1);
+
+        assert_eq!(tgph.containers[0].name, container_name);
+        if let ElementArrayType::U32(elements) = &tgph.containers[0].elements {
+            assert_eq!(elements.len(), 4);
+            assert_eq!(elements[0], 12);
+            assert_eq!(elements[1], 34);
+            assert_eq!(elements[2], 56);
+            assert_eq!(elements[3], 1 << 31);
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn deserialize_multiple_containers_different_types() {
+        let mut bytes: Vec<u8> = Vec::new();
+        let container_name1 = "quick";
+        let container_name2 = "red fox";
+        let container_name3 = "jumped over THE LAZY";
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x03, 0x00]);
+        bytes.extend_from_slice(&(container_name1.len() as u8).to_le_bytes());
+        bytes.extend_from_slice(container_name1.as_bytes());
+        bytes.extend_from_slice(&[1]); // Element Type
+        bytes.extend_from_slice(&4_u32.to_le_bytes()); // Element Count
+        bytes.extend_from_slice(&12_u32.to_le_bytes());
+        bytes.extend_from_slice(&34_u32.to_le_bytes());
+        bytes.extend_from_slice(&56_u32.to_le_bytes());
+        bytes.extend_from_slice(&((1 << 31) as u32).to_le_bytes());
+        bytes.extend_from_slice(&(container_name2.len() as u8).to_le_bytes());
+        bytes.extend_from_slice(container_name2.as_bytes());
+        bytes.extend_from_slice(&[2]); // Element Type
+        bytes.extend_from_slice(&3_u32.to_le_bytes()); // Element Count
+        bytes.extend_from_slice(&PI.to_le_bytes());
+        bytes.extend_from_slice(&1.618_f32.to_le_bytes());
+        bytes.extend_from_slice(&0.3_f32.to_le_bytes());
+        bytes.extend_from_slice(&(container_name3.len() as u8).to_le_bytes());
+        bytes.extend_from_slice(container_name3.as_bytes());
+        bytes.extend_from_slice(&[3]); // Element Type
+        bytes.extend_from_slice(&3_u32.to_le_bytes()); // Element Count
+        bytes.extend_from_slice(&5_u8.to_le_bytes());
+        bytes.extend_from_slice("lorem".as_bytes());
+        bytes.extend_from_slice(&5_u8.to_le_bytes());
+        bytes.extend_from_slice("foxem".as_bytes());
+        bytes.extend_from_slice(&23_u8.to_le_bytes());
+        bytes.extend_from_slice("verylongstringemlatinem".as_bytes());
+
+        let mut cursor = Cursor::new(bytes);
+        let tgph = TGPH::deserialize_from(&mut cursor).unwrap();
+
+        assert_eq!(
+            String::from_utf8(tgph.magic.to_le_bytes().to_vec()).unwrap(),
+            "TGPH"
+        );
+
+        assert_eq!(tgph.version, 1);
+        assert_eq!(tgph.containers.len(), 3);
+
+        assert_eq!(tgph.containers[0].name, container_name1);
+        if let ElementArrayType::U32(elements) = &tgph.containers[0].elements {
+            assert_eq!(elements.len(), 4);
+            assert_eq!(elements[0], 12);
+            assert_eq!(elements[1], 34);
+            assert_eq!(elements[2], 56);
+            assert_eq!(elements[3], 1 << 31);
+        } else {
+            unreachable!();
+        }
+
+        assert_eq!(tgph.containers[1].name, container_name2);
+        if let ElementArrayType::FLOAT32(elements) = &tgph.containers[1].elements {
+            assert_eq!(elements.len(), 3);
+            assert_eq!(elements[0], PI);
+            assert_eq!(elements[1], 1.618);
+            assert_eq!(elements[2], 0.3);
+        } else {
+            unreachable!();
+        }
+
+        assert_eq!(tgph.containers[2].name, container_name3);
+        if let ElementArrayType::STRING(elements) = &tgph.containers[2].elements {
+            assert_eq!(elements.len(), 3);
+            assert_eq!(elements[0], "lorem");
+            assert_eq!(elements[1], "foxem");
+            assert_eq!(elements[2], "verylongstringemlatinem");
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn deserialize_u64_container() {
+        let mut bytes: Vec<u8> = Vec::new();
+        let container_name = "counter";
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x02, 0x01, 0x00]);
+        bytes.extend_from_slice(&(container_name.len() as u8).to_le_bytes());
+        bytes.extend_from_slice(container_name.as_bytes());
+        bytes.extend_from_slice(&[4]); // Element Type
+        bytes.extend_from_slice(&2_u32.to_le_bytes()); // Element Count
+        bytes.extend_from_slice(&12_u64.to_le_bytes());
+        bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+
+        let mut cursor = Cursor::new(bytes);
+        let tgph = TGPH::deserialize_from(&mut cursor).unwrap();
+
+        assert_eq!(tgph.version, 2);
+        assert_eq!(tgph.containers[0].name, container_name);
+        if let ElementArrayType::U64(elements) = &tgph.containers[0].elements {
+            assert_eq!(elements.len(), 2);
+            assert_eq!(elements[0], 12);
+            assert_eq!(elements[1], u64::MAX);
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn deserialize_i64_and_f64_containers() {
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x02, 0x02, 0x00]);
+        bytes.extend_from_slice(&6_u8.to_le_bytes());
+        bytes.extend_from_slice("signed".as_bytes());
+        bytes.extend_from_slice(&[5]); // Element Type
+        bytes.extend_from_slice(&1_u32.to_le_bytes()); // Element Count
+        bytes.extend_from_slice(&i64::MIN.to_le_bytes());
+        bytes.extend_from_slice(&7_u8.to_le_bytes());
+        bytes.extend_from_slice("doubles".as_bytes());
+        bytes.extend_from_slice(&[6]); // Element Type
+        bytes.extend_from_slice(&1_u32.to_le_bytes()); // Element Count
+        bytes.extend_from_slice(&1e300_f64.to_le_bytes());
+
+        let mut cursor = Cursor::new(bytes);
+        let tgph = TGPH::deserialize_from(&mut cursor).unwrap();
+
+        assert_eq!(tgph.containers.len(), 2);
+        if let ElementArrayType::I64(elements) = &tgph.containers[0].elements {
+            assert_eq!(elements[0], i64::MIN);
+        } else {
+            unreachable!();
+        }
+        if let ElementArrayType::FLOAT64(elements) = &tgph.containers[1].elements {
+            assert_eq!(elements[0], 1e300);
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn deserialize_metadata() {
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x03, 0x02, 0x00]);
+        bytes.extend_from_slice(&8_u8.to_le_bytes());
+        bytes.extend_from_slice("Hostname".as_bytes());
+        bytes.extend_from_slice(&[3]); // Metric Kind
+        bytes.extend_from_slice(&0_u8.to_le_bytes());
+        bytes.extend_from_slice(&4_u8.to_le_bytes());
+        bytes.extend_from_slice("Host".as_bytes());
+        bytes.extend_from_slice(&0_u8.to_le_bytes());
+        bytes.extend_from_slice(&[3]); // Element Type
+        bytes.extend_from_slice(&0_u32.to_le_bytes()); // Element Count
+        bytes.extend_from_slice(&7_u8.to_le_bytes());
+        bytes.extend_from_slice("Packets".as_bytes());
+        bytes.extend_from_slice(&[2]); // Metric Kind
+        bytes.extend_from_slice(&7_u8.to_le_bytes());
+        bytes.extend_from_slice("packets".as_bytes());
+        bytes.extend_from_slice(&0_u8.to_le_bytes());
+        bytes.extend_from_slice(&4_u8.to_le_bytes());
+        bytes.extend_from_slice("Time".as_bytes());
+        bytes.extend_from_slice(&[4]); // Element Type
+        bytes.extend_from_slice(&1_u32.to_le_bytes()); // Element Count
+        bytes.extend_from_slice(&42_u64.to_le_bytes());
+
+        let mut cursor = Cursor::new(bytes);
+        let tgph = TGPH::deserialize_from(&mut cursor).unwrap();
+
+        assert_eq!(
+            tgph.metadata("Hostname"),
+            Some(&ContainerMetadata::new(MetricKind::Info, "", "Host"))
+        );
+        assert_eq!(
+            tgph.metadata("Packets"),
+            Some(
+                &ContainerMetadata::new(MetricKind::Counter, "packets", "").with_time_axis("Time")
+            )
+        );
+        assert_eq!(tgph.containers[1].elements.len(), 1);
+    }
+
+    #[test]
+    fn deserialize_version_2_has_no_metadata() {
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x02, 0x01, 0x00]);
+        bytes.extend_from_slice(&4_u8.to_le_bytes());
+        bytes.extend_from_slice("test".as_bytes());
+        bytes.extend_from_slice(&[1]); // Element Type
+        bytes.extend_from_slice(&0_u32.to_le_bytes()); // Element Count
+
+        let mut cursor = Cursor::new(bytes);
+        let tgph = TGPH::deserialize_from(&mut cursor).unwrap();
+
+        assert_eq!(tgph.metadata("test"), Some(&ContainerMetadata::default()));
+    }
+
+    #[test]
+    fn deserialize_unknown_metric_kind() {
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x03, 0x01, 0x00]);
+        bytes.extend_from_slice(&4_u8.to_le_bytes());
+        bytes.extend_from_slice("test".as_bytes());
+        bytes.extend_from_slice(&[0x7f]); // Metric Kind
+
+        let mut cursor = Cursor::new(bytes);
+        match TGPH::deserialize_from(&mut cursor) {
+            Err(TgphError::UnknownMetricKind { kind, offset }) => {
+                assert_eq!(kind, 0x7f);
+                assert_eq!(offset, 12);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn compact_tgph_roundtrip() {
+        let mut tgph = TGPH::new(100);
+        tgph.column_encoding = ColumnEncoding::Compact;
+        for i in 0..50_u32 {
+            tgph.append(1_700_000_000 + i * 60, "u32").unwrap();
+            tgph.append(i as f32 * 0.25, "f32").unwrap();
+            tgph.append((i as u64) << 40, "u64").unwrap();
+            tgph.append(-(i as i64) * 3, "i64").unwrap();
+            tgph.append(i as f64 / 3.0, "f64").unwrap();
+        }
+
+        let mut buffer = Vec::new();
+        tgph.serialize_into(&mut buffer).unwrap();
+        let read = TGPH::deserialize_from(&mut Cursor::new(&buffer)).unwrap();
+
+        // Same version on both sides, so only the elements can differ.
+        let mut raw = Vec::new();
+        tgph.column_encoding = ColumnEncoding::Raw;
+        tgph.version = TGPH_VERSION_4;
+        tgph.serialize_into(&mut raw).unwrap();
+        let mut read_raw = Vec::new();
+        let mut read = read;
+        read.column_encoding = ColumnEncoding::Raw;
+        read.serialize_into(&mut read_raw).unwrap();
+
+        assert_eq!(read_raw, raw);
+        assert!(buffer.len() < raw.len() / 2);
+    }
+
+    #[test]
+    fn deserialize_encoded_flag_before_version_4() {
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x03, 0x01, 0x00]);
+        bytes.extend_from_slice(&[1, b'n', 0, 0, 0, 0]); // Name, Metadata
+        bytes.extend_from_slice(&[0x81]); // Element Type
+        bytes.extend_from_slice(&0_u32.to_le_bytes()); // Element Count
+
+        let mut cursor = Cursor::new(bytes);
+        assert!(matches!(
+            TGPH::deserialize_from(&mut cursor),
+            Err(TgphError::UnknownElementType {
+                element_type: 0x81,
+                offset: 13
+            })
+        ));
+    }
+
+    #[test]
+    fn deserialize_invalid_encoding() {
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x04, 0x01, 0x00]);
+        bytes.extend_from_slice(&[1, b'n', 0, 0, 0, 0]); // Name, Metadata
+        bytes.extend_from_slice(&[0x81]); // Element Type
+        bytes.extend_from_slice(&2_u32.to_le_bytes()); // Element Count
+        bytes.extend_from_slice(&8_u32.to_le_bytes()); // Encoded Length
+        bytes.extend_from_slice(&7_u64.to_be_bytes()); // Second element missing
+
+        let mut cursor = Cursor::new(bytes);
+        match TGPH::deserialize_from(&mut cursor) {
+            Err(TgphError::InvalidEncoding { offset }) => assert_eq!(offset, 22),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn deserialize_truncated_encoding() {
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x04, 0x01, 0x00]);
+        bytes.extend_from_slice(&[1, b'n', 0, 0, 0, 0]); // Name, Metadata
+        bytes.extend_from_slice(&[0x84]); // Element Type
+        bytes.extend_from_slice(&1_u32.to_le_bytes()); // Element Count
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes()); // Encoded Length
+        bytes.extend_from_slice(&7_u64.to_be_bytes());
+
+        let mut cursor = Cursor::new(bytes);
+        match TGPH::deserialize_from(&mut cursor) {
+            Err(TgphError::Truncated { offset }) => assert_eq!(offset, 30),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn deserialize_long_string() {
+        let mut bytes: Vec<u8> = Vec::new();
+        let container_name = "88g8g8g8g01023-123-13-12-31-23";
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
+        bytes.extend_from_slice(&0xff_u8.to_le_bytes());
+        bytes.extend_from_slice(&(container_name.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(container_name.as_bytes());
+        bytes.extend_from_slice(&[3]); // Element Type
+        bytes.extend_from_slice(&2_u32.to_le_bytes()); // Element Count
+        bytes.extend_from_slice(&0xff_u8.to_le_bytes());
+        bytes.extend_from_slice(&1000_u16.to_le_bytes());
+        bytes.extend_from_slice("bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".as_bytes());
+        bytes.extend_from_slice(&0xff_u8.to_le_bytes());
+        bytes.extend_from_slice(&1024_u16.to_le_bytes());
+        bytes.extend_from_slice("++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++".as_bytes());
+
+        let mut cursor = Cursor::new(bytes);
+        let tgph = TGPH::deserialize_from(&mut cursor).unwrap();
+
+        assert_eq!(
+            String::from_utf8(tgph.magic.to_le_bytes().to_vec()).unwrap(),
+            "TGPH"
+        );
+
+        assert_eq!(tgph.version, 1);
+        assert_eq!(tgph.containers.len(), 1);
+
+        assert_eq!(tgph.containers[0].name, container_name);
+        if let ElementArrayType::STRING(elements) = &tgph.containers[0].elements {
+            assert_eq!(elements.len(), 2);
+            assert_eq!(elements[0], "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb");
+            assert_eq!(elements[1], "++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++");
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn deserialize_bad_magic() {
+        let bytes: Vec<u8> = vec![0x54, 0x47, 0x50, 0x47, 0x01, 0x00, 0x00];
+        let mut cursor = Cursor::new(bytes);
+
+        match TGPH::deserialize_from(&mut cursor) {
+            Err(TgphError::BadMagic { found }) => assert_eq!(found, 0x47504754),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn deserialize_unsupported_version() {
+        let bytes: Vec<u8> = vec![0x54, 0x47, 0x50, 0x48, 0x7f, 0x00, 0x00];
+        let mut cursor = Cursor::new(bytes);
+
+        match TGPH::deserialize_from(&mut cursor) {
+            Err(TgphError::UnsupportedVersion { version, offset }) => {
+                assert_eq!(version, 0x7f);
+                assert_eq!(offset, 4);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn deserialize_unknown_element_type() {
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
+        bytes.extend_from_slice(&4_u8.to_le_bytes());
+        bytes.extend_from_slice("test".as_bytes());
+        bytes.extend_from_slice(&[0xee]); // Element Type
+        bytes.extend_from_slice(&0_u32.to_le_bytes()); // Element Count
+
+        let mut cursor = Cursor::new(bytes);
+        match TGPH::deserialize_from(&mut cursor) {
+            Err(TgphError::UnknownElementType {
+                element_type,
+                offset,
+            }) => {
+                assert_eq!(element_type, 0xee);
+                assert_eq!(offset, 12);
             }
-            _ => unreachable!(), // Should error
-        };
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn deserialize_invalid_utf8() {
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
+        bytes.extend_from_slice(&2_u8.to_le_bytes());
+        bytes.extend_from_slice(&[0xc3, 0x28]);
+        bytes.extend_from_slice(&[1]); // Element Type
+        bytes.extend_from_slice(&0_u32.to_le_bytes()); // Element Count
 
-        result.elements = elements;
+        let mut cursor = Cursor::new(bytes);
+        match TGPH::deserialize_from(&mut cursor) {
+            Err(TgphError::InvalidUtf8 { offset }) => assert_eq!(offset, 8),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn deserialize_truncated() {
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
+        bytes.extend_from_slice(&4_u8.to_le_bytes());
+        bytes.extend_from_slice("test".as_bytes());
+        bytes.extend_from_slice(&[1]); // Element Type
+        bytes.extend_from_slice(&2_u32.to_le_bytes()); // Element Count
+        bytes.extend_from_slice(&12_u32.to_le_bytes());
+
+        let mut cursor = Cursor::new(bytes);
+        match TGPH::deserialize_from(&mut cursor) {
+            Err(TgphError::Truncated { offset }) => assert_eq!(offset, 21),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn deserialize_truncated_string() {
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
+        bytes.extend_from_slice(&10_u8.to_le_bytes());
+        bytes.extend_from_slice("test".as_bytes());
 
-        Ok(result)
+        let mut cursor = Cursor::new(bytes);
+        match TGPH::deserialize_from(&mut cursor) {
+            Err(TgphError::Truncated { offset }) => assert_eq!(offset, 8),
+            _ => unreachable!(),
+        }
     }
 }
 
 #[cfg(test)]
-mod serialize {
+mod append {
     use crate::tgph_format::*;
-    use std::f32::consts::PI;
 
     #[test]
-    fn default_tgph() {
-        let tgph = TGPH::default();
-        assert_eq!(
-            String::from_utf8(tgph.magic.to_le_bytes().to_vec()).unwrap(),
-            "TGPH"
-        );
+    fn append_trims_oldest_elements() {
+        let mut tgph = TGPH::new(3);
+        for i in 0..10_u32 {
+            tgph.append(i, "counter").unwrap();
+        }
+
+        if let ElementArrayType::U32(elements) = &tgph.containers[0].elements {
+            assert_eq!(elements.iter().copied().collect::<Vec<_>>(), [7, 8, 9]);
+        } else {
+            unreachable!();
+        }
     }
 
     #[test]
-    fn write_default_tgph() {
-        let tgph = TGPH::default();
-        let mut output_buffer = Vec::new();
-        tgph.serialize_into(&mut output_buffer).unwrap();
-        assert_eq!(output_buffer, [0x54, 0x47, 0x50, 0x48, 0x01, 0x00, 0x00]);
+    fn trimmed_container_serializes_oldest_to_newest() {
+        let mut wrapped = TGPH::new(3);
+        for i in 0..5_u32 {
+            wrapped.append(i, "counter").unwrap();
+        }
+
+        let mut fresh = TGPH::new(3);
+        fresh.add_container(TGPHContainer {
+            name: "counter".into(),
+            elements: ElementArrayType::U32(vec![2, 3, 4].into()),
+            metadata: ContainerMetadata::default(),
+        });
+
+        let mut wrapped_buffer = Vec::new();
+        wrapped.serialize_into(&mut wrapped_buffer).unwrap();
+        let mut fresh_buffer = Vec::new();
+        fresh.serialize_into(&mut fresh_buffer).unwrap();
+
+        assert_eq!(wrapped_buffer, fresh_buffer);
     }
 
     #[test]
-    fn write_tgph_with_one_empty_container() {
-        let mut tgph = TGPH::default();
-        let container = TGPHContainer {
-            name: "testing".into(),
-            elements: ElementArrayType::U32(Vec::new()),
-        };
+    fn append_mismatched_type_is_rejected() {
+        let mut tgph = TGPH::new(10);
+        tgph.append(1_u32, "sensor").unwrap();
+
+        match tgph.append(1.5_f32, "sensor") {
+            Err(TgphError::TypeMismatch {
+                name,
+                container_type,
+                element_type,
+            }) => {
+                assert_eq!(name, "sensor");
+                assert_eq!(container_type, "U32");
+                assert_eq!(element_type, "FLOAT32");
+            }
+            _ => unreachable!(),
+        }
 
-        let mut expected: Vec<u8> = Vec::new();
-        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
-        expected.extend_from_slice(&(container.name.len() as u8).to_le_bytes());
-        expected.extend_from_slice(container.name.as_bytes());
-        expected.extend_from_slice(&[1]); // Element Type
-        expected.extend_from_slice(&0_u32.to_le_bytes()); // Element Count
+        if let ElementArrayType::U32(elements) = &tgph.containers[0].elements {
+            assert_eq!(elements.len(), 1);
+        } else {
+            unreachable!();
+        }
+    }
 
-        tgph.add_container(container);
-        let mut output_buffer = Vec::new();
-        tgph.serialize_into(&mut output_buffer).unwrap();
+    #[test]
+    fn append_float_widens_u32_container() {
+        let mut tgph = TGPH::new(10);
+        tgph.type_mismatch_policy = TypeMismatchPolicy::Widen;
+        tgph.append(1_u32, "sensor").unwrap();
+        tgph.append(2_u32, "sensor").unwrap();
+        tgph.append(2.5_f32, "sensor").unwrap();
+        tgph.append(3_u32, "sensor").unwrap();
+
+        if let ElementArrayType::FLOAT32(elements) = &tgph.containers[0].elements {
+            assert_eq!(
+                elements.iter().copied().collect::<Vec<_>>(),
+                [1.0, 2.0, 2.5, 3.0]
+            );
+        } else {
+            unreachable!();
+        }
+    }
 
-        assert_eq!(output_buffer, expected);
+    #[test]
+    fn widen_keeps_the_elements() {
+        let mut tgph = TGPH::new(10);
+        tgph.append(1_u32, "time").unwrap();
+        tgph.append(2_u32, "time").unwrap();
+        tgph.widen("time", ElementType::U64).unwrap();
+        tgph.append(1_u64 << 40, "time").unwrap();
+
+        let elements = &tgph.container("time").unwrap().elements;
+        assert_eq!(elements.as_u64().unwrap(), &[1, 2, 1 << 40]);
+        assert!(tgph.widen("time", ElementType::U32).is_err());
+        assert!(tgph.widen("missing", ElementType::U64).is_ok());
     }
 
     #[test]
-    fn write_tgph_with_one_filled_container() {
-        let mut tgph = TGPH::default();
-        let container = TGPHContainer {
-            name: "testing".into(),
-            elements: ElementArrayType::U32(vec![12, 34, 56, 1 << 31]),
-        };
+    fn append_u64_widens_u32_container() {
+        let mut tgph = TGPH::new(10);
+        tgph.type_mismatch_policy = TypeMismatchPolicy::Widen;
+        tgph.append(1_u32, "counter").unwrap();
+        tgph.append(1_u64 << 40, "counter").unwrap();
+        tgph.append(2_u32, "counter").unwrap();
+
+        if let ElementArrayType::U64(elements) = &tgph.containers[0].elements {
+            assert_eq!(
+                elements.iter().copied().collect::<Vec<_>>(),
+                [1, 1 << 40, 2]
+            );
+        } else {
+            unreachable!();
+        }
+    }
 
-        let mut expected: Vec<u8> = Vec::new();
-        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
-        expected.extend_from_slice(&(container.name.len() as u8).to_le_bytes());
-        expected.extend_from_slice(container.name.as_bytes());
-        expected.extend_from_slice(&[1]); // Element Type
-        expected.extend_from_slice(&4_u32.to_le_bytes()); // Element Count
-        expected.extend_from_slice(&12_u32.to_le_bytes());
-        expected.extend_from_slice(&34_u32.to_le_bytes());
-        expected.extend_from_slice(&56_u32.to_le_bytes());
-        expected.extend_from_slice(&((1 << 31) as u32).to_le_bytes());
+    #[test]
+    fn append_widens_to_common_type() {
+        let mut tgph = TGPH::new(10);
+        tgph.type_mismatch_policy = TypeMismatchPolicy::Widen;
+        tgph.append(1_u32, "signed").unwrap();
+        tgph.append(-1_i64, "signed").unwrap();
+        tgph.append(0.5_f32, "double").unwrap();
+        tgph.append(0.25_f64, "double").unwrap();
+        tgph.append(1_u64, "mixed").unwrap();
+        tgph.append(-1_i64, "mixed").unwrap();
+
+        assert_eq!(tgph.containers[0].elements.element_type(), ElementType::I64);
+        assert_eq!(
+            tgph.containers[1].elements.element_type(),
+            ElementType::FLOAT64
+        );
+        if let ElementArrayType::FLOAT64(elements) = &tgph.containers[2].elements {
+            assert_eq!(elements.iter().copied().collect::<Vec<_>>(), [1.0, -1.0]);
+        } else {
+            unreachable!();
+        }
+    }
 
-        tgph.add_container(container);
-        let mut output_buffer = Vec::new();
-        tgph.serialize_into(&mut output_buffer).unwrap();
+    #[test]
+    fn append_u32_to_float64_container_without_widening_is_rejected() {
+        let mut tgph = TGPH::new(10);
+        tgph.append(0.5_f64, "double").unwrap();
+
+        assert!(matches!(
+            tgph.append(1_u32, "double"),
+            Err(TgphError::TypeMismatch { .. })
+        ));
+    }
 
-        assert_eq!(output_buffer, expected);
+    #[test]
+    fn replace_keeps_metadata() {
+        let mut tgph = TGPH::new(10);
+        tgph.replace(vec![1_u32, 2], "readings").unwrap();
+        let metadata = ContainerMetadata::new(MetricKind::Gauge, "ppm", "CO2");
+        tgph.set_metadata("readings", metadata.clone());
+        tgph.replace(vec![3_u32], "readings").unwrap();
+
+        assert_eq!(tgph.metadata("readings"), Some(&metadata));
+        assert_eq!(tgph.containers[0].elements.len(), 1);
     }
 
     #[test]
-    fn write_tgph_with_one_filled_container_floats() {
-        let mut tgph = TGPH::default();
-        let container = TGPHContainer {
-            name: "testing".into(),
-            elements: ElementArrayType::FLOAT32(vec![PI, 1.618, 0.3]),
-        };
+    fn merge_appends_and_replaces() {
+        let mut tgph = TGPH::new(3);
+        tgph.append(1_u32, "appended").unwrap();
+        tgph.append(2_u32, "appended").unwrap();
+        tgph.replace(vec![1_u32, 2], "replaced").unwrap();
+
+        let mut sample = TGPH::new(3);
+        sample.append(3_u32, "appended").unwrap();
+        sample.append(4_u32, "appended").unwrap();
+        sample.replace(vec![5_u32], "replaced").unwrap();
+        sample.set_metadata(
+            "replaced",
+            ContainerMetadata::new(MetricKind::Gauge, "ppm", ""),
+        );
+        tgph.merge(sample).unwrap();
 
-        let mut expected: Vec<u8> = Vec::new();
-        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
-        expected.extend_from_slice(&(container.name.len() as u8).to_le_bytes());
-        expected.extend_from_slice(container.name.as_bytes());
-        expected.extend_from_slice(&[2]); // Element Type
-        expected.extend_from_slice(&3_u32.to_le_bytes()); // Element Count
-        expected.extend_from_slice(&PI.to_le_bytes());
-        expected.extend_from_slice(&1.618_f32.to_le_bytes());
-        expected.extend_from_slice(&0.3_f32.to_le_bytes());
+        if let ElementArrayType::U32(elements) = &tgph.containers[0].elements {
+            assert_eq!(elements.iter().copied().collect::<Vec<_>>(), [2, 3, 4]);
+        } else {
+            unreachable!();
+        }
+        if let ElementArrayType::U32(elements) = &tgph.containers[1].elements {
+            assert_eq!(elements.iter().copied().collect::<Vec<_>>(), [5]);
+        } else {
+            unreachable!();
+        }
+        assert_eq!(tgph.metadata("replaced").unwrap().unit, "ppm");
+    }
 
-        tgph.add_container(container);
-        let mut output_buffer = Vec::new();
-        tgph.serialize_into(&mut output_buffer).unwrap();
+    #[test]
+    fn merge_continues_after_mismatch() {
+        let mut tgph = TGPH::new(10);
+        tgph.append(1_u32, "numbers").unwrap();
+
+        let mut sample = TGPH::new(10);
+        sample.append("text".to_string(), "numbers").unwrap();
+        sample.append(2_u32, "others").unwrap();
+
+        assert!(matches!(
+            tgph.merge(sample),
+            Err(TgphError::TypeMismatch { .. })
+        ));
+        assert_eq!(tgph.containers[0].elements.len(), 1);
+        assert_eq!(tgph.containers[1].elements.len(), 1);
+    }
 
-        assert_eq!(output_buffer, expected);
+    #[test]
+    fn set_metadata_of_missing_container_does_nothing() {
+        let mut tgph = TGPH::new(10);
+        tgph.set_metadata("missing", ContainerMetadata::new(MetricKind::Info, "", ""));
+
+        assert!(tgph.containers.is_empty());
+        assert_eq!(tgph.required_version(), 1);
     }
 
     #[test]
-    fn write_tgph_with_one_filled_container_strings() {
-        let mut tgph = TGPH::default();
-        let container = TGPHContainer {
-            name: "testing".into(),
-            elements: ElementArrayType::STRING(vec![
-                "lorem".into(),
-                "foxem".into(),
-                "verylongstringemlatinem".into(),
-            ]),
-        };
+    fn append_string_to_numeric_container_is_rejected_when_widening() {
+        let mut tgph = TGPH::new(10);
+        tgph.type_mismatch_policy = TypeMismatchPolicy::Widen;
+        tgph.append(1_u32, "sensor").unwrap();
+
+        assert!(matches!(
+            tgph.append(String::from("one"), "sensor"),
+            Err(TgphError::TypeMismatch { .. })
+        ));
+    }
+}
 
-        let mut expected: Vec<u8> = Vec::new();
-        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
-        expected.extend_from_slice(&(container.name.len() as u8).to_le_bytes());
-        expected.extend_from_slice(container.name.as_bytes());
-        expected.extend_from_slice(&[3]); // Element Type
-        expected.extend_from_slice(&3_u32.to_le_bytes()); // Element Count
-        expected.extend_from_slice(&5_u8.to_le_bytes());
-        expected.extend_from_slice("lorem".as_bytes());
-        expected.extend_from_slice(&5_u8.to_le_bytes());
-        expected.extend_from_slice("foxem".as_bytes());
-        expected.extend_from_slice(&23_u8.to_le_bytes());
-        expected.extend_from_slice("verylongstringemlatinem".as_bytes());
+#[cfg(test)]
+mod axis {
+    use crate::tgph_format::*;
 
-        tgph.add_container(container);
-        let mut output_buffer = Vec::new();
-        tgph.serialize_into(&mut output_buffer).unwrap();
+    #[test]
+    fn containers_line_up_with_their_axis_at_the_newest_element() {
+        let mut tgph = TGPH::new(100);
+        for time in [100_u32, 110, 120] {
+            tgph.append(time, "time").unwrap();
+        }
+        tgph.append(1_u32, "late").unwrap();
+        tgph.append(1_u32, "no axis").unwrap();
+        tgph.set_metadata(
+            "late",
+            ContainerMetadata::new(MetricKind::Gauge, "", "").with_time_axis("time"),
+        );
 
-        assert_eq!(output_buffer, expected);
+        let late = tgph.container("late").unwrap();
+        assert_eq!(tgph.axis_offset(late), Some(2));
+        assert_eq!(late.offset_in(tgph.container("time").unwrap()), 2);
+        assert_eq!(tgph.axis_offset(tgph.container("no axis").unwrap()), None);
     }
 
     #[test]
-    fn write_tgph_with_multiple_containers_different_types() {
-        let mut tgph = TGPH::default();
-        let container1 = TGPHContainer {
-            name: "integers".into(),
-            elements: ElementArrayType::U32(vec![12, 34, 56, 1 << 31]),
-        };
-        let container2 = TGPHContainer {
-            name: "floats".into(),
-            elements: ElementArrayType::FLOAT32(vec![PI, 1.618, 0.3]),
-        };
-        let container3 = TGPHContainer {
-            name: "strings".into(),
-            elements: ElementArrayType::STRING(vec![
-                "lorem".into(),
-                "foxem".into(),
-                "verylongstringemlatinem".into(),
-            ]),
-        };
+    fn rows_join_time_axes_on_equal_times() {
+        let mut tgph = TGPH::new(100);
+        for time in [100_u32, 110, 120] {
+            tgph.append(time, "time").unwrap();
+        }
+        for value in [1_u32, 2] {
+            tgph.append(value, "late").unwrap();
+        }
+        for time in [110_u32, 130] {
+            tgph.append(time, "other time").unwrap();
+            tgph.append(time, "other").unwrap();
+        }
+        tgph.append(1_u32, "no axis").unwrap();
+        for (name, axis) in [("late", "time"), ("other", "other time")] {
+            tgph.set_metadata(
+                name,
+                ContainerMetadata::new(MetricKind::Gauge, "", "").with_time_axis(axis),
+            );
+        }
 
-        let mut expected: Vec<u8> = Vec::new();
-        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x03, 0x00]);
-        expected.extend_from_slice(&(container1.name.len() as u8).to_le_bytes());
-        expected.extend_from_slice(container1.name.as_bytes());
-        expected.extend_from_slice(&[1]); // Element Type
-        expected.extend_from_slice(&4_u32.to_le_bytes()); // Element Count
-        expected.extend_from_slice(&12_u32.to_le_bytes());
-        expected.extend_from_slice(&34_u32.to_le_bytes());
-        expected.extend_from_slice(&56_u32.to_le_bytes());
-        expected.extend_from_slice(&((1 << 31) as u32).to_le_bytes());
-        expected.extend_from_slice(&(container2.name.len() as u8).to_le_bytes());
-        expected.extend_from_slice(container2.name.as_bytes());
-        expected.extend_from_slice(&[2]); // Element Type
-        expected.extend_from_slice(&3_u32.to_le_bytes()); // Element Count
-        expected.extend_from_slice(&PI.to_le_bytes());
-        expected.extend_from_slice(&1.618_f32.to_le_bytes());
-        expected.extend_from_slice(&0.3_f32.to_le_bytes());
-        expected.extend_from_slice(&(container3.name.len() as u8).to_le_bytes());
-        expected.extend_from_slice(container3.name.as_bytes());
-        expected.extend_from_slice(&[3]); // Element Type
-        expected.extend_from_slice(&3_u32.to_le_bytes()); // Element Count
-        expected.extend_from_slice(&5_u8.to_le_bytes());
-        expected.extend_from_slice("lorem".as_bytes());
-        expected.extend_from_slice(&5_u8.to_le_bytes());
-        expected.extend_from_slice("foxem".as_bytes());
-        expected.extend_from_slice(&23_u8.to_le_bytes());
-        expected.extend_from_slice("verylongstringemlatinem".as_bytes());
+        let (columns, rows) = tgph.rows();
+        let names: Vec<_> = columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["late", "other"]);
+        // Nothing but the time axis was sampled at 100.
+        let rows: Vec<_> = rows.into_iter().collect();
+        assert_eq!(
+            rows,
+            [
+                (110, vec![Some(0), Some(0)]),
+                (120, vec![Some(1), None]),
+                (130, vec![None, Some(1)]),
+            ]
+        );
+    }
+}
 
-        tgph.add_container(container1);
-        tgph.add_container(container2);
-        tgph.add_container(container3);
-        let mut output_buffer = Vec::new();
-        tgph.serialize_into(&mut output_buffer).unwrap();
+#[cfg(test)]
+mod retention {
+    use crate::tgph_format::*;
 
-        assert_eq!(output_buffer, expected);
+    /// Samples taken every 10 seconds from 100 to 150, with a "value" on the
+    /// "time" axis and an "info" container without one.
+    fn sampled_tgph() -> TGPH {
+        let mut tgph = TGPH::new(100);
+        for time in (100..=150_u32).step_by(10) {
+            tgph.append(time, "time").unwrap();
+            tgph.append(time * 2, "value").unwrap();
+            tgph.append(time, "info").unwrap();
+        }
+        tgph.set_metadata(
+            "value",
+            ContainerMetadata::new(MetricKind::Gauge, "", "").with_time_axis("time"),
+        );
+        tgph
+    }
+
+    fn u32_elements(tgph: &TGPH, name: &str) -> Vec<u32> {
+        match &tgph
+            .containers
+            .iter()
+            .find(|c| c.name == name)
+            .unwrap()
+            .elements
+        {
+            ElementArrayType::U32(elements) => elements.iter().copied().collect(),
+            _ => unreachable!(),
+        }
     }
 
     #[test]
-    fn long_container_name() {
-        let mut tgph = TGPH::default();
-        let container = TGPHContainer {
-            name: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".into(),
-            elements: ElementArrayType::U32(vec![12, 34, 56, 1 << 31]),
+    fn old_elements_are_dropped() {
+        let mut tgph = sampled_tgph();
+        let retention = Retention {
+            max_age: Some(25),
+            ..Retention::default()
         };
+        tgph.apply_retention(&retention, 150);
 
-        let mut expected: Vec<u8> = Vec::new();
-        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
-        expected.extend_from_slice(&0xff_u8.to_le_bytes());
-        expected.extend_from_slice(&(container.name.len() as u16).to_le_bytes());
-        expected.extend_from_slice(container.name.as_bytes());
-        expected.extend_from_slice(&[1]); // Element Type
-        expected.extend_from_slice(&4_u32.to_le_bytes()); // Element Count
-        expected.extend_from_slice(&12_u32.to_le_bytes());
-        expected.extend_from_slice(&34_u32.to_le_bytes());
-        expected.extend_from_slice(&56_u32.to_le_bytes());
-        expected.extend_from_slice(&((1 << 31) as u32).to_le_bytes());
+        assert_eq!(u32_elements(&tgph, "time"), [130, 140, 150]);
+        assert_eq!(u32_elements(&tgph, "value"), [260, 280, 300]);
+        assert_eq!(u32_elements(&tgph, "info").len(), 6);
+    }
 
-        tgph.add_container(container);
-        let mut output_buffer = Vec::new();
-        tgph.serialize_into(&mut output_buffer).unwrap();
+    #[test]
+    fn no_max_age_keeps_everything() {
+        let mut tgph = sampled_tgph();
+        tgph.apply_retention(&Retention::default(), 1000);
 
-        assert_eq!(output_buffer, expected);
+        assert_eq!(u32_elements(&tgph, "time").len(), 6);
+        assert_eq!(u32_elements(&tgph, "value").len(), 6);
     }
 
     #[test]
-    fn long_container_name_and_long_string_elements() {
-        let mut tgph = TGPH::default();
-        let container = TGPHContainer {
-            name: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".into(),
-            elements: ElementArrayType::STRING(vec![
-                                               "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".into(),
-                                               "++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++".into(),
-            ]),
+    fn time_axis_lives_as_long_as_its_containers() {
+        let mut tgph = sampled_tgph();
+        let retention = Retention {
+            max_age: Some(5),
+            overrides: HashMap::from([("value".to_string(), Some(35))]),
+            ..Retention::default()
         };
+        tgph.apply_retention(&retention, 150);
 
-        let mut expected: Vec<u8> = Vec::new();
-        expected.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
-        expected.extend_from_slice(&0xff_u8.to_le_bytes());
-        expected.extend_from_slice(&(container.name.len() as u16).to_le_bytes());
-        expected.extend_from_slice(container.name.as_bytes());
-        expected.extend_from_slice(&[3]); // Element Type
-        expected.extend_from_slice(&2_u32.to_le_bytes()); // Element Count
-        expected.extend_from_slice(&0xff_u8.to_le_bytes());
-        expected.extend_from_slice(&1000_u16.to_le_bytes());
-        expected.extend_from_slice("bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".as_bytes());
-        expected.extend_from_slice(&0xff_u8.to_le_bytes());
-        expected.extend_from_slice(&1024_u16.to_le_bytes());
-        expected.extend_from_slice("++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++".as_bytes());
+        assert_eq!(u32_elements(&tgph, "time"), [120, 130, 140, 150]);
+        assert_eq!(u32_elements(&tgph, "value"), [240, 260, 280, 300]);
+    }
 
-        tgph.add_container(container);
-        let mut output_buffer = Vec::new();
-        tgph.serialize_into(&mut output_buffer).unwrap();
+    #[test]
+    fn override_keeps_container_forever() {
+        let mut tgph = sampled_tgph();
+        let retention = Retention {
+            max_age: Some(5),
+            overrides: HashMap::from([("value".to_string(), None)]),
+            ..Retention::default()
+        };
+        tgph.apply_retention(&retention, 150);
 
-        assert_eq!(output_buffer, expected);
+        assert_eq!(u32_elements(&tgph, "time").len(), 6);
+        assert_eq!(u32_elements(&tgph, "value").len(), 6);
+    }
+
+    #[test]
+    fn shorter_container_lines_up_at_newest_element() {
+        let mut tgph = sampled_tgph();
+        tgph.replace(vec![1_u32, 2, 3], "late").unwrap();
+        tgph.set_metadata(
+            "late",
+            ContainerMetadata::new(MetricKind::Gauge, "", "").with_time_axis("time"),
+        );
+        let retention = Retention {
+            max_age: Some(15),
+            ..Retention::default()
+        };
+        tgph.apply_retention(&retention, 150);
+
+        assert_eq!(u32_elements(&tgph, "time"), [140, 150]);
+        assert_eq!(u32_elements(&tgph, "late"), [2, 3]);
     }
 }
 
 #[cfg(test)]
-mod deserialize {
-    use std::f32::consts::PI;
+mod index {
     use std::io::Cursor;
 
     use crate::tgph_format::*;
-    #[test]
-    fn deserialize_without_containers() {
-        let bytes: Vec<u8> = vec![0x54, 0x47, 0x50, 0x48, 0x01, 0x00, 0x00];
-        let mut cursor = Cursor::new(bytes);
-        let tgph = TGPH::deserialize_from(&mut cursor).unwrap();
 
-        assert_eq!(
-            String::from_utf8(tgph.magic.to_le_bytes().to_vec()).unwrap(),
-            "TGPH"
-        );
+    fn sampled_tgph(container_index: bool) -> TGPH {
+        let mut tgph = TGPH::new(100);
+        for time in (100..=150_u32).step_by(10) {
+            tgph.append(time, "time").unwrap();
+            tgph.append(time as f32 / 2.0, "value").unwrap();
+        }
+        tgph.append("pi".to_string(), "host").unwrap();
+        tgph.container_index = container_index;
+        tgph
+    }
 
-        assert_eq!(tgph.version, 1);
-        assert_eq!(tgph.containers.len(), 0);
+    fn serialized(tgph: &TGPH) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        tgph.serialize_into(&mut bytes).unwrap();
+        bytes
     }
 
     #[test]
-    fn deserialize_one_empty_container() {
-        let mut bytes: Vec<u8> = Vec::new();
-        let container_name = "test";
-
-        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
-        bytes.extend_from_slice(&(container_name.len() as u8).to_le_bytes());
-        bytes.extend_from_slice(container_name.as_bytes());
-        bytes.extend_from_slice(&[1]); // Element Type
-        bytes.extend_from_slice(&0_u32.to_le_bytes()); // Element Count
+    fn entries_match_the_layout() {
+        let bytes = serialized(&sampled_tgph(true));
+        let (_, layout) = TGPH::deserialize_with_layout_from(&mut Cursor::new(&bytes)).unwrap();
+        let index = TGPH::read_index(&mut Cursor::new(&bytes)).unwrap().unwrap();
+
+        let names: Vec<_> = index.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["time", "value", "host"]);
+        let found: Vec<_> = index.iter().map(|e| e.layout).collect();
+        assert_eq!(found, layout);
+        assert_eq!(index[1].element_type, ElementType::FLOAT32);
+        assert_eq!(index[1].element_count, 6);
+    }
 
-        let mut cursor = Cursor::new(bytes);
-        let tgph = TGPH::deserialize_from(&mut cursor).unwrap();
+    #[test]
+    fn readers_not_looking_for_the_index_ignore_it() {
+        let tgph = sampled_tgph(true);
+        let bytes = serialized(&tgph);
+        assert!(bytes.len() > serialized(&sampled_tgph(false)).len());
 
+        let read = TGPH::deserialize_from(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(read.containers, tgph.containers);
         assert_eq!(
-            String::from_utf8(tgph.magic.to_le_bytes().to_vec()).unwrap(),
-            "TGPH"
+            crate::TgphView::parse(&bytes).unwrap().containers().len(),
+            3
         );
 
-        assert_eq!(tgph.version, 1);
-        assert_eq!(tgph.containers.len(), 1);
+        // The index is kept when the file is written again, unless turned off.
+        assert!(read.container_index);
+        assert_eq!(serialized(&read), bytes);
+        let mut unindexed = read;
+        unindexed.container_index = false;
+        // Version 6 comes with the checksums of version 5.
+        let mut expected = sampled_tgph(false);
+        expected.checksums = true;
+        assert_eq!(serialized(&unindexed), serialized(&expected));
+    }
 
-        assert_eq!(tgph.containers[0].name, container_name);
-        if let ElementArrayType::U32(elements) = &tgph.containers[0].elements {
-            assert_eq!(elements.len(), 0);
-        } else {
-            unreachable!();
+    #[test]
+    fn read_single_container() {
+        for container_index in [true, false] {
+            let tgph = sampled_tgph(container_index);
+            let mut cursor = Cursor::new(serialized(&tgph));
+
+            assert_eq!(
+                TGPH::read_index(&mut cursor).unwrap().is_some(),
+                container_index
+            );
+            let value = TGPH::read_container(&mut cursor, "value").unwrap();
+            assert_eq!(value.as_ref(), tgph.container("value"));
+            let host = TGPH::read_container(&mut cursor, "host").unwrap();
+            assert_eq!(host.as_ref(), tgph.container("host"));
+            assert!(TGPH::read_container(&mut cursor, "missing")
+                .unwrap()
+                .is_none());
         }
     }
 
     #[test]
-    fn deserialize_one_filled_container() {
-        let mut bytes: Vec<u8> = Vec::new();
-        let container_name = "bigredbarrow";
-        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
-        bytes.extend_from_slice(&(container_name.len() as u8).to_le_bytes());
-        bytes.extend_from_slice(container_name.as_bytes());
-        bytes.extend_from_slice(&[1]); // Element Type
-        bytes.extend_from_slice(&4_u32.to_le_bytes()); // Element Count
-        bytes.extend_from_slice(&12_u32.to_le_bytes());
-        bytes.extend_from_slice(&34_u32.to_le_bytes());
-        bytes.extend_from_slice(&56_u32.to_le_bytes());
-        bytes.extend_from_slice(&((1 << 31) as u32).to_le_bytes());
+    fn index_is_only_read_when_marked_in_the_header() {
+        let mut tgph = sampled_tgph(false);
+        tgph.append("length, checksum and magic TGPI".to_string(), "host")
+            .unwrap();
+        let bytes = serialized(&tgph);
+        assert!(bytes.ends_with(b"TGPI"));
 
         let mut cursor = Cursor::new(bytes);
-        let tgph = TGPH::deserialize_from(&mut cursor).unwrap();
-
-        assert_eq!(
-            String::from_utf8(tgph.magic.to_le_bytes().to_vec()).unwrap(),
-            "TGPH"
-        );
-
-        assert_eq!(tgph.version, 1);
-        assert_eq!(tgph.containers.len(), 1);
-
-        assert_eq!(tgph.containers[0].name, container_name);
-        if let ElementArrayType::U32(elements) = &tgph.containers[0].elements {
-            assert_eq!(elements.len(), 4);
-            assert_eq!(elements[0], 12);
-            assert_eq!(elements[1], 34);
-            assert_eq!(elements[2], 56);
-            assert_eq!(elements[3], 1 << 31);
-        } else {
-            unreachable!();
-        }
+        assert!(TGPH::read_index(&mut cursor).unwrap().is_none());
+        let host = TGPH::read_container(&mut cursor, "host").unwrap();
+        assert_eq!(host.as_ref(), tgph.container("host"));
     }
 
     #[test]
-    fn deserialize_multiple_containers_different_types() {
-        let mut bytes: Vec<u8> = Vec::new();
-        let container_name1 = "quick";
-        let container_name2 = "red fox";
-        let container_name3 = "jumped over THE LAZY";
-        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x03, 0x00]);
-        bytes.extend_from_slice(&(container_name1.len() as u8).to_le_bytes());
-        bytes.extend_from_slice(container_name1.as_bytes());
-        bytes.extend_from_slice(&[1]); // Element Type
-        bytes.extend_from_slice(&4_u32.to_le_bytes()); // Element Count
-        bytes.extend_from_slice(&12_u32.to_le_bytes());
-        bytes.extend_from_slice(&34_u32.to_le_bytes());
-        bytes.extend_from_slice(&56_u32.to_le_bytes());
-        bytes.extend_from_slice(&((1 << 31) as u32).to_le_bytes());
-        bytes.extend_from_slice(&(container_name2.len() as u8).to_le_bytes());
-        bytes.extend_from_slice(container_name2.as_bytes());
-        bytes.extend_from_slice(&[2]); // Element Type
-        bytes.extend_from_slice(&3_u32.to_le_bytes()); // Element Count
-        bytes.extend_from_slice(&PI.to_le_bytes());
-        bytes.extend_from_slice(&1.618_f32.to_le_bytes());
-        bytes.extend_from_slice(&0.3_f32.to_le_bytes());
-        bytes.extend_from_slice(&(container_name3.len() as u8).to_le_bytes());
-        bytes.extend_from_slice(container_name3.as_bytes());
-        bytes.extend_from_slice(&[3]); // Element Type
-        bytes.extend_from_slice(&3_u32.to_le_bytes()); // Element Count
-        bytes.extend_from_slice(&5_u8.to_le_bytes());
-        bytes.extend_from_slice("lorem".as_bytes());
-        bytes.extend_from_slice(&5_u8.to_le_bytes());
-        bytes.extend_from_slice("foxem".as_bytes());
-        bytes.extend_from_slice(&23_u8.to_le_bytes());
-        bytes.extend_from_slice("verylongstringemlatinem".as_bytes());
+    fn damaged_index_is_rejected() {
+        let mut bytes = serialized(&sampled_tgph(true));
+        let entries_end = bytes.len() - INDEX_TRAILER_SIZE as usize;
+        bytes[entries_end - 1] ^= 0x01;
+
+        assert!(matches!(
+            TGPH::read_index(&mut Cursor::new(&bytes)),
+            Err(TgphError::InvalidIndex { .. })
+        ));
+    }
 
-        let mut cursor = Cursor::new(bytes);
-        let tgph = TGPH::deserialize_from(&mut cursor).unwrap();
+    #[test]
+    fn entries_out_of_range_are_rejected() {
+        let mut bytes = serialized(&sampled_tgph(true));
+        // Point the offset of the last entry past the end of the containers,
+        // with a checksum matching the changed index.
+        let entries_end = bytes.len() - INDEX_TRAILER_SIZE as usize;
+        let offset_at = entries_end - 16;
+        bytes[offset_at..offset_at + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        let length = u32::from_le_bytes(bytes[entries_end..entries_end + 4].try_into().unwrap());
+        let checksum = crc32c::crc32c(&bytes[entries_end - length as usize..entries_end]);
+        bytes[entries_end + 4..entries_end + 8].copy_from_slice(&checksum.to_le_bytes());
+
+        assert!(matches!(
+            TGPH::read_index(&mut Cursor::new(&bytes)),
+            Err(TgphError::InvalidIndex { offset }) if offset < entries_end as u64
+        ));
+    }
+}
 
-        assert_eq!(
-            String::from_utf8(tgph.magic.to_le_bytes().to_vec()).unwrap(),
-            "TGPH"
-        );
+#[cfg(test)]
+mod checksums {
+    use std::io::Cursor;
 
-        assert_eq!(tgph.version, 1);
-        assert_eq!(tgph.containers.len(), 3);
+    use crate::tgph_format::*;
 
-        assert_eq!(tgph.containers[0].name, container_name1);
-        if let ElementArrayType::U32(elements) = &tgph.containers[0].elements {
-            assert_eq!(elements.len(), 4);
-            assert_eq!(elements[0], 12);
-            assert_eq!(elements[1], 34);
-            assert_eq!(elements[2], 56);
-            assert_eq!(elements[3], 1 << 31);
-        } else {
-            unreachable!();
+    fn checked_tgph(container_index: bool) -> TGPH {
+        let mut tgph = TGPH::new(100);
+        for time in (100..=150_u32).step_by(10) {
+            tgph.append(time, "time").unwrap();
+            tgph.append(time as f32 / 2.0, "value").unwrap();
         }
+        tgph.append("pi".to_string(), "host").unwrap();
+        tgph.checksums = true;
+        tgph.container_index = container_index;
+        tgph
+    }
 
-        assert_eq!(tgph.containers[1].name, container_name2);
-        if let ElementArrayType::FLOAT32(elements) = &tgph.containers[1].elements {
-            assert_eq!(elements.len(), 3);
-            assert_eq!(elements[0], PI);
-            assert_eq!(elements[1], 1.618);
-            assert_eq!(elements[2], 0.3);
-        } else {
-            unreachable!();
-        }
+    fn serialized(tgph: &TGPH) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        tgph.serialize_into(&mut bytes).unwrap();
+        bytes
+    }
 
-        assert_eq!(tgph.containers[2].name, container_name3);
-        if let ElementArrayType::STRING(elements) = &tgph.containers[2].elements {
-            assert_eq!(elements.len(), 3);
-            assert_eq!(elements[0], "lorem");
-            assert_eq!(elements[1], "foxem");
-            assert_eq!(elements[2], "verylongstringemlatinem");
-        } else {
-            unreachable!();
-        }
+    fn layout(bytes: &[u8]) -> Vec<ContainerLayout> {
+        TGPH::deserialize_with_layout_from(&mut Cursor::new(bytes))
+            .unwrap()
+            .1
     }
 
     #[test]
-    fn deserialize_long_string() {
-        let mut bytes: Vec<u8> = Vec::new();
-        let container_name = "88g8g8g8g01023-123-13-12-31-23";
-        bytes.extend_from_slice(&[0x54, 0x47, 0x50, 0x48, 0x01, 0x01, 0x00]);
-        bytes.extend_from_slice(&0xff_u8.to_le_bytes());
-        bytes.extend_from_slice(&(container_name.len() as u16).to_le_bytes());
-        bytes.extend_from_slice(container_name.as_bytes());
-        bytes.extend_from_slice(&[3]); // Element Type
-        bytes.extend_from_slice(&2_u32.to_le_bytes()); // Element Count
-        bytes.extend_from_slice(&0xff_u8.to_le_bytes());
-        bytes.extend_from_slice(&1000_u16.to_le_bytes());
-        bytes.extend_from_slice("bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".as_bytes());
-        bytes.extend_from_slice(&0xff_u8.to_le_bytes());
-        bytes.extend_from_slice(&1024_u16.to_le_bytes());
-        bytes.extend_from_slice("++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++".as_bytes());
+    fn checksums_need_version_5() {
+        let mut tgph = checked_tgph(false);
+        tgph.set_metadata(
+            "value",
+            ContainerMetadata::new(MetricKind::Gauge, "", "").with_time_axis("time"),
+        );
+        assert_eq!(tgph.required_version(), TGPH_VERSION_5);
+
+        let bytes = serialized(&tgph);
+        let read = TGPH::deserialize_from(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(read.version(), TGPH_VERSION_5);
+        assert_eq!(read.containers, tgph.containers);
+        // The checksums are kept when the file is written again.
+        assert!(read.checksums);
+        assert_eq!(serialized(&read), bytes);
+
+        // Turned off, the file goes back to the version before them, with
+        // 4 bytes less for every container and the whole file.
+        let mut unchecked = read;
+        unchecked.checksums = false;
+        let unchecked = serialized(&unchecked);
+        assert_eq!(unchecked[4], TGPH_VERSION_3);
+        assert_eq!(bytes.len(), unchecked.len() + 4 * 4);
+    }
 
-        let mut cursor = Cursor::new(bytes);
-        let tgph = TGPH::deserialize_from(&mut cursor).unwrap();
+    #[test]
+    fn damaged_containers_are_named() {
+        let mut bytes = serialized(&checked_tgph(true));
+        let value = layout(&bytes)[1];
+        bytes[(value.offset + value.length) as usize - 6] ^= 0x10;
+
+        let err = TGPH::deserialize_from(&mut Cursor::new(&bytes)).err();
+        assert!(matches!(
+            &err,
+            Some(TgphError::ChecksumMismatch { container: Some(name), offset })
+                if name == "value" && *offset == value.offset + value.length - 4
+        ));
+        assert!(TGPH::read_container(&mut Cursor::new(&bytes), "value").is_err());
+        assert!(TGPH::read_container(&mut Cursor::new(&bytes), "host").is_ok());
+    }
 
-        assert_eq!(
-            String::from_utf8(tgph.magic.to_le_bytes().to_vec()).unwrap(),
-            "TGPH"
-        );
+    #[test]
+    fn damaged_header_fails_the_file_checksum() {
+        let mut bytes = serialized(&checked_tgph(false));
+        // One container less, so the last one is read as the checksum of
+        // the file.
+        bytes[5] = 2;
+
+        let err = TGPH::deserialize_from(&mut Cursor::new(&bytes)).err();
+        assert!(matches!(
+            err,
+            Some(TgphError::ChecksumMismatch {
+                container: None,
+                ..
+            })
+        ));
+    }
 
-        assert_eq!(tgph.version, 1);
-        assert_eq!(tgph.containers.len(), 1);
+    #[test]
+    fn intact_containers_are_salvaged() {
+        let tgph = checked_tgph(false);
+        let mut bytes = serialized(&tgph);
+        let salvage = TGPH::salvage(&bytes).unwrap();
+        assert!(salvage.is_intact());
+        assert_eq!(salvage.tgph.containers, tgph.containers);
+
+        let time = layout(&bytes)[0];
+        bytes[(time.offset + time.length) as usize - 6] ^= 0x01;
+        let salvage = TGPH::salvage(&bytes).unwrap();
+        assert!(!salvage.is_intact());
+        assert_eq!(salvage.tgph.containers, tgph.containers[1..]);
+        assert_eq!(salvage.containers[0].name.as_deref(), Some("time"));
+        assert!(salvage.containers[0].error.is_some());
+        assert!(salvage.containers[1].error.is_none());
+        assert!(salvage.file_error.is_some());
+    }
 
-        assert_eq!(tgph.containers[0].name, container_name);
-        if let ElementArrayType::STRING(elements) = &tgph.containers[0].elements {
-            assert_eq!(elements.len(), 2);
-            assert_eq!(elements[0], "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb");
-            assert_eq!(elements[1], "++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++");
-        } else {
-            unreachable!();
+    #[test]
+    fn index_finds_containers_after_unreadable_ones() {
+        for container_index in [true, false] {
+            let tgph = checked_tgph(container_index);
+            let mut bytes = serialized(&tgph);
+            // An unknown element type, after which the end of the container
+            // is unknown.
+            bytes[header_size(tgph.required_version()) as usize + 9] = 0x7f;
+
+            let salvage = TGPH::salvage(&bytes).unwrap();
+            assert!(matches!(
+                salvage.containers[0].error,
+                Some(TgphError::UnknownElementType { .. })
+            ));
+            if container_index {
+                assert_eq!(salvage.containers[0].name.as_deref(), Some("time"));
+                assert_eq!(salvage.tgph.containers, tgph.containers[1..]);
+                assert_eq!(salvage.lost, 0);
+            } else {
+                assert_eq!(salvage.containers[0].name, None);
+                assert!(salvage.tgph.containers.is_empty());
+                assert_eq!(salvage.lost, 2);
+            }
         }
     }
 }
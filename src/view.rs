@@ -0,0 +1,763 @@
+//! Read-only access to an uncompressed TGPH in a borrowed buffer, e.g. a
+//! decompressed file or an mmap of one, without copying the elements out.
+//!
+//! `TgphView::parse` walks the buffer once to find the containers and check
+//! them, after which elements are read straight out of the buffer when they
+//! are asked for. Nothing assumes the buffer to be aligned, numbers are read
+//! with `from_le_bytes` from byte slices.
+
+use std::marker::PhantomData;
+use std::slice::ChunksExact;
+
+use crate::column_encoding::{FloatDecoder, IntegerDecoder};
+use crate::tgph_format::{
+    ContainerLayout, ContainerMetadata, ElementArrayType, ElementType, MetricKind, TGPHContainer,
+    TgphError, ENCODED_FLAG, TGPH_LATEST_VERSION, TGPH_MAGIC, TGPH_VERSION_1, TGPH_VERSION_3,
+    TGPH_VERSION_4, TGPH_VERSION_5, TGPH_VERSION_6,
+};
+
+/// The numeric element types, as stored in a TGPH.
+pub trait Number: Copy + std::fmt::Debug {
+    /// Bytes of a raw element.
+    const SIZE: usize;
+    /// Whether compact containers of the type use the float encoding rather
+    /// than the integer one.
+    const FLOAT: bool;
+
+    /// Reads a raw element from exactly `SIZE` little endian bytes.
+    fn from_le_slice(bytes: &[u8]) -> Self;
+    /// Converts bits of the compact encoding, `None` if they do not fit.
+    fn from_encoded(bits: u64) -> Option<Self>;
+}
+
+impl Number for u32 {
+    const SIZE: usize = 4;
+    const FLOAT: bool = false;
+
+    fn from_le_slice(bytes: &[u8]) -> Self {
+        Self::from_le_bytes(bytes.try_into().unwrap())
+    }
+
+    fn from_encoded(bits: u64) -> Option<Self> {
+        Self::try_from(bits).ok()
+    }
+}
+
+impl Number for f32 {
+    const SIZE: usize = 4;
+    const FLOAT: bool = true;
+
+    fn from_le_slice(bytes: &[u8]) -> Self {
+        Self::from_le_bytes(bytes.try_into().unwrap())
+    }
+
+    fn from_encoded(bits: u64) -> Option<Self> {
+        u32::try_from(bits).ok().map(Self::from_bits)
+    }
+}
+
+impl Number for u64 {
+    const SIZE: usize = 8;
+    const FLOAT: bool = false;
+
+    fn from_le_slice(bytes: &[u8]) -> Self {
+        Self::from_le_bytes(bytes.try_into().unwrap())
+    }
+
+    fn from_encoded(bits: u64) -> Option<Self> {
+        Some(bits)
+    }
+}
+
+impl Number for i64 {
+    const SIZE: usize = 8;
+    const FLOAT: bool = false;
+
+    fn from_le_slice(bytes: &[u8]) -> Self {
+        Self::from_le_bytes(bytes.try_into().unwrap())
+    }
+
+    fn from_encoded(bits: u64) -> Option<Self> {
+        Some(bits as i64)
+    }
+}
+
+impl Number for f64 {
+    const SIZE: usize = 8;
+    const FLOAT: bool = true;
+
+    fn from_le_slice(bytes: &[u8]) -> Self {
+        Self::from_le_bytes(bytes.try_into().unwrap())
+    }
+
+    fn from_encoded(bits: u64) -> Option<Self> {
+        Some(Self::from_bits(bits))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Storage<'a> {
+    /// `count` little endian values.
+    Raw(&'a [u8]),
+    /// The payload of a container stored with `ColumnEncoding::Compact`.
+    Compact(&'a [u8]),
+}
+
+/// The elements of a numeric container.
+#[derive(Clone, Copy, Debug)]
+pub struct Numbers<'a, T> {
+    storage: Storage<'a>,
+    count: u32,
+    element: PhantomData<T>,
+}
+
+impl<'a, T: Number> Numbers<'a, T> {
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The element at `index`. Compact containers have to be decoded up to
+    /// it, so iterate rather than calling this for every index.
+    pub fn get(&self, index: usize) -> Option<T> {
+        match self.storage {
+            Storage::Raw(bytes) => bytes
+                .get(index * T::SIZE..(index + 1) * T::SIZE)
+                .map(T::from_le_slice),
+            Storage::Compact(_) => self.iter().nth(index),
+        }
+    }
+
+    pub fn iter(&self) -> NumberIter<'a, T> {
+        let decoder = match self.storage {
+            Storage::Raw(bytes) => Decoder::Raw(bytes.chunks_exact(T::SIZE)),
+            Storage::Compact(payload) if T::FLOAT => {
+                Decoder::Floats(FloatDecoder::new(payload, self.count))
+            }
+            Storage::Compact(payload) => {
+                Decoder::Integers(IntegerDecoder::new(payload, self.count))
+            }
+        };
+        NumberIter {
+            decoder,
+            element: PhantomData,
+        }
+    }
+
+    /// The elements as little endian values of `T::SIZE` bytes, `None` for
+    /// compact containers.
+    pub fn raw_bytes(&self) -> Option<&'a [u8]> {
+        match self.storage {
+            Storage::Raw(bytes) => Some(bytes),
+            Storage::Compact(_) => None,
+        }
+    }
+}
+
+impl<'a, T: Number> IntoIterator for &Numbers<'a, T> {
+    type Item = T;
+    type IntoIter = NumberIter<'a, T>;
+
+    fn into_iter(self) -> NumberIter<'a, T> {
+        self.iter()
+    }
+}
+
+#[derive(Clone)]
+enum Decoder<'a> {
+    Raw(ChunksExact<'a, u8>),
+    Integers(IntegerDecoder<'a>),
+    Floats(FloatDecoder<'a>),
+}
+
+/// Iterator over the elements of `Numbers`, oldest first.
+#[derive(Clone)]
+pub struct NumberIter<'a, T> {
+    decoder: Decoder<'a>,
+    element: PhantomData<T>,
+}
+
+impl<T: Number> Iterator for NumberIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        // Compact payloads were checked to decode to values of `T` while
+        // parsing.
+        match &mut self.decoder {
+            Decoder::Raw(chunks) => chunks.next().map(T::from_le_slice),
+            Decoder::Integers(decoder) => decoder.next().and_then(T::from_encoded),
+            Decoder::Floats(decoder) => decoder.next().and_then(T::from_encoded),
+        }
+    }
+}
+
+/// Splits a string as stored in a TGPH off the front of `bytes`, returning
+/// its bytes and the remainder.
+pub(crate) fn split_string(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (&length, rest) = bytes.split_first()?;
+    let (length, rest) = match length {
+        0xff => {
+            let (length, rest) = rest.split_at_checked(2)?;
+            (u16::from_le_bytes([length[0], length[1]]) as usize, rest)
+        }
+        length => (length as usize, rest),
+    };
+    rest.split_at_checked(length)
+}
+
+/// The elements of a string container.
+#[derive(Clone, Copy, Debug)]
+pub struct Strings<'a> {
+    bytes: &'a [u8],
+    count: u32,
+}
+
+impl<'a> Strings<'a> {
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn iter(&self) -> StringIter<'a> {
+        StringIter {
+            bytes: self.bytes,
+            remaining: self.count,
+        }
+    }
+}
+
+impl<'a> IntoIterator for &Strings<'a> {
+    type Item = &'a str;
+    type IntoIter = StringIter<'a>;
+
+    fn into_iter(self) -> StringIter<'a> {
+        self.iter()
+    }
+}
+
+/// Iterator over the elements of `Strings`, oldest first.
+#[derive(Clone)]
+pub struct StringIter<'a> {
+    bytes: &'a [u8],
+    remaining: u32,
+}
+
+impl<'a> Iterator for StringIter<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.remaining = self.remaining.checked_sub(1)?;
+        // Lengths and UTF-8 were checked while parsing.
+        let (string, rest) = split_string(self.bytes)?;
+        self.bytes = rest;
+        std::str::from_utf8(string).ok()
+    }
+}
+
+/// The elements of a container, borrowed from the buffer.
+#[derive(Clone, Copy, Debug)]
+pub enum ElementsView<'a> {
+    U32(Numbers<'a, u32>),
+    FLOAT32(Numbers<'a, f32>),
+    STRING(Strings<'a>),
+    U64(Numbers<'a, u64>),
+    I64(Numbers<'a, i64>),
+    FLOAT64(Numbers<'a, f64>),
+}
+
+impl ElementsView<'_> {
+    pub fn element_type(&self) -> ElementType {
+        match self {
+            Self::U32(_) => ElementType::U32,
+            Self::FLOAT32(_) => ElementType::FLOAT32,
+            Self::STRING(_) => ElementType::STRING,
+            Self::U64(_) => ElementType::U64,
+            Self::I64(_) => ElementType::I64,
+            Self::FLOAT64(_) => ElementType::FLOAT64,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::U32(arr) => arr.len(),
+            Self::FLOAT32(arr) => arr.len(),
+            Self::STRING(arr) => arr.len(),
+            Self::U64(arr) => arr.len(),
+            Self::I64(arr) => arr.len(),
+            Self::FLOAT64(arr) => arr.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The element at `index` converted to f64, `None` for strings.
+    pub fn get_f64(&self, index: usize) -> Option<f64> {
+        match self {
+            Self::U32(arr) => arr.get(index).map(|e| e as f64),
+            Self::FLOAT32(arr) => arr.get(index).map(|e| e as f64),
+            Self::U64(arr) => arr.get(index).map(|e| e as f64),
+            Self::I64(arr) => arr.get(index).map(|e| e as f64),
+            Self::FLOAT64(arr) => arr.get(index),
+            Self::STRING(_) => None,
+        }
+    }
+
+    /// Copies the elements out of the buffer.
+    pub fn to_elements(&self) -> ElementArrayType {
+        match self {
+            Self::U32(arr) => ElementArrayType::U32(arr.iter().collect()),
+            Self::FLOAT32(arr) => ElementArrayType::FLOAT32(arr.iter().collect()),
+            Self::STRING(arr) => ElementArrayType::STRING(arr.iter().map(String::from).collect()),
+            Self::U64(arr) => ElementArrayType::U64(arr.iter().collect()),
+            Self::I64(arr) => ElementArrayType::I64(arr.iter().collect()),
+            Self::FLOAT64(arr) => ElementArrayType::FLOAT64(arr.iter().collect()),
+        }
+    }
+}
+
+/// A container, borrowed from the buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct ContainerView<'a> {
+    pub name: &'a str,
+    pub kind: MetricKind,
+    pub unit: &'a str,
+    pub label: &'a str,
+    pub time_axis: Option<&'a str>,
+    pub elements: ElementsView<'a>,
+    /// Where the container is in the buffer.
+    pub layout: ContainerLayout,
+}
+
+impl ContainerView<'_> {
+    pub fn metadata(&self) -> ContainerMetadata {
+        ContainerMetadata {
+            unit: self.unit.to_string(),
+            label: self.label.to_string(),
+            kind: self.kind,
+            time_axis: self.time_axis.map(String::from),
+        }
+    }
+
+    /// Copies the container out of the buffer.
+    pub fn to_container(&self) -> TGPHContainer {
+        TGPHContainer {
+            name: self.name.to_string(),
+            elements: self.elements.to_elements(),
+            metadata: self.metadata(),
+        }
+    }
+}
+
+/// What comes before the elements of a container.
+pub(crate) struct ContainerHead<'a> {
+    pub name: &'a str,
+    pub kind: MetricKind,
+    pub unit: &'a str,
+    pub label: &'a str,
+    pub time_axis: Option<&'a str>,
+    pub element_type: ElementType,
+    /// Stored with `ColumnEncoding::Compact`, with the length of the payload
+    /// still to be read.
+    pub encoded: bool,
+    pub count: u32,
+}
+
+impl ContainerHead<'_> {
+    pub fn metadata(&self) -> ContainerMetadata {
+        ContainerMetadata {
+            unit: self.unit.to_string(),
+            label: self.label.to_string(),
+            kind: self.kind,
+            time_axis: self.time_axis.map(String::from),
+        }
+    }
+}
+
+/// Position in the buffer being parsed. Running out of bytes is
+/// `TgphError::Truncated` at the length of the buffer.
+pub(crate) struct Input<'a> {
+    bytes: &'a [u8],
+    pub offset: usize,
+}
+
+impl<'a> Input<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], TgphError> {
+        let taken = self
+            .offset
+            .checked_add(length)
+            .and_then(|end| self.bytes.get(self.offset..end))
+            .ok_or(TgphError::Truncated {
+                offset: self.bytes.len() as u64,
+            })?;
+        self.offset += length;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, TgphError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, TgphError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, TgphError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn string(&mut self) -> Result<&'a str, TgphError> {
+        let length = match self.u8()? {
+            0xff => self.u16()? as usize,
+            length => length as usize,
+        };
+        let offset = self.offset as u64;
+        std::str::from_utf8(self.take(length)?).map_err(|_| TgphError::InvalidUtf8 { offset })
+    }
+
+    fn numbers<T: Number>(
+        &mut self,
+        encoded: bool,
+        count: u32,
+    ) -> Result<Numbers<'a, T>, TgphError> {
+        let storage = if encoded {
+            let length = self.u32()?;
+            let offset = self.offset as u64;
+            let payload = self.take(length as usize)?;
+
+            // Decoding once here keeps `NumberIter` from running into
+            // invalid payloads later.
+            let decoded = match T::FLOAT {
+                true => FloatDecoder::new(payload, count)
+                    .try_fold(0, |n, bits| T::from_encoded(bits).map(|_| n + 1)),
+                false => IntegerDecoder::new(payload, count)
+                    .try_fold(0, |n, bits| T::from_encoded(bits).map(|_| n + 1)),
+            };
+            if decoded != Some(count) {
+                return Err(TgphError::InvalidEncoding { offset });
+            }
+            Storage::Compact(payload)
+        } else {
+            let length = (count as usize)
+                .checked_mul(T::SIZE)
+                .ok_or(TgphError::Truncated {
+                    offset: self.bytes.len() as u64,
+                })?;
+            Storage::Raw(self.take(length)?)
+        };
+
+        Ok(Numbers {
+            storage,
+            count,
+            element: PhantomData,
+        })
+    }
+
+    fn strings(&mut self, count: u32) -> Result<Strings<'a>, TgphError> {
+        let start = self.offset;
+        for _ in 0..count {
+            self.string()?;
+        }
+        Ok(Strings {
+            bytes: &self.bytes[start..self.offset],
+            count,
+        })
+    }
+
+    /// Magic, version and container count. The flags of version 6 are
+    /// skipped, nothing after the containers is read.
+    pub fn header(&mut self) -> Result<(u8, u16), TgphError> {
+        let magic = self.u32()?;
+        if magic != TGPH_MAGIC {
+            return Err(TgphError::BadMagic { found: magic });
+        }
+
+        let version_offset = self.offset as u64;
+        let version = self.u8()?;
+        if !(TGPH_VERSION_1..=TGPH_LATEST_VERSION).contains(&version) {
+            return Err(TgphError::UnsupportedVersion {
+                version,
+                offset: version_offset,
+            });
+        }
+
+        let container_num = self.u16()?;
+        if version >= TGPH_VERSION_6 {
+            self.u8()?;
+        }
+        Ok((version, container_num))
+    }
+
+    pub fn container_head(&mut self, version: u8) -> Result<ContainerHead<'a>, TgphError> {
+        let name = self.string()?;
+
+        let (kind, unit, label, time_axis) = if version >= TGPH_VERSION_3 {
+            let kind_offset = self.offset as u64;
+            let kind = self.u8()?;
+            let kind = MetricKind::from_index(kind).ok_or(TgphError::UnknownMetricKind {
+                kind,
+                offset: kind_offset,
+            })?;
+            let unit = self.string()?;
+            let label = self.string()?;
+            let time_axis = Some(self.string()?).filter(|axis| !axis.is_empty());
+            (kind, unit, label, time_axis)
+        } else {
+            (MetricKind::Unspecified, "", "", None)
+        };
+
+        let type_offset = self.offset as u64;
+        let type_byte = self.u8()?;
+        let count = self.u32()?;
+
+        let encoded = version >= TGPH_VERSION_4 && type_byte & ENCODED_FLAG != 0;
+        let element_type = match encoded {
+            true => ElementType::from_index(type_byte & !ENCODED_FLAG)
+                .filter(|&t| t != ElementType::STRING),
+            false => ElementType::from_index(type_byte),
+        }
+        .ok_or(TgphError::UnknownElementType {
+            element_type: type_byte,
+            offset: type_offset,
+        })?;
+
+        Ok(ContainerHead {
+            name,
+            kind,
+            unit,
+            label,
+            time_axis,
+            element_type,
+            encoded,
+            count,
+        })
+    }
+
+    /// Checks the CRC32C of version 5 following the bytes from `start` on.
+    fn checksum(&mut self, start: usize, container: Option<&str>) -> Result<(), TgphError> {
+        let computed = crc32c::crc32c(&self.bytes[start..self.offset]);
+        let offset = self.offset as u64;
+        match self.u32()? == computed {
+            true => Ok(()),
+            false => Err(TgphError::ChecksumMismatch {
+                container: container.map(str::to_string),
+                offset,
+            }),
+        }
+    }
+
+    fn container(&mut self, version: u8) -> Result<ContainerView<'a>, TgphError> {
+        let offset = self.offset;
+        let ContainerHead {
+            name,
+            kind,
+            unit,
+            label,
+            time_axis,
+            element_type,
+            encoded,
+            count,
+        } = self.container_head(version)?;
+
+        let elements = match element_type {
+            ElementType::U32 => ElementsView::U32(self.numbers(encoded, count)?),
+            ElementType::FLOAT32 => ElementsView::FLOAT32(self.numbers(encoded, count)?),
+            ElementType::STRING => ElementsView::STRING(self.strings(count)?),
+            ElementType::U64 => ElementsView::U64(self.numbers(encoded, count)?),
+            ElementType::I64 => ElementsView::I64(self.numbers(encoded, count)?),
+            ElementType::FLOAT64 => ElementsView::FLOAT64(self.numbers(encoded, count)?),
+        };
+        if version >= TGPH_VERSION_5 {
+            self.checksum(offset, Some(name))?;
+        }
+
+        Ok(ContainerView {
+            name,
+            kind,
+            unit,
+            label,
+            time_axis,
+            elements,
+            layout: ContainerLayout {
+                offset: offset as u64,
+                length: (self.offset - offset) as u64,
+            },
+        })
+    }
+}
+
+/// An uncompressed TGPH borrowed from a buffer, see the module documentation.
+#[derive(Clone, Debug)]
+pub struct TgphView<'a> {
+    version: u8,
+    containers: Vec<ContainerView<'a>>,
+}
+
+impl<'a> TgphView<'a> {
+    /// Finds the containers in `bytes`, failing with the same errors as
+    /// `TGPH::deserialize_from` on anything it would fail on.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, TgphError> {
+        let mut input = Input::new(bytes);
+        let (version, container_num) = input.header()?;
+        let containers = (0..container_num)
+            .map(|_| input.container(version))
+            .collect::<Result<_, _>>()?;
+        if version >= TGPH_VERSION_5 {
+            input.checksum(0, None)?;
+        }
+
+        Ok(Self {
+            version,
+            containers,
+        })
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn containers(&self) -> &[ContainerView<'a>] {
+        &self.containers
+    }
+
+    pub fn container(&self, name: &str) -> Option<&ContainerView<'a>> {
+        self.containers.iter().find(|c| c.name == name)
+    }
+}
+
+#[cfg(test)]
+mod borrowed {
+    use std::io::Cursor;
+
+    use crate::tgph_format::{ColumnEncoding, TGPH};
+    use crate::view::*;
+
+    fn tgph() -> TGPH {
+        let mut tgph = TGPH::new(100);
+        for i in 0..5_u32 {
+            tgph.append(1700000000 + i * 60, "time").unwrap();
+            tgph.append(0.5 + i as f32, "CPU load").unwrap();
+            tgph.append(u64::MAX - i as u64, "Received").unwrap();
+            tgph.append(-(i as i64), "Offset").unwrap();
+            tgph.append(1.0 / (i + 1) as f64, "Ratio").unwrap();
+        }
+        tgph.append("pi".to_string(), "Hostname").unwrap();
+        tgph.append("x".repeat(300), "Hostname").unwrap();
+        tgph.set_metadata(
+            "CPU load",
+            ContainerMetadata::new(MetricKind::Gauge, "%", "CPU").with_time_axis("time"),
+        );
+        tgph
+    }
+
+    fn serialized(encoding: ColumnEncoding) -> Vec<u8> {
+        let mut tgph = tgph();
+        tgph.column_encoding = encoding;
+        let mut bytes = Vec::new();
+        tgph.serialize_into(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn matches_deserialize() {
+        for encoding in [ColumnEncoding::Raw, ColumnEncoding::Compact] {
+            let bytes = serialized(encoding);
+            let view = TgphView::parse(&bytes).unwrap();
+            let (tgph, layout) =
+                TGPH::deserialize_with_layout_from(&mut Cursor::new(&bytes)).unwrap();
+
+            assert_eq!(view.version(), tgph.version());
+            assert_eq!(view.containers().len(), tgph.containers.len());
+            for ((view, container), layout) in
+                view.containers().iter().zip(&tgph.containers).zip(layout)
+            {
+                assert_eq!(&view.to_container(), container);
+                assert_eq!(view.layout, layout);
+            }
+        }
+    }
+
+    #[test]
+    fn elements_are_borrowed() {
+        let bytes = serialized(ColumnEncoding::Compact);
+        let view = TgphView::parse(&bytes).unwrap();
+
+        let ElementsView::STRING(hostnames) = view.container("Hostname").unwrap().elements else {
+            panic!("Hostname is not a string container");
+        };
+        let hostnames: Vec<&str> = hostnames.iter().collect();
+        assert_eq!(hostnames[0], "pi");
+        assert!(bytes.as_ptr_range().contains(&hostnames[1].as_ptr()));
+
+        let cpu = view.container("CPU load").unwrap();
+        assert_eq!(cpu.time_axis, Some("time"));
+        assert_eq!(cpu.elements.get_f64(2), Some(2.5));
+        let ElementsView::U64(received) = view.container("Received").unwrap().elements else {
+            panic!("Received is not a U64 container");
+        };
+        assert_eq!(received.raw_bytes(), None);
+        assert_eq!(received.iter().last(), Some(u64::MAX - 4));
+    }
+
+    #[test]
+    fn unaligned_buffers() {
+        // Shifting by one byte misaligns every number in the buffer.
+        let mut bytes = vec![0];
+        bytes.extend(serialized(ColumnEncoding::Raw));
+        let view = TgphView::parse(&bytes[1..]).unwrap();
+
+        let ElementsView::FLOAT64(ratio) = view.container("Ratio").unwrap().elements else {
+            panic!("Ratio is not a FLOAT64 container");
+        };
+        assert_eq!(ratio.get(1), Some(0.5));
+        assert_eq!(ratio.raw_bytes().unwrap().len(), 5 * 8);
+    }
+
+    #[test]
+    fn damaged_buffers_are_errors() {
+        for encoding in [ColumnEncoding::Raw, ColumnEncoding::Compact] {
+            let bytes = serialized(encoding);
+            for length in 0..bytes.len() {
+                assert!(TgphView::parse(&bytes[..length]).is_err());
+            }
+        }
+
+        let mut bytes = serialized(ColumnEncoding::Raw);
+        bytes[0] = b'X';
+        assert!(matches!(
+            TgphView::parse(&bytes),
+            Err(TgphError::BadMagic { .. })
+        ));
+    }
+
+    #[test]
+    fn checksums_are_verified() {
+        let mut tgph = tgph();
+        tgph.checksums = true;
+        let mut bytes = Vec::new();
+        tgph.serialize_into(&mut bytes).unwrap();
+
+        let view = TgphView::parse(&bytes).unwrap();
+        let (_, layout) = TGPH::deserialize_with_layout_from(&mut Cursor::new(&bytes)).unwrap();
+        let view_layout: Vec<_> = view.containers().iter().map(|c| c.layout).collect();
+        assert_eq!(view_layout, layout);
+
+        let ratio = view.container("Ratio").unwrap().layout;
+        bytes[ratio.offset as usize + ratio.length as usize - 5] ^= 0x80;
+        assert!(matches!(
+            TgphView::parse(&bytes),
+            Err(TgphError::ChecksumMismatch { container: Some(name), .. }) if name == "Ratio"
+        ));
+    }
+}
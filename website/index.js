@@ -1,5 +1,6 @@
 const TGPH_FORMAT_MAGIC = 0x48504754;
-const TGPH_FORMAT_VERSION = 1;
+const TGPH_FORMAT_MAX_VERSION = 6;
+const TGPH_ENCODED_FLAG = 0x80;
 const SVG_HTML_NAMESPACE = "http://www.w3.org/2000/svg";
 
 const SHORT_MONTH_NAMES = [
@@ -61,6 +62,24 @@ class TGPHFormatDecoder {
     return res;
   }
 
+  readU64() {
+    const res = this.dataView.getBigUint64(this.offset, true);
+    this.offset += 8;
+    return Number(res);
+  }
+
+  readI64() {
+    const res = this.dataView.getBigInt64(this.offset, true);
+    this.offset += 8;
+    return Number(res);
+  }
+
+  readF64() {
+    const res = this.dataView.getFloat64(this.offset, true);
+    this.offset += 8;
+    return res;
+  }
+
   readU32() {
     const res = this.dataView.getUint32(this.offset, true);
     this.offset += 4;
@@ -93,11 +112,157 @@ class TGPHFormatDecoder {
   }
 }
 
-function parseContainer(decoder) {
+// Reads the compact encodings of numeric containers, most significant bit of
+// every byte first. Values are BigInts holding up to 64 bits.
+class BitReader {
+  constructor(bytes) {
+    this.bytes = bytes;
+    this.position = 0;
+  }
+
+  readBit() {
+    const byte = this.bytes[this.position >> 3];
+    if (byte === undefined) {
+      throw new Error("Compact encoding ended early");
+    }
+    const bit = (byte >> (7 - (this.position & 7))) & 1;
+    this.position += 1;
+    return bit;
+  }
+
+  readBits(count) {
+    let value = 0n;
+    for (let i = 0; i < count; i++) {
+      value = (value << 1n) | BigInt(this.readBit());
+    }
+    return value;
+  }
+}
+
+const DELTA_OF_DELTA_BUCKETS = [7, 9, 12, 32, 64];
+
+function decodeIntegers(bits, count) {
+  const values = [];
+  if (count === 0) {
+    return values;
+  }
+
+  let previous = bits.readBits(64);
+  let previousDelta = 0n;
+  values.push(previous);
+
+  for (let i = 1; i < count; i++) {
+    let ones = 0;
+    while (ones < DELTA_OF_DELTA_BUCKETS.length && bits.readBit() === 1) {
+      ones += 1;
+    }
+
+    let deltaOfDelta = 0n;
+    if (ones > 0) {
+      const zigzag = bits.readBits(DELTA_OF_DELTA_BUCKETS[ones - 1]);
+      deltaOfDelta = (zigzag >> 1n) ^ -(zigzag & 1n);
+    }
+    previousDelta = BigInt.asIntN(64, previousDelta + deltaOfDelta);
+    previous = BigInt.asUintN(64, previous + previousDelta);
+    values.push(previous);
+  }
+
+  return values;
+}
+
+function decodeFloats(bits, count) {
+  const values = [];
+  if (count === 0) {
+    return values;
+  }
+
+  let previous = bits.readBits(64);
+  let leading = undefined;
+  let length = undefined;
+  values.push(previous);
+
+  for (let i = 1; i < count; i++) {
+    if (bits.readBit() === 1) {
+      if (bits.readBit() === 1) {
+        leading = Number(bits.readBits(6));
+        length = Number(bits.readBits(6)) + 1;
+      }
+      if (length === undefined) {
+        throw new Error("Invalid compact float encoding");
+      }
+      const trailing = 64 - leading - length;
+      previous ^= bits.readBits(length) << BigInt(trailing);
+    }
+    values.push(previous);
+  }
+
+  return values;
+}
+
+function parseEncodedElements(decoder, elementType, elementCount) {
+  const length = decoder.readU32();
+  const bits = new BitReader(
+    new Uint8Array(decoder.bytes, decoder.offset, length),
+  );
+  decoder.offset += length;
+
+  const view = new DataView(new ArrayBuffer(8));
+  switch (elementType) {
+    case 1:
+    case 4:
+      return decodeIntegers(bits, elementCount).map((v) => Number(v));
+    case 5:
+      return decodeIntegers(bits, elementCount).map((v) =>
+        Number(BigInt.asIntN(64, v)),
+      );
+    case 2:
+      return decodeFloats(bits, elementCount).map((v) => {
+        view.setUint32(0, Number(v));
+        return view.getFloat32(0);
+      });
+    case 6:
+      return decodeFloats(bits, elementCount).map((v) => {
+        view.setBigUint64(0, v);
+        return view.getFloat64(0);
+      });
+    default:
+      throw new Error(`Unexpected encoded elementType = ${elementType}`);
+  }
+}
+
+function parseMetadata(decoder) {
+  const kind = decoder.readU8();
+  const unit = decoder.readString();
+  const label = decoder.readString();
+  const timeAxis = decoder.readString();
+
+  return {
+    kind,
+    unit,
+    label,
+    timeAxis,
+  };
+}
+
+function parseContainer(decoder, version) {
   const name = decoder.readString();
+  const metadata =
+    version >= 3
+      ? parseMetadata(decoder)
+      : { kind: 0, unit: "", label: "", timeAxis: "" };
   const elementType = decoder.readU8();
   const elementCount = decoder.readU32();
 
+  if (version >= 4 && elementType & TGPH_ENCODED_FLAG) {
+    const type = elementType & ~TGPH_ENCODED_FLAG;
+    return {
+      name,
+      type,
+      metadata,
+      elements: parseEncodedElements(decoder, type, elementCount),
+    };
+  }
+
   const elements = [];
   switch (elementType) {
     case 1:
@@ -115,6 +280,21 @@ function parseContainer(decoder) {
         elements.push(decoder.readString());
       }
       break;
+    case 4:
+      for (let j = 0; j < elementCount; j++) {
+        elements.push(decoder.readU64());
+      }
+      break;
+    case 5:
+      for (let j = 0; j < elementCount; j++) {
+        elements.push(decoder.readI64());
+      }
+      break;
+    case 6:
+      for (let j = 0; j < elementCount; j++) {
+        elements.push(decoder.readF64());
+      }
+      break;
     default:
       throw new Error(`Unexpected elementType = ${elementType}`);
   }
@@ -122,6 +302,7 @@ function parseContainer(decoder) {
   return {
     name,
     type: elementType,
+    metadata,
     elements,
   };
 }
@@ -135,14 +316,24 @@ function parseTGPH(bytes) {
   if (magic !== TGPH_FORMAT_MAGIC) {
     throw new Error("Invalid magic at the start of fetched file");
   }
-  if (version !== TGPH_FORMAT_VERSION) {
+  if (version < 1 || version > TGPH_FORMAT_MAX_VERSION) {
     throw new Error("Unexpected version in the fetched TGPH file");
   }
 
   let containers = [];
   const containerCount = decoder.readU16();
+  // Since version 6 a flags byte follows, which only says whether an index
+  // comes after the containers, and that is not needed here.
+  if (version >= 6) {
+    decoder.readU8();
+  }
   for (let i = 0; i < containerCount; i++) {
-    containers.push(parseContainer(decoder));
+    containers.push(parseContainer(decoder, version));
+    // Since version 5 every container is followed by its CRC32C, which is
+    // left to `tinygraph fsck`: the gzip checksum already covers the download.
+    if (version >= 5) {
+      decoder.readU32();
+    }
   }
 
   return containers;
@@ -155,8 +346,16 @@ function unpackContainers(containers) {
   };
 }
 
-function getContainersNamedLike(name) {
-  return containers.filter((c) => c.name.includes(name));
+// Downsampled copies of the containers, see "Rollup tiers" in the README.
+const ROLLUP_PREFIX = "Rollup ";
+
+function getContainersNamedLike(name, unit = "") {
+  return containers.filter(
+    (c) =>
+      c.name.includes(name) &&
+      c.name.includes(unit) &&
+      !c.name.startsWith(ROLLUP_PREFIX),
+  );
 }
 
 function getContainerNamedExactly(name) {
@@ -673,9 +872,21 @@ class LineGraph {
   }
 }
 
+// Files since version 3 name the time axis of every container, older ones rely
+// on the graph configuration.
+function timeContainerOf(dataContainers, fallback) {
+  const timeAxis = dataContainers[0]?.metadata.timeAxis;
+  return timeAxis ? getContainerNamedExactly(timeAxis) : fallback;
+}
+
 function createLineGraph(containers, timeContainer, title) {
   const { elements, names } = unpackContainers(containers);
-  const graph = new LineGraph(elements, timeContainer.elements, names, title);
+  const graph = new LineGraph(
+    elements,
+    timeContainerOf(containers, timeContainer).elements,
+    names,
+    title,
+  );
   wrapSvgAndAppendToGlobalContainer(graph.getTopElement());
   return graph;
 }
@@ -683,6 +894,67 @@ function createLineGraph(containers, timeContainer, title) {
 let containers = undefined;
 let graphs = [];
 
+// Replaces the contents of the known containers in place, as the graphs keep
+// references to their element arrays.
+function replaceElements(container, elements) {
+  container.elements.splice(0, container.elements.length, ...elements);
+}
+
+// Applies a sample pushed by tinygraph's built-in web server, see "Built-in
+// web server" in the README. Containers that did not exist on load are only
+// picked up by reloading the page.
+function applySample(sample) {
+  for (const update of sample.containers) {
+    const container = containers.find((c) => c.name === update.name);
+    if (container === undefined) {
+      continue;
+    }
+
+    // Not a number is sent as null.
+    const values = update.values.map((v) => (v === null ? NaN : v));
+    if (sample.replaced.includes(update.name)) {
+      replaceElements(container, values);
+    } else {
+      container.elements.push(...values);
+      const excess = container.elements.length - sample.entry_limit;
+      container.elements.splice(0, Math.max(0, excess));
+    }
+  }
+
+  graphs.forEach((g) => g.draw());
+}
+
+async function reloadContainers() {
+  const fresh = parseTGPH(await getContainerData());
+  for (const container of containers) {
+    const match = fresh.find((c) => c.name === container.name);
+    replaceElements(container, match === undefined ? [] : match.elements);
+  }
+
+  graphs.forEach((g) => g.draw());
+}
+
+// Without the built-in web server the stream fails with a 404 and is closed,
+// so a statically served dashboard keeps showing the data it loaded.
+function subscribeToSamples() {
+  if (typeof EventSource === "undefined") {
+    return;
+  }
+
+  const events = new EventSource("api/events");
+  let missedSamples = false;
+  events.addEventListener("sample", (e) => applySample(JSON.parse(e.data)));
+  events.addEventListener("error", (_) => {
+    missedSamples = true;
+  });
+  events.addEventListener("open", async (_) => {
+    if (missedSamples) {
+      missedSamples = false;
+      await reloadContainers();
+    }
+  });
+}
+
 window.onload = async () => {
   const tgphBytes = await getContainerData();
   containers = parseTGPH(tgphBytes);
@@ -699,6 +971,7 @@ window.onload = async () => {
     {
       title: "Network usage",
       dataContainerNamePart: "Interface enp1s0",
+      unit: "[B/s]",
       timeContainer: timeContainer,
     },
     {
@@ -735,13 +1008,14 @@ window.onload = async () => {
 
   graphs = graphConfigurations.map((config) =>
     createLineGraph(
-      getContainersNamedLike(config.dataContainerNamePart),
+      getContainersNamedLike(config.dataContainerNamePart, config.unit),
       config.timeContainer,
       config.title,
     ),
   );
 
   graphs.forEach((g) => g.draw());
+  subscribeToSamples();
 };
 
 window.addEventListener("resize", (_) => {